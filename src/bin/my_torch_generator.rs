@@ -1,15 +1,28 @@
 use my_torch_analyzer::chess::config::TrainingConfig;
+use my_torch_analyzer::chess::trainer::{describe_network, network_from_architecture};
+use my_torch_analyzer::network::datastruct::binary::ModelFormat;
 use my_torch_analyzer::network::datastruct::network::Network;
+use my_torch_analyzer::network::random;
 use std::env;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
 
     if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
         print_help();
         std::process::exit(0);
     }
 
+    let seed = match extract_seed(&mut args) {
+        Ok(seed) => seed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!();
+            print_help();
+            std::process::exit(84);
+        }
+    };
+
     if args.len() < 3 {
         eprintln!("Error: Not enough arguments");
         eprintln!();
@@ -17,7 +30,7 @@ fn main() {
         std::process::exit(84);
     }
 
-    if (args.len() - 1) % 2 != 0 {
+    if !(args.len() - 1).is_multiple_of(2) {
         eprintln!("Error: Arguments must come in pairs (config_file nb)");
         eprintln!();
         print_help();
@@ -49,7 +62,7 @@ fn main() {
 
         println!("Processing: {} ({} networks)", config_file, nb);
 
-        match generate_networks(config_file, nb) {
+        match generate_networks(config_file, nb, seed) {
             Ok(count) => {
                 println!("  ✓ Generated {} networks", count);
                 total_generated += count;
@@ -66,11 +79,29 @@ fn main() {
     println!("\n✅ Total networks generated: {}", total_generated);
 }
 
-/// Generate N networks from a configuration file
-fn generate_networks(config_file: &str, nb: usize) -> Result<usize, String> {
+/// Remove `--seed N` from the arguments
+fn extract_seed(args: &mut Vec<String>) -> Result<Option<u64>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--seed") else {
+        return Ok(None);
+    };
+    let value = args.get(index + 1).ok_or("--seed requires a number")?;
+    let seed = value
+        .parse()
+        .map_err(|_| format!("Invalid seed: {}", value))?;
+    args.drain(index..index + 2);
+    Ok(Some(seed))
+}
+
+/// Generate N networks from a configuration file. With a seed, from `--seed`
+/// or the configuration, the same networks come out on every run.
+fn generate_networks(config_file: &str, nb: usize, seed: Option<u64>) -> Result<usize, String> {
     // Load configuration
-    let config = TrainingConfig::load(config_file)
+    let mut config = TrainingConfig::load(config_file)
         .map_err(|e| format!("Failed to load config {}: {}", config_file, e))?;
+    if let Some(seed) = seed.or(config.seed) {
+        random::seed(seed);
+        config.seed = Some(seed);
+    }
 
     let base_name = extract_base_name(config_file);
 
@@ -83,11 +114,8 @@ fn generate_networks(config_file: &str, nb: usize) -> Result<usize, String> {
 
     // Get dropout rates from configuration
     let dropout_rates = config.get_dropout_rates();
-    // Use 'linear' activation for output layer (softmax is applied at network level)
-    let output_activation = "linear";
-
     // Validate that dropout_rates length matches number of layers
-    if dropout_rates.len() != layers.len() {
+    if config.architecture.is_empty() && dropout_rates.len() != layers.len() {
         return Err(format!(
             "Dropout rates count ({}) must match number of layers ({})",
             dropout_rates.len(),
@@ -96,12 +124,18 @@ fn generate_networks(config_file: &str, nb: usize) -> Result<usize, String> {
     }
 
     for i in 1..=nb {
-        let network = Network::new_random_he(
-            input_size,
-            layers.clone(),
-            dropout_rates.clone(),
-            output_activation,
-        );
+        let mut network = if config.architecture.is_empty() {
+            Network::new_random_he(
+                input_size,
+                layers.clone(),
+                dropout_rates.clone(),
+                config.hidden_activation,
+                config.output_activation,
+            )
+        } else {
+            network_from_architecture::<f64>(&config)?
+        };
+        describe_network(&mut network.metadata, &config);
 
         let filename = if nb == 1 {
             format!("{}.nn", base_name)
@@ -110,7 +144,7 @@ fn generate_networks(config_file: &str, nb: usize) -> Result<usize, String> {
         };
 
         network
-            .save(&filename)
+            .save_as(&filename, ModelFormat::Text)
             .map_err(|e| format!("Failed to save {}: {}", filename, e))?;
 
         if nb <= 10 {
@@ -130,7 +164,7 @@ fn extract_base_name(path: &str) -> String {
 
 fn print_help() {
     println!("USAGE");
-    println!("    ./my_torch_generator config_file_1 nb_1 [config_file_2 nb_2...]");
+    println!("    ./my_torch_generator [--seed N] config_file_1 nb_1 [config_file_2 nb_2...]");
     println!();
     println!("DESCRIPTION");
     println!("    config_file_i  Configuration file containing description of a neural network");
@@ -139,6 +173,9 @@ fn print_help() {
     println!("    nb_i           Number of neural networks to generate based on the");
     println!("                   configuration file.");
     println!();
+    println!("    --seed N       Seed of the weight initialization, instead of the seed key of");
+    println!("                   each configuration file. The same seed gives the same networks.");
+    println!();
     println!("EXAMPLES");
     println!("    # Generate 1 network from default config");
     println!("    ./my_torch_generator configs/default.conf 1");
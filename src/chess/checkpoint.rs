@@ -0,0 +1,264 @@
+//! Training checkpoints, to resume a run where it stopped.
+//!
+//! ```text
+//! magic       "MTCK"
+//! version     u16
+//! config      u32 length + UTF-8    TrainingConfig::to_string() of the run
+//! seed        u64
+//! epoch       u32                   epochs already trained
+//! patience    u32
+//! best_loss   f64
+//! network     u32 length + binary network (f64)
+//! has_best    u8
+//! if has_best:
+//!   epoch       u32
+//!   network     u32 length + binary network (f64)
+//! nb_buffers  u32                   optimizer state
+//! per buffer:
+//!   len         u32
+//!   values      len f64
+//! len         u32                   schedule state
+//! values      len f64
+//! ```
+//!
+//! Every integer and float is little-endian. Networks trained in `f32` are
+//! stored widened to `f64`, which keeps every value.
+
+use crate::chess::config::TrainingConfig;
+use crate::network::datastruct::binary::{self, Precision, Reader};
+use crate::network::datastruct::network::Network;
+use crate::network::float::Float;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs;
+
+pub const MAGIC: &[u8; 4] = b"MTCK";
+pub const VERSION: u16 = 1;
+
+/// Where a training run stands, besides its current weights
+pub struct Progress<T: Float = f64> {
+    /// Epochs already trained, i.e. the next epoch to run
+    pub epoch: usize,
+    pub best_val_loss: f64,
+    /// Epochs since the last improvement of the validation loss
+    pub patience: usize,
+    /// Epoch and weights of the best validation loss, kept with
+    /// `restore_best_weights`
+    pub best: Option<(usize, Network<T>)>,
+    /// Seed of the run, see `epoch_rng`
+    pub seed: u64,
+    /// `Optimizer::state`
+    pub optimizer: Vec<Vec<f64>>,
+    /// `LrSchedule::state`
+    pub schedule: Vec<f64>,
+}
+
+impl<T: Float> Progress<T> {
+    pub fn new(seed: u64) -> Self {
+        Progress {
+            epoch: 0,
+            best_val_loss: f64::INFINITY,
+            patience: 0,
+            best: None,
+            seed,
+            optimizer: vec![],
+            schedule: vec![],
+        }
+    }
+
+    /// Generator of the batches and dropout masks of `epoch`. It only
+    /// depends on the seed and the epoch, so a resumed run draws the same
+    /// ones; `seed` itself initializes the weights.
+    pub fn epoch_rng(&self, epoch: usize) -> StdRng {
+        StdRng::seed_from_u64(self.seed.wrapping_add(epoch as u64 + 1))
+    }
+
+    /// The same progress, with the best weights in precision `U`
+    pub fn convert<U: Float>(self) -> Progress<U> {
+        Progress {
+            epoch: self.epoch,
+            best_val_loss: self.best_val_loss,
+            patience: self.patience,
+            best: self.best.map(|(epoch, network)| (epoch, network.convert())),
+            seed: self.seed,
+            optimizer: self.optimizer,
+            schedule: self.schedule,
+        }
+    }
+}
+
+pub fn save<T: Float>(
+    path: &str,
+    config: &TrainingConfig,
+    network: &Network<T>,
+    progress: &Progress<T>,
+) -> Result<(), String> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    binary::write_str(&mut out, &config.to_string());
+    binary::write_u64(&mut out, progress.seed);
+    binary::write_u32(&mut out, progress.epoch);
+    binary::write_u32(&mut out, progress.patience);
+    binary::write_floats(&mut out, &[progress.best_val_loss], Precision::F64);
+    write_network(&mut out, network);
+    match &progress.best {
+        Some((epoch, best)) => {
+            out.push(1);
+            binary::write_u32(&mut out, *epoch);
+            write_network(&mut out, best);
+        }
+        None => out.push(0),
+    }
+    binary::write_u32(&mut out, progress.optimizer.len());
+    for buffer in &progress.optimizer {
+        binary::write_u32(&mut out, buffer.len());
+        binary::write_floats(&mut out, buffer, Precision::F64);
+    }
+    binary::write_u32(&mut out, progress.schedule.len());
+    binary::write_floats(&mut out, &progress.schedule, Precision::F64);
+
+    // Written aside then renamed, so a run killed while saving keeps the
+    // previous checkpoint
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, out)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|e| format!("Failed to save checkpoint to {}: {}", path, e))
+}
+
+/// The networks are read in `f64`, see `Network::convert` and
+/// `Progress::convert` to resume in `config.precision`
+pub fn load(path: &str) -> Result<(TrainingConfig, Network, Progress), String> {
+    let content =
+        fs::read(path).map_err(|e| format!("Failed to load checkpoint from {}: {}", path, e))?;
+    decode(&content).map_err(|e| format!("Failed to load checkpoint from {}: {}", path, e))
+}
+
+fn decode(content: &[u8]) -> Result<(TrainingConfig, Network, Progress), String> {
+    let mut reader = Reader::new(content);
+    if reader.bytes(4)? != MAGIC {
+        return Err(String::from("not a checkpoint file (bad magic)"));
+    }
+    let version = u16::from_le_bytes(reader.bytes(2)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(format!(
+            "unsupported checkpoint version {} (expected {})",
+            version, VERSION
+        ));
+    }
+
+    let config = TrainingConfig::parse(&reader.string()?)
+        .map_err(|e| format!("invalid configuration: {}", e))?;
+    let seed = reader.u64()?;
+    let epoch = reader.u32()?;
+    let patience = reader.u32()?;
+    let best_val_loss = reader.floats(1, Precision::F64)?[0];
+    let network = read_network(&mut reader)?;
+    let best = match reader.u8()? {
+        0 => None,
+        _ => {
+            let epoch = reader.u32()?;
+            Some((epoch, read_network(&mut reader)?))
+        }
+    };
+    let mut optimizer = vec![];
+    for _ in 0..reader.u32()? {
+        let len = reader.u32()?;
+        optimizer.push(reader.floats(len, Precision::F64)?);
+    }
+    let len = reader.u32()?;
+    let schedule = reader.floats(len, Precision::F64)?;
+    if !reader.is_at_end() {
+        return Err(String::from("unexpected trailing bytes"));
+    }
+
+    let progress = Progress {
+        epoch,
+        best_val_loss,
+        patience,
+        best,
+        seed,
+        optimizer,
+        schedule,
+    };
+    Ok((config, network, progress))
+}
+
+fn write_network<T: Float>(out: &mut Vec<u8>, network: &Network<T>) {
+    let bytes = binary::encode(network, Precision::F64);
+    binary::write_u32(out, bytes.len());
+    out.extend_from_slice(&bytes);
+}
+
+fn read_network(reader: &mut Reader) -> Result<Network, String> {
+    let len = reader.u32()?;
+    binary::decode(reader.bytes(len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::activation::Activation;
+    use crate::network::datastruct::layer::Layer;
+    use crate::network::datastruct::metadata::Metadata;
+
+    #[test]
+    fn test_round_trip() {
+        let network: Network = Network {
+            layers: vec![Box::new(Layer::new_random_he(3, 4, Activation::Relu))],
+            metadata: Metadata::default(),
+        };
+        let mut config = TrainingConfig::default();
+        config.lr_schedule = String::from("plateau(0.5, 3)");
+        let progress = Progress {
+            epoch: 12,
+            best_val_loss: 0.25,
+            patience: 2,
+            best: Some((10, network.clone())),
+            seed: u64::MAX - 7,
+            optimizer: vec![vec![3.0], vec![0.1, -0.2], vec![]],
+            schedule: vec![0.5, 0.25, 2.0],
+        };
+        let path = std::env::temp_dir().join(format!("checkpoint_{}.ckpt", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, &config, &network, &progress).unwrap();
+        let (loaded_config, loaded, loaded_progress) = load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded_config.to_string(), config.to_string());
+        assert_eq!(loaded.to_string(), network.to_string());
+        assert_eq!(loaded_progress.epoch, 12);
+        assert_eq!(loaded_progress.best_val_loss, 0.25);
+        assert_eq!(loaded_progress.patience, 2);
+        assert_eq!(loaded_progress.seed, u64::MAX - 7);
+        assert_eq!(loaded_progress.optimizer, progress.optimizer);
+        assert_eq!(loaded_progress.schedule, progress.schedule);
+        let (best_epoch, best) = loaded_progress.best.unwrap();
+        assert_eq!(best_epoch, 10);
+        assert_eq!(best.to_string(), network.to_string());
+
+        assert!(decode(b"MTNN\x01\x00").is_err());
+    }
+
+    #[test]
+    fn test_f32_network_round_trip() {
+        let network: Network<f32> = Network {
+            layers: vec![Box::new(Layer::new_random_he(3, 4, Activation::Relu))],
+            metadata: Metadata::default(),
+        };
+        let progress = Progress {
+            best: Some((0, network.clone())),
+            ..Progress::new(5)
+        };
+        let path = std::env::temp_dir().join(format!("checkpoint_f32_{}.ckpt", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, &TrainingConfig::default(), &network, &progress).unwrap();
+        let (_, loaded, loaded_progress) = load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let loaded: Network<f32> = loaded.convert();
+        assert_eq!(loaded.to_string(), network.to_string());
+        let (_, best) = loaded_progress.convert::<f32>().best.unwrap();
+        assert_eq!(best.to_string(), network.to_string());
+    }
+}
@@ -1,5 +1,43 @@
+use crate::chess::predictor::CLASS_LABELS;
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::architecture::LayerSpec;
+use crate::network::datastruct::binary::Precision;
+use crate::network::datastruct::network::GradientClipping;
+use crate::network::float::Float;
+use crate::network::loss::{self, Loss};
+use crate::network::optimizer::{Adam, Momentum, Optimizer, RmsProp, Sgd};
+use crate::network::regularization::Regularization;
+use crate::network::schedule::{self, LrSchedule};
+use std::fmt;
 use std::fs;
 
+/// Per-class loss weights, against class imbalance
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassWeights {
+    None,
+    /// Inverse class frequencies of the training set
+    Auto,
+    Manual(Vec<f64>),
+}
+
+impl fmt::Display for ClassWeights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClassWeights::None => write!(f, "none"),
+            ClassWeights::Auto => write!(f, "auto"),
+            ClassWeights::Manual(weights) => write!(
+                f,
+                "[{}]",
+                weights
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TrainingConfig {
     // Hyperparamètres d'apprentissage
@@ -7,12 +45,25 @@ pub struct TrainingConfig {
     pub epochs: usize,
     pub batch_size: usize,
     pub patience: usize,
+    /// Put back the weights of the best validation loss after training
+    pub restore_best_weights: bool,
+    /// Write a checkpoint every N epochs, 0 to disable
+    pub checkpoint_every: usize,
+    /// Seed of the weight initialization, dropout masks and batch order,
+    /// `None` to draw one at random
+    pub seed: Option<u64>,
+    /// Float type the network is trained in
+    pub precision: Precision,
 
     // Ratio de split train/validation
     pub train_ratio: f64,
 
     // Architecture du réseau
     pub hidden_layers: Vec<u32>,
+    pub hidden_activation: Activation,
+    pub output_activation: Activation,
+    /// Hidden layer stack, replaces `hidden_layers` when not empty
+    pub architecture: Vec<LayerSpec>,
 
     // Initialisation des poids
     pub weight_min: f64,
@@ -24,10 +75,41 @@ pub struct TrainingConfig {
     pub dropout_rate: f64,
     pub dropout_rates: Vec<f64>,
 
-    // Learning rate decay
+    // Weight penalties and constraint
+    pub l1: f64,
+    pub l2: f64,
+    /// 0 disables the max-norm constraint
+    pub max_norm: f64,
+    pub regularize_biases: bool,
+
+    // Gradient clipping, 0 = off
+    pub clip_grad_norm: f64,
+    pub clip_grad_value: f64,
+
+    /// Learning rate schedule, see `schedule::from_name`
+    pub lr_schedule: String,
+
+    // Learning rate decay (legacy step schedule)
     pub lr_decay_enabled: bool,
     pub lr_decay_rate: f64,
     pub lr_decay_step: usize,
+
+    /// Training loss, see `loss::from_name`
+    pub loss: String,
+
+    // Class imbalance
+    pub class_weights: ClassWeights,
+    /// Examples of each class guaranteed in every batch, 0 to just shuffle
+    pub min_per_class: usize,
+
+    // Optimizer
+    pub optimizer: String,
+    pub momentum: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    pub rms_decay: f64,
+    pub weight_decay: f64,
 }
 
 impl TrainingConfig {
@@ -81,6 +163,28 @@ impl TrainingConfig {
                         .parse()
                         .map_err(|_| format!("Invalid patience: {}", value))?;
                 }
+                "restore_best_weights" => {
+                    config.restore_best_weights = Self::parse_bool(value)?;
+                }
+                "checkpoint_every" => {
+                    config.checkpoint_every = value
+                        .parse()
+                        .map_err(|_| format!("Invalid checkpoint_every: {}", value))?;
+                }
+                "seed" => {
+                    config.seed = match value {
+                        "random" => None,
+                        _ => Some(
+                            value
+                                .parse()
+                                .map_err(|_| format!("Invalid seed: {}", value))?,
+                        ),
+                    };
+                }
+                "precision" => {
+                    config.precision = Precision::from_name(value)
+                        .map_err(|e| format!("Invalid precision: {}", e))?;
+                }
                 "train_ratio" => {
                     config.train_ratio = value
                         .parse()
@@ -89,6 +193,18 @@ impl TrainingConfig {
                 "hidden_layers" => {
                     config.hidden_layers = Self::parse_vec_u32(value)?;
                 }
+                "architecture" => {
+                    config.architecture = LayerSpec::parse_list(value)
+                        .map_err(|e| format!("Invalid architecture: {}", e))?;
+                }
+                "hidden_activation" => {
+                    config.hidden_activation = Activation::from_name(value)
+                        .map_err(|e| format!("Invalid hidden_activation: {}", e))?;
+                }
+                "output_activation" => {
+                    config.output_activation = Activation::from_name(value)
+                        .map_err(|e| format!("Invalid output_activation: {}", e))?;
+                }
                 "weight_min" => {
                     config.weight_min = value
                         .parse()
@@ -109,6 +225,9 @@ impl TrainingConfig {
                         .parse()
                         .map_err(|_| format!("Invalid bias_max: {}", value))?;
                 }
+                "lr_schedule" => {
+                    config.lr_schedule = value.to_lowercase();
+                }
                 "lr_decay_enabled" => {
                     config.lr_decay_enabled = Self::parse_bool(value)?;
                 }
@@ -130,6 +249,85 @@ impl TrainingConfig {
                 "dropout_rates" => {
                     config.dropout_rates = Self::parse_vec_f64(value)?;
                 }
+                "l1" => {
+                    config.l1 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid l1: {}", value))?;
+                }
+                "l2" => {
+                    config.l2 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid l2: {}", value))?;
+                }
+                "max_norm" => {
+                    config.max_norm = value
+                        .parse()
+                        .map_err(|_| format!("Invalid max_norm: {}", value))?;
+                }
+                "regularize_biases" => {
+                    config.regularize_biases = Self::parse_bool(value)?;
+                }
+                "clip_grad_norm" => {
+                    config.clip_grad_norm = value
+                        .parse()
+                        .map_err(|_| format!("Invalid clip_grad_norm: {}", value))?;
+                }
+                "clip_grad_value" => {
+                    config.clip_grad_value = value
+                        .parse()
+                        .map_err(|_| format!("Invalid clip_grad_value: {}", value))?;
+                }
+                "loss" => {
+                    config.loss = value.to_lowercase();
+                }
+                "class_weights" => {
+                    config.class_weights = match value.to_lowercase().as_str() {
+                        "none" => ClassWeights::None,
+                        "auto" => ClassWeights::Auto,
+                        _ => ClassWeights::Manual(
+                            Self::parse_vec_f64(value)
+                                .map_err(|_| format!("Invalid class_weights: {}", value))?,
+                        ),
+                    };
+                }
+                "min_per_class" => {
+                    config.min_per_class = value
+                        .parse()
+                        .map_err(|_| format!("Invalid min_per_class: {}", value))?;
+                }
+                "optimizer" => {
+                    config.optimizer = value.to_lowercase();
+                }
+                "momentum" => {
+                    config.momentum = value
+                        .parse()
+                        .map_err(|_| format!("Invalid momentum: {}", value))?;
+                }
+                "beta1" => {
+                    config.beta1 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid beta1: {}", value))?;
+                }
+                "beta2" => {
+                    config.beta2 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid beta2: {}", value))?;
+                }
+                "epsilon" => {
+                    config.epsilon = value
+                        .parse()
+                        .map_err(|_| format!("Invalid epsilon: {}", value))?;
+                }
+                "rms_decay" => {
+                    config.rms_decay = value
+                        .parse()
+                        .map_err(|_| format!("Invalid rms_decay: {}", value))?;
+                }
+                "weight_decay" => {
+                    config.weight_decay = value
+                        .parse()
+                        .map_err(|_| format!("Invalid weight_decay: {}", value))?;
+                }
                 _ => {
                     return Err(format!("Unknown configuration key: {}", key));
                 }
@@ -141,23 +339,48 @@ impl TrainingConfig {
         Ok(config)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Self {
         TrainingConfig {
             learning_rate: 0.01,
             epochs: 1000,
             batch_size: 1,
             patience: 50,
+            restore_best_weights: true,
+            checkpoint_every: 0,
+            seed: None,
+            precision: Precision::F64,
             train_ratio: 0.8,
             hidden_layers: vec![256, 128, 64],
+            hidden_activation: Activation::Relu,
+            output_activation: Activation::Linear,
+            architecture: vec![],
             weight_min: -0.3,
             weight_max: 0.3,
             bias_min: -0.1,
             bias_max: 0.1,
             dropout_rate: 0.0,
             dropout_rates: vec![],
+            l1: 0.0,
+            l2: 0.0,
+            max_norm: 0.0,
+            regularize_biases: false,
+            clip_grad_norm: 0.0,
+            clip_grad_value: 0.0,
+            lr_schedule: String::from("constant"),
             lr_decay_enabled: false,
             lr_decay_rate: 0.95,
             lr_decay_step: 100,
+            loss: String::from("cross_entropy"),
+            class_weights: ClassWeights::None,
+            min_per_class: 0,
+            optimizer: String::from("sgd"),
+            momentum: 0.9,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            rms_decay: 0.9,
+            weight_decay: 0.01,
         }
     }
 
@@ -167,6 +390,7 @@ impl TrainingConfig {
         fs::write(path, content).map_err(|e| format!("Cannot write config file {}: {}", path, e))
     }
 
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         format!(
             "# Training Configuration\n\
@@ -175,12 +399,19 @@ impl TrainingConfig {
             epochs = {}\n\
             batch_size = {}\n\
             patience = {}\n\
+            restore_best_weights = {}\n\
+            checkpoint_every = {}\n\
+            seed = {}\n\
+            precision = {}\n\
             \n\
             # Data split\n\
             train_ratio = {}\n\
             \n\
             # Network architecture\n\
             hidden_layers = {}\n\
+            hidden_activation = {}\n\
+            output_activation = {}\n\
+            architecture = {}\n\
             \n\
             # Weight initialization\n\
             weight_min = {}\n\
@@ -192,25 +423,79 @@ impl TrainingConfig {
             dropout_rate = {}\n\
             dropout_rates = {}\n\
             \n\
+            # Weight penalties\n\
+            l1 = {}\n\
+            l2 = {}\n\
+            max_norm = {}\n\
+            regularize_biases = {}\n\
+            \n\
+            # Gradient clipping\n\
+            clip_grad_norm = {}\n\
+            clip_grad_value = {}\n\
+            \n\
+            # Learning rate schedule\n\
+            lr_schedule = {}\n\
+            \n\
             # Learning rate decay\n\
             lr_decay_enabled = {}\n\
             lr_decay_rate = {}\n\
-            lr_decay_step = {}\n",
+            lr_decay_step = {}\n\
+            \n\
+            # Loss\n\
+            loss = {}\n\
+            \n\
+            # Class imbalance\n\
+            class_weights = {}\n\
+            min_per_class = {}\n\
+            \n\
+            # Optimizer\n\
+            optimizer = {}\n\
+            momentum = {}\n\
+            beta1 = {}\n\
+            beta2 = {}\n\
+            epsilon = {}\n\
+            rms_decay = {}\n\
+            weight_decay = {}\n",
             self.learning_rate,
             self.epochs,
             self.batch_size,
             self.patience,
+            self.restore_best_weights,
+            self.checkpoint_every,
+            self.seed
+                .map_or(String::from("random"), |seed| seed.to_string()),
+            self.precision,
             self.train_ratio,
             self.format_vec_u32(&self.hidden_layers),
+            self.hidden_activation,
+            self.output_activation,
+            self.format_architecture(),
             self.weight_min,
             self.weight_max,
             self.bias_min,
             self.bias_max,
             self.dropout_rate,
             self.format_vec_f64(&self.dropout_rates),
+            self.l1,
+            self.l2,
+            self.max_norm,
+            self.regularize_biases,
+            self.clip_grad_norm,
+            self.clip_grad_value,
+            self.lr_schedule,
             self.lr_decay_enabled,
             self.lr_decay_rate,
             self.lr_decay_step,
+            self.loss,
+            self.class_weights,
+            self.min_per_class,
+            self.optimizer,
+            self.momentum,
+            self.beta1,
+            self.beta2,
+            self.epsilon,
+            self.rms_decay,
+            self.weight_decay,
         )
     }
 
@@ -271,8 +556,89 @@ impl TrainingConfig {
             ));
         }
 
+        self.create_optimizer::<f64>()?;
+        self.create_schedule()?;
+        let loss = self.create_loss::<f64>()?;
+        if let Some(weights) = loss.class_weights() {
+            if weights.len() != CLASS_LABELS.len() {
+                return Err(format!(
+                    "Invalid loss: {} class weights given for {} classes",
+                    weights.len(),
+                    CLASS_LABELS.len()
+                ));
+            }
+            if self.class_weights != ClassWeights::None {
+                return Err(String::from(
+                    "class_weights cannot be combined with a weighted loss",
+                ));
+            }
+        }
+
+        if let ClassWeights::Manual(weights) = &self.class_weights {
+            if weights.len() != CLASS_LABELS.len() {
+                return Err(format!(
+                    "Invalid class_weights: {} weights given for {} classes",
+                    weights.len(),
+                    CLASS_LABELS.len()
+                ));
+            }
+            if weights.iter().any(|w| !w.is_finite() || *w < 0.0)
+                || weights.iter().all(|&w| w == 0.0)
+            {
+                return Err(String::from(
+                    "Invalid class_weights: weights must be >= 0 and not all zero",
+                ));
+            }
+        }
+
+        if self.min_per_class * CLASS_LABELS.len() > self.batch_size {
+            return Err(format!(
+                "Invalid min_per_class: {} examples of {} classes do not fit in a batch of {}",
+                self.min_per_class,
+                CLASS_LABELS.len(),
+                self.batch_size
+            ));
+        }
+
+        for (name, value) in [
+            ("momentum", self.momentum),
+            ("beta1", self.beta1),
+            ("beta2", self.beta2),
+            ("rms_decay", self.rms_decay),
+        ] {
+            if !(0.0..1.0).contains(&value) {
+                return Err(format!(
+                    "Invalid {}: {} (must be 0 <= value < 1)",
+                    name, value
+                ));
+            }
+        }
+
+        if self.epsilon <= 0.0 {
+            return Err(format!("Invalid epsilon: {} (must be > 0)", self.epsilon));
+        }
+
+        for (name, value) in [
+            ("l1", self.l1),
+            ("l2", self.l2),
+            ("max_norm", self.max_norm),
+            ("clip_grad_norm", self.clip_grad_norm),
+            ("clip_grad_value", self.clip_grad_value),
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(format!("Invalid {}: {} (must be >= 0)", name, value));
+            }
+        }
+
+        if self.weight_decay < 0.0 {
+            return Err(format!(
+                "Invalid weight_decay: {} (must be >= 0)",
+                self.weight_decay
+            ));
+        }
+
         for (idx, &rate) in self.dropout_rates.iter().enumerate() {
-            if rate < 0.0 || rate >= 1.0 {
+            if !(0.0..1.0).contains(&rate) {
                 return Err(format!(
                     "Invalid dropout_rates[{}]: {} (must be 0 <= rate < 1)",
                     idx, rate
@@ -290,7 +656,7 @@ impl TrainingConfig {
         let s = s.trim_start_matches('[').trim_end_matches(']');
 
         let numbers: Result<Vec<u32>, _> = s
-            .split(|c| c == ',' || c == ' ')
+            .split([',', ' '])
             .filter(|x| !x.is_empty())
             .map(|x| x.trim().parse())
             .collect();
@@ -310,7 +676,7 @@ impl TrainingConfig {
         let s = s.trim_start_matches('[').trim_end_matches(']');
 
         let numbers: Result<Vec<f64>, _> = s
-            .split(|c| c == ',' || c == ' ')
+            .split([',', ' '])
             .filter(|x| !x.is_empty())
             .map(|x| x.trim().parse())
             .collect();
@@ -319,7 +685,7 @@ impl TrainingConfig {
     }
 
     #[warn(unused)]
-    fn format_vec_u32(&self, vec: &Vec<u32>) -> String {
+    fn format_vec_u32(&self, vec: &[u32]) -> String {
         format!(
             "[{}]",
             vec.iter()
@@ -329,7 +695,7 @@ impl TrainingConfig {
         )
     }
 
-    fn format_vec_f64(&self, vec: &Vec<f64>) -> String {
+    fn format_vec_f64(&self, vec: &[f64]) -> String {
         if vec.is_empty() {
             return String::from("[]");
         }
@@ -350,15 +716,73 @@ impl TrainingConfig {
         }
     }
 
-    pub fn get_learning_rate(&self, epoch: usize) -> f64 {
-        if self.lr_decay_enabled {
-            let decay_count = epoch / self.lr_decay_step;
-            self.learning_rate * self.lr_decay_rate.powi(decay_count as i32)
+    /// Build the schedule selected by `lr_schedule`, or the step decay of
+    /// the `lr_decay_*` keys when they are enabled
+    pub fn create_schedule(&self) -> Result<Box<dyn LrSchedule>, String> {
+        let spec = if self.lr_decay_enabled {
+            if self.lr_schedule != "constant" {
+                return Err(String::from(
+                    "lr_decay_enabled cannot be combined with lr_schedule, use lr_schedule = step(RATE, STEP)",
+                ));
+            }
+            format!("step({}, {})", self.lr_decay_rate, self.lr_decay_step)
         } else {
-            self.learning_rate
+            self.lr_schedule.clone()
+        };
+        schedule::from_name(&spec, self.epochs).map_err(|e| format!("Invalid lr_schedule: {}", e))
+    }
+
+    /// Build the optimizer selected by the `optimizer` key
+    pub fn create_optimizer<T: Float>(&self) -> Result<Box<dyn Optimizer<T>>, String> {
+        match self.optimizer.as_str() {
+            "sgd" => Ok(Box::new(Sgd)),
+            "momentum" => Ok(Box::new(Momentum::new(self.momentum, false))),
+            "nesterov" => Ok(Box::new(Momentum::new(self.momentum, true))),
+            "rmsprop" => Ok(Box::new(RmsProp::new(self.rms_decay, self.epsilon))),
+            "adam" => Ok(Box::new(Adam::new(self.beta1, self.beta2, self.epsilon))),
+            "adamw" => Ok(Box::new(Adam::adamw(
+                self.beta1,
+                self.beta2,
+                self.epsilon,
+                self.weight_decay,
+            ))),
+            _ => Err(format!(
+                "Unknown optimizer: {} (expected sgd, momentum, nesterov, rmsprop, adam or adamw)",
+                self.optimizer
+            )),
         }
     }
 
+    /// Build the loss selected by the `loss` key
+    pub fn create_loss<T: Float>(&self) -> Result<Box<dyn Loss<T>>, String> {
+        loss::from_name(&self.loss).map_err(|e| format!("Invalid loss: {}", e))
+    }
+
+    pub fn regularization(&self) -> Regularization {
+        Regularization {
+            l1: self.l1,
+            l2: self.l2,
+            max_norm: self.max_norm,
+            include_biases: self.regularize_biases,
+        }
+    }
+
+    pub fn gradient_clipping(&self) -> GradientClipping {
+        GradientClipping {
+            norm: self.clip_grad_norm,
+            value: self.clip_grad_value,
+        }
+    }
+
+    /// The `architecture` key as written in configuration files
+    pub fn format_architecture(&self) -> String {
+        self.architecture
+            .iter()
+            .map(|spec| spec.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     /// Get dropout rates for all layers (including output layer)
     #[allow(dead_code)]
     pub fn get_dropout_rates(&self) -> Vec<f64> {
@@ -421,6 +845,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_parse_bool() {
         assert_eq!(TrainingConfig::parse_bool("true").unwrap(), true);
         assert_eq!(TrainingConfig::parse_bool("false").unwrap(), false);
@@ -438,10 +863,12 @@ mod tests {
         config.lr_decay_rate = 0.9;
         config.lr_decay_step = 100;
 
-        assert!((config.get_learning_rate(0) - 0.1).abs() < 1e-10);
-        assert!((config.get_learning_rate(50) - 0.1).abs() < 1e-10);
-        assert!((config.get_learning_rate(100) - 0.09).abs() < 1e-10);
-        assert!((config.get_learning_rate(200) - 0.081).abs() < 1e-10);
+        let schedule = config.create_schedule().unwrap();
+        let lr = |epoch| config.learning_rate * schedule.factor(epoch);
+        assert!((lr(0) - 0.1).abs() < 1e-10);
+        assert!((lr(50) - 0.1).abs() < 1e-10);
+        assert!((lr(100) - 0.09).abs() < 1e-10);
+        assert!((lr(200) - 0.081).abs() < 1e-10);
     }
 
     #[test]
@@ -492,6 +919,144 @@ mod tests {
         assert_eq!(config.get_dropout_rates(), vec![0.3, 0.2, 0.1, 0.0]);
     }
 
+    #[test]
+    fn test_parse_optimizer() {
+        let content = "optimizer = Adam\nbeta1 = 0.8\nbeta2 = 0.99\nepsilon = 1e-6";
+        let config = TrainingConfig::parse(content).unwrap();
+        assert_eq!(config.optimizer, "adam");
+        assert_eq!(config.beta1, 0.8);
+        assert_eq!(config.beta2, 0.99);
+        assert_eq!(config.epsilon, 1e-6);
+        assert_eq!(config.create_optimizer::<f64>().unwrap().name(), "adam");
+
+        assert!(TrainingConfig::parse("optimizer = adagrad").is_err());
+        assert!(TrainingConfig::parse("optimizer = adam\nbeta2 = 1.0").is_err());
+    }
+
+    #[test]
+    fn test_parse_loss() {
+        let config = TrainingConfig::default();
+        assert_eq!(config.create_loss::<f64>().unwrap().name(), "cross_entropy");
+
+        let config = TrainingConfig::parse("loss = Focal(1.5)").unwrap();
+        assert_eq!(config.create_loss::<f64>().unwrap().name(), "focal(1.5)");
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.loss, "focal(1.5)");
+
+        let config = TrainingConfig::parse("loss = weighted_cross_entropy(1, 2, 2, 4, 4)").unwrap();
+        assert_eq!(
+            config.create_loss::<f64>().unwrap().class_weights(),
+            Some(&[1.0, 2.0, 2.0, 4.0, 4.0][..])
+        );
+
+        assert!(TrainingConfig::parse("loss = weighted_cross_entropy(1, 2)").is_err());
+        assert!(TrainingConfig::parse("loss = hinge").is_err());
+    }
+
+    #[test]
+    fn test_parse_regularization() {
+        assert_eq!(
+            TrainingConfig::default().regularization(),
+            Regularization::default()
+        );
+
+        let content = "l1 = 1e-5\nl2 = 0.001\nmax_norm = 3\nregularize_biases = yes";
+        let config = TrainingConfig::parse(content).unwrap();
+        let expected = Regularization {
+            l1: 1e-5,
+            l2: 0.001,
+            max_norm: 3.0,
+            include_biases: true,
+        };
+        assert_eq!(config.regularization(), expected);
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.regularization(), expected);
+
+        assert!(TrainingConfig::parse("l2 = -0.1").is_err());
+        assert!(TrainingConfig::parse("max_norm = inf").is_err());
+    }
+
+    #[test]
+    fn test_parse_gradient_clipping() {
+        assert_eq!(
+            TrainingConfig::default().gradient_clipping(),
+            GradientClipping::default()
+        );
+
+        let config = TrainingConfig::parse("clip_grad_norm = 1.5\nclip_grad_value = 0.1").unwrap();
+        let expected = GradientClipping {
+            norm: 1.5,
+            value: 0.1,
+        };
+        assert_eq!(config.gradient_clipping(), expected);
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.gradient_clipping(), expected);
+
+        assert!(TrainingConfig::parse("clip_grad_norm = -1").is_err());
+    }
+
+    #[test]
+    fn test_parse_class_imbalance() {
+        let config = TrainingConfig::default();
+        assert_eq!(config.class_weights, ClassWeights::None);
+        assert_eq!(config.min_per_class, 0);
+
+        let config =
+            TrainingConfig::parse("class_weights = Auto\nbatch_size = 20\nmin_per_class = 4")
+                .unwrap();
+        assert_eq!(config.class_weights, ClassWeights::Auto);
+        assert_eq!(config.min_per_class, 4);
+
+        let config = TrainingConfig::parse("class_weights = [1, 2, 2, 8, 8]").unwrap();
+        assert_eq!(
+            config.class_weights,
+            ClassWeights::Manual(vec![1.0, 2.0, 2.0, 8.0, 8.0])
+        );
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.class_weights, config.class_weights);
+
+        assert!(TrainingConfig::parse("class_weights = [1, 2]").is_err());
+        assert!(TrainingConfig::parse("class_weights = [0, 0, 0, 0, 0]").is_err());
+        assert!(TrainingConfig::parse("class_weights = balanced").is_err());
+        assert!(TrainingConfig::parse(
+            "class_weights = auto\nloss = weighted_cross_entropy(1, 2, 2, 4, 4)"
+        )
+        .is_err());
+        assert!(TrainingConfig::parse("batch_size = 8\nmin_per_class = 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_activations() {
+        let content = "hidden_activation = leaky_relu(0.05)\noutput_activation = linear";
+        let config = TrainingConfig::parse(content).unwrap();
+        assert_eq!(config.hidden_activation, Activation::LeakyRelu(0.05));
+        assert_eq!(config.output_activation, Activation::Linear);
+
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.hidden_activation, Activation::LeakyRelu(0.05));
+
+        assert!(TrainingConfig::parse("hidden_activation = rellu").is_err());
+    }
+
+    #[test]
+    fn test_parse_architecture() {
+        let content = "architecture = dense(64), layernorm, dropout(0.2), dense(32, tanh)";
+        let config = TrainingConfig::parse(content).unwrap();
+        assert_eq!(config.architecture.len(), 4);
+        assert_eq!(config.architecture[2], LayerSpec::Dropout(0.2));
+
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.architecture, config.architecture);
+        assert!(
+            TrainingConfig::parse(&TrainingConfig::default().to_string())
+                .unwrap()
+                .architecture
+                .is_empty()
+        );
+
+        assert!(TrainingConfig::parse("architecture = dense(64), pool(2)").is_err());
+    }
+
     #[test]
     fn test_dropout_validation() {
         let mut config = TrainingConfig::default();
@@ -511,4 +1076,59 @@ mod tests {
         config.dropout_rates = vec![0.2, 0.3, 0.1];
         assert!(config.validate().is_ok());
     }
+
+    #[test]
+    fn test_parse_lr_schedule() {
+        let config = TrainingConfig::default();
+        assert_eq!(config.create_schedule().unwrap().name(), "constant");
+
+        let config = TrainingConfig::parse("epochs = 50\nlr_schedule = warmup(5), Cosine").unwrap();
+        assert_eq!(
+            config.create_schedule().unwrap().name(),
+            "warmup(5), cosine(45, 1)"
+        );
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.lr_schedule, "warmup(5), cosine");
+
+        assert!(TrainingConfig::parse("lr_schedule = cosine(10, 0.5)").is_err());
+        assert!(TrainingConfig::parse("lr_schedule = plateau\nlr_decay_enabled = true").is_err());
+    }
+
+    #[test]
+    fn test_parse_restore_best_weights() {
+        assert!(TrainingConfig::default().restore_best_weights);
+        let config = TrainingConfig::parse("restore_best_weights = false").unwrap();
+        assert!(!config.restore_best_weights);
+        assert!(TrainingConfig::parse("restore_best_weights = maybe").is_err());
+    }
+
+    #[test]
+    fn test_parse_checkpoint_every() {
+        assert_eq!(TrainingConfig::default().checkpoint_every, 0);
+        let config = TrainingConfig::parse("checkpoint_every = 5").unwrap();
+        assert_eq!(config.checkpoint_every, 5);
+        assert!(TrainingConfig::parse("checkpoint_every = -1").is_err());
+    }
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(TrainingConfig::default().seed, None);
+        let config = TrainingConfig::parse("seed = 18446744073709551615").unwrap();
+        assert_eq!(config.seed, Some(u64::MAX));
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.seed, Some(u64::MAX));
+        let reparsed = TrainingConfig::parse(&TrainingConfig::default().to_string()).unwrap();
+        assert_eq!(reparsed.seed, None);
+        assert!(TrainingConfig::parse("seed = -3").is_err());
+    }
+
+    #[test]
+    fn test_parse_precision() {
+        assert_eq!(TrainingConfig::default().precision, Precision::F64);
+        let config = TrainingConfig::parse("precision = f32").unwrap();
+        assert_eq!(config.precision, Precision::F32);
+        let reparsed = TrainingConfig::parse(&config.to_string()).unwrap();
+        assert_eq!(reparsed.precision, Precision::F32);
+        assert!(TrainingConfig::parse("precision = f16").is_err());
+    }
 }
@@ -0,0 +1,36 @@
+use crate::network::datastruct::binary::{ModelFormat, Precision};
+use crate::network::datastruct::network::Network;
+use crate::parse_config::Config;
+
+pub fn run_convert(config: &Config) -> Result<(), String> {
+    let (network, input_format) = Network::<f64>::load_with_format(&config.loadfile)?;
+
+    let output_format = match config.format {
+        Some(ref name) => ModelFormat::from_name(name)?,
+        None => match input_format {
+            ModelFormat::Text => ModelFormat::Binary(Precision::F64),
+            ModelFormat::Binary(_) => ModelFormat::Text,
+        },
+    };
+
+    let save_path = config.savefile.as_ref().ok_or("SAVEFILE not specified")?;
+    network.save_as(save_path, output_format)?;
+
+    println!(
+        "Converted '{}' ({}) to '{}' ({})",
+        config.loadfile,
+        format_name(input_format),
+        save_path,
+        format_name(output_format)
+    );
+
+    Ok(())
+}
+
+fn format_name(format: ModelFormat) -> &'static str {
+    match format {
+        ModelFormat::Text => "text",
+        ModelFormat::Binary(Precision::F32) => "binary f32",
+        ModelFormat::Binary(Precision::F64) => "binary f64",
+    }
+}
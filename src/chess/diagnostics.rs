@@ -0,0 +1,89 @@
+use crate::chess::config::TrainingConfig;
+use crate::chess::fen;
+use crate::chess::trainer::{convert_to_training_data, create_weighted_loss, read_training_file};
+use crate::network::datastruct::network::Network;
+use crate::network::gradient_check::GradientCheck;
+use crate::network::random;
+use crate::parse_config::Config;
+
+/// Positions of CHESSFILE used for the check
+const CHECK_BATCH: usize = 16;
+
+/// Compare the backprop gradients of the network in LOADFILE with finite
+/// differences of the loss (the one of --config with its class weights,
+/// cross-entropy by default) on the first positions of CHESSFILE
+pub fn run_check_gradients(config: &Config) -> Result<(), String> {
+    let mut network =
+        Network::load(&config.loadfile).map_err(|e| format!("Failed to load network: {}", e))?;
+    if network.nb_inputs() != fen::INPUT_SIZE {
+        return Err(format!(
+            "Network expects {} inputs, chessboards are encoded on {}",
+            network.nb_inputs(),
+            fen::INPUT_SIZE
+        ));
+    }
+
+    let mut raw_data = read_training_file(&config.chessfile)?;
+    raw_data.truncate(CHECK_BATCH);
+    let batch = convert_to_training_data(&raw_data)?;
+    if network.nb_outputs() != batch[0].1.len() {
+        return Err(format!(
+            "Network has {} outputs, positions are labelled with {} classes",
+            network.nb_outputs(),
+            batch[0].1.len()
+        ));
+    }
+
+    let train_config = match config.configfile {
+        Some(ref conf_file) => TrainingConfig::load(conf_file)?,
+        None => TrainingConfig::default(),
+    };
+    // The loss training minimises, class weights included (`auto` ones
+    // computed on the checked positions)
+    let loss = create_weighted_loss(&batch, &train_config)?;
+    // Picks the checked values and the dropout masks
+    if let Some(seed) = config.seed.or(train_config.seed) {
+        random::seed(seed);
+    }
+
+    let check = GradientCheck {
+        samples: config.samples,
+        ..GradientCheck::default()
+    };
+    match config.samples {
+        Some(samples) => println!(
+            "Checking {} random values per parameter group on {} positions ({} loss)...",
+            samples,
+            batch.len(),
+            loss.name()
+        ),
+        None => println!(
+            "Checking every parameter on {} positions ({} loss)...",
+            batch.len(),
+            loss.name()
+        ),
+    }
+    // Dropout is only active in training mode, batch normalization behaves
+    // differently in each: both modes are checked
+    let mut ok = true;
+    for training in [false, true] {
+        println!(
+            "\n{} mode:",
+            if training { "Training" } else { "Inference" }
+        );
+        let report = check.check_network(&mut network, &batch, loss.as_ref(), training);
+        println!("Loss gradients of the parameters:\n{}", report);
+        ok &= report.is_ok();
+        let report = check.check_layers(&mut network, &batch, training);
+        println!("Input and parameter gradients, layer by layer:\n{}", report);
+        ok &= report.is_ok();
+    }
+
+    if ok {
+        Ok(())
+    } else {
+        Err(String::from(
+            "backprop gradients do not match finite differences",
+        ))
+    }
+}
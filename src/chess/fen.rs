@@ -1,12 +1,27 @@
+use crate::network::datastruct::accumulator::FeatureDelta;
+
+/// Name recorded in network metadata for the encoding built by `to_inputs`
+pub const INPUT_ENCODING: &str = "fen_onehot_64x13_side";
+/// 64 squares × 13 states + 1 (active color)
+pub const INPUT_SIZE: usize = 833;
+/// One-hot planes of the board (empty square + 12 pieces), one value per
+/// plane for each square in turn: the 8×8×13 planes, channels last
+pub const BOARD_PLANES: usize = 13;
+
 #[derive(Debug, Clone)]
 pub struct FenPosition {
     pub board: [char; 64],
     pub active_color: char,
 }
 
+/// Input index of `piece` (' ' for an empty square) standing on `square`
+pub fn feature(square: usize, piece: char) -> usize {
+    square * 13 + FenPosition::piece_to_index(piece)
+}
+
 impl FenPosition {
     pub fn parse(fen: &str) -> Result<Self, String> {
-        let parts: Vec<&str> = fen.trim().split_whitespace().collect();
+        let parts: Vec<&str> = fen.split_whitespace().collect();
 
         if parts.is_empty() {
             return Err(String::from("Empty FEN string"));
@@ -89,12 +104,10 @@ impl FenPosition {
 
     // Encode la position en vecteur d'inputs pour le réseau de neurones
     pub fn to_inputs(&self) -> Vec<f64> {
-        let mut inputs = vec![0.0; 833];
+        let mut inputs = vec![0.0; INPUT_SIZE];
 
         for (i, &piece) in self.board.iter().enumerate() {
-            let piece_idx = Self::piece_to_index(piece);
-            let input_idx = i * 13 + piece_idx;
-            inputs[input_idx] = 1.0;
+            inputs[feature(i, piece)] = 1.0;
         }
 
         inputs[832] = if self.active_color == 'w' { 1.0 } else { 0.0 };
@@ -102,6 +115,38 @@ impl FenPosition {
         inputs
     }
 
+    /// Indices of the inputs `to_inputs` sets to 1, in increasing order
+    pub fn active_features(&self) -> Vec<usize> {
+        let mut features: Vec<usize> = self
+            .board
+            .iter()
+            .enumerate()
+            .map(|(i, &piece)| feature(i, piece))
+            .collect();
+        if self.active_color == 'w' {
+            features.push(832);
+        }
+        features
+    }
+
+    /// Features to switch off and on to go from this position to `next`:
+    /// two per changed square, plus the side to move
+    pub fn delta_to(&self, next: &FenPosition) -> FeatureDelta {
+        let mut delta = FeatureDelta::default();
+        for (square, (&before, &after)) in self.board.iter().zip(next.board.iter()).enumerate() {
+            if before != after {
+                delta = delta
+                    .with_removed(feature(square, before))
+                    .with_added(feature(square, after));
+            }
+        }
+        match (self.active_color == 'w', next.active_color == 'w') {
+            (true, false) => delta.with_removed(832),
+            (false, true) => delta.with_added(832),
+            _ => delta,
+        }
+    }
+
     fn piece_to_index(piece: char) -> usize {
         match piece {
             ' ' => 0,
@@ -159,4 +204,31 @@ mod tests {
         // Donc l'input à l'index 56*13 + 6 devrait être 1.0
         assert_eq!(inputs[56 * 13 + 6], 1.0);
     }
+
+    #[test]
+    fn test_active_features() {
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let pos = FenPosition::parse(fen).unwrap();
+        let inputs = pos.to_inputs();
+        let from_inputs: Vec<usize> = (0..INPUT_SIZE).filter(|&i| inputs[i] != 0.0).collect();
+        assert_eq!(pos.active_features(), from_inputs);
+        assert_eq!(from_inputs.len(), 64);
+    }
+
+    #[test]
+    fn test_delta_to() {
+        let start =
+            FenPosition::parse("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let next =
+            FenPosition::parse("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+                .unwrap();
+        let delta = start.delta_to(&next);
+        assert_eq!(delta.nb_changes(), 5);
+
+        let mut features = start.active_features();
+        features.retain(|f| !delta.removed.contains(f));
+        features.extend(&delta.added);
+        features.sort();
+        assert_eq!(features, next.active_features());
+    }
 }
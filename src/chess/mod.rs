@@ -1,4 +1,8 @@
+pub mod checkpoint;
 pub mod config;
+pub mod converter;
+pub mod diagnostics;
 pub mod fen;
 pub mod predictor;
+pub mod sampler;
 pub mod trainer;
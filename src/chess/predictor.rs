@@ -1,25 +1,96 @@
-use crate::chess::fen::FenPosition;
+use crate::chess::fen::{self, FenPosition};
+use crate::network::datastruct::accumulator::Accumulator;
+use crate::network::datastruct::binary::Precision;
+use crate::network::datastruct::metadata;
 use crate::network::datastruct::network::Network;
+use crate::network::float::{self, Float};
 use crate::parse_config::Config;
 use std::fs;
 
+/// Output classes of the chess networks, in output order
+pub const CLASS_LABELS: [&str; 5] = [
+    "Nothing",
+    "Check White",
+    "Check Black",
+    "Checkmate White",
+    "Checkmate Black",
+];
+
 pub fn run_predict(config: &Config) -> Result<(), String> {
-    let network =
-        Network::load(&config.loadfile).map_err(|e| format!("Failed to load network: {}", e))?;
+    match config.precision {
+        Precision::F64 => predict::<f64>(config),
+        Precision::F32 => predict::<f32>(config),
+    }
+}
+
+/// `run_predict` with the network loaded in precision `T`, whatever the
+/// precision of its file
+fn predict<T: Float>(config: &Config) -> Result<(), String> {
+    let network = Network::<T>::load(&config.loadfile)
+        .map_err(|e| format!("Failed to load network: {}", e))?;
+
+    if let Some(encoding) = network.metadata.get(metadata::INPUT_ENCODING) {
+        if encoding != fen::INPUT_ENCODING {
+            return Err(format!(
+                "Network expects '{}' inputs, only '{}' is supported",
+                encoding,
+                fen::INPUT_ENCODING
+            ));
+        }
+    }
+
+    if network.nb_inputs() != fen::INPUT_SIZE {
+        return Err(format!(
+            "Network expects {} inputs, chessboards are encoded on {}",
+            network.nb_inputs(),
+            fen::INPUT_SIZE
+        ));
+    }
+
+    // Networks saved with metadata name their classes; older files are
+    // decoded from the number of outputs.
+    let labels = network.metadata.labels();
+    if let Some(labels) = &labels {
+        if labels.len() != network.nb_outputs() {
+            return Err(format!(
+                "Network has {} outputs but its metadata lists {} labels",
+                network.nb_outputs(),
+                labels.len()
+            ));
+        }
+    }
 
     let positions = read_chess_file(&config.chessfile)?;
 
+    // Consecutive positions usually differ by a move: with a dense or
+    // sparse_dense first layer, only the changed features are updated.
+    let mut accumulator = Accumulator::new(&network, &[]).ok();
+    let mut previous: Option<FenPosition> = None;
+
     for fen_line in positions {
         let fen = extract_fen(&fen_line);
 
         let position =
             FenPosition::parse(&fen).map_err(|e| format!("Invalid FEN '{}': {}", fen, e))?;
 
-        let inputs = position.to_inputs();
-
-        let outputs = network.exec(inputs);
+        let outputs = match &mut accumulator {
+            Some(accumulator) => {
+                let delta = previous.as_ref().map(|p| p.delta_to(&position));
+                match delta {
+                    Some(delta) if delta.nb_changes() < 32 => accumulator.update(&delta),
+                    _ => accumulator.refresh(&position.active_features()),
+                }
+                accumulator.evaluate()
+            }
+            None => network.exec(float::cast_all(&position.to_inputs())),
+        };
+        let outputs = float::widen_all(&outputs);
+        previous = Some(position);
 
-        let prediction = outputs_to_label(&outputs);
+        let prediction = match labels {
+            Some(ref labels) => labels[find_max_index(&outputs)].clone(),
+            None => outputs_to_label(&outputs),
+        };
 
         println!("{}", prediction);
     }
@@ -55,7 +126,7 @@ fn extract_fen(line: &str) -> String {
     }
 }
 
-fn outputs_to_label(outputs: &Vec<f64>) -> String {
+fn outputs_to_label(outputs: &[f64]) -> String {
     match outputs.len() {
         1 => {
             if outputs[0] > 0.5 {
@@ -98,7 +169,7 @@ fn outputs_to_label(outputs: &Vec<f64>) -> String {
     }
 }
 
-fn find_max_index(values: &Vec<f64>) -> usize {
+fn find_max_index(values: &[f64]) -> usize {
     values
         .iter()
         .enumerate()
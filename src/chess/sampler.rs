@@ -0,0 +1,172 @@
+use crate::chess::trainer::Dataset;
+use crate::network::float::Float;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Index of the largest value: the class of a one-hot target, or the
+/// predicted class of network outputs
+pub fn class_of<T: Float>(values: &[T]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(idx, _)| idx)
+        .unwrap_or(0)
+}
+
+/// Number of examples of each class
+pub fn class_counts<T: Float>(data: &Dataset<T>, classes: usize) -> Vec<usize> {
+    let mut counts = vec![0; classes];
+    for (_, targets) in data {
+        counts[class_of(targets)] += 1;
+    }
+    counts
+}
+
+/// `total / (classes · count)` for every class, so that each class weighs
+/// as much as the others in the loss. Only the classes present in the data
+/// are counted in `classes`, absent ones keep a weight of 1.
+pub fn inverse_frequency_weights(counts: &[usize]) -> Vec<f64> {
+    let total: usize = counts.iter().sum();
+    let classes = counts.iter().filter(|&&count| count > 0).count();
+    counts
+        .iter()
+        .map(|&count| {
+            if count == 0 {
+                1.0
+            } else {
+                total as f64 / (classes * count) as f64
+            }
+        })
+        .collect()
+}
+
+/// Mini-batch sampler that guarantees every batch a minimum number of
+/// examples of each class present in the data.
+///
+/// An epoch keeps the usual number and sizes of batches. Each batch first
+/// draws `min_per_class` examples of every class, cycling through a
+/// shuffled copy of that class (rare classes are repeated), then fills up
+/// with examples of a shuffled pass over the whole dataset.
+pub struct StratifiedSampler {
+    by_class: Vec<Vec<usize>>,
+    len: usize,
+}
+
+/// Shuffled indices drawn in a loop, reshuffled at every wrap
+struct Cycle {
+    indices: Vec<usize>,
+    position: usize,
+}
+
+impl Cycle {
+    fn next(&mut self, rng: &mut impl Rng) -> usize {
+        if self.position == self.indices.len() {
+            self.indices.shuffle(rng);
+            self.position = 0;
+        }
+        self.position += 1;
+        self.indices[self.position - 1]
+    }
+}
+
+impl StratifiedSampler {
+    pub fn new<T: Float>(data: &Dataset<T>, classes: usize) -> Self {
+        let mut by_class = vec![vec![]; classes];
+        for (index, (_, targets)) in data.iter().enumerate() {
+            by_class[class_of(targets)].push(index);
+        }
+        StratifiedSampler {
+            by_class,
+            len: data.len(),
+        }
+    }
+
+    /// Indices of the examples of every batch of one epoch
+    pub fn batches(
+        &self,
+        batch_size: usize,
+        min_per_class: usize,
+        rng: &mut impl Rng,
+    ) -> Vec<Vec<usize>> {
+        let mut order: Vec<usize> = (0..self.len).collect();
+        order.shuffle(rng);
+        let mut order = order.into_iter();
+        let mut cycles: Vec<Cycle> = self
+            .by_class
+            .iter()
+            .filter(|indices| !indices.is_empty())
+            .map(|indices| Cycle {
+                indices: indices.clone(),
+                position: indices.len(),
+            })
+            .collect();
+
+        let mut batches = vec![];
+        let mut remaining = self.len;
+        while remaining > 0 {
+            let size = batch_size.min(remaining);
+            remaining -= size;
+            let mut batch = Vec::with_capacity(size);
+            if size >= cycles.len() * min_per_class {
+                for cycle in &mut cycles {
+                    for _ in 0..min_per_class {
+                        batch.push(cycle.next(rng));
+                    }
+                }
+            }
+            while batch.len() < size {
+                batch.push(order.next().expect("batches never exceed the dataset"));
+            }
+            batch.shuffle(rng);
+            batches.push(batch);
+        }
+        batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn dataset(counts: &[usize]) -> Dataset {
+        let mut data = vec![];
+        for (class, &count) in counts.iter().enumerate() {
+            let mut targets = vec![0.0; counts.len()];
+            targets[class] = 1.0;
+            for _ in 0..count {
+                data.push((vec![class as f64], targets.clone()));
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_every_batch_has_each_class() {
+        let data = dataset(&[90, 7, 0, 3]);
+        assert_eq!(class_counts(&data, 4), vec![90, 7, 0, 3]);
+
+        let sampler = StratifiedSampler::new(&data, 4);
+        let mut rng = StdRng::seed_from_u64(1);
+        let batches = sampler.batches(16, 2, &mut rng);
+        let sizes: Vec<usize> = batches.iter().map(|b| b.len()).collect();
+        assert_eq!(sizes, vec![16, 16, 16, 16, 16, 16, 4]);
+
+        for batch in &batches[..6] {
+            let classes: Vec<usize> = batch.iter().map(|&i| class_of(&data[i].1)).collect();
+            for class in [0, 1, 3] {
+                assert!(classes.iter().filter(|&&c| c == class).count() >= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inverse_frequency_weights() {
+        let weights = inverse_frequency_weights(&[80, 20, 0]);
+        assert!((weights[0] - 100.0 / 160.0).abs() < 1e-12);
+        assert!((weights[1] - 100.0 / 40.0).abs() < 1e-12);
+        assert_eq!(weights[2], 1.0);
+    }
+}
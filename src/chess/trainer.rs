@@ -1,22 +1,52 @@
-use crate::chess::config::TrainingConfig;
-use crate::chess::fen::FenPosition;
+use crate::chess::checkpoint::{self, Progress};
+use crate::chess::config::{ClassWeights, TrainingConfig};
+use crate::chess::fen::{self, FenPosition};
+use crate::chess::predictor::CLASS_LABELS;
+use crate::chess::sampler::{self, StratifiedSampler};
+use crate::network::datastruct::architecture::{LayerSpec, Shape};
+use crate::network::datastruct::binary::{ModelFormat, Precision};
+use crate::network::datastruct::metadata::{self, Metadata};
 use crate::network::datastruct::network::Network;
+use crate::network::float::{self, Float};
+use crate::network::loss::{ClassWeighted, Loss};
+use crate::network::random;
+use crate::network::regularization::Regularization;
 use crate::parse_config::Config;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng, SeedableRng};
 use std::fs;
 
+/// Encoded network inputs paired with their one-hot targets
+pub type Dataset<T = f64> = Vec<(Vec<T>, Vec<T>)>;
+
 pub fn run_train(config: &Config) -> Result<(), String> {
     println!("=== Training Mode ===\n");
 
     // Load training configuration
-    let train_config = if let Some(ref conf_file) = config.configfile {
+    let mut resumed = None;
+    let mut train_config = if let Some(ref checkpoint_file) = config.resume {
+        println!("Resuming training from checkpoint '{}'...", checkpoint_file);
+        let (train_config, network, progress) = checkpoint::load(checkpoint_file)?;
+        resumed = Some((network, progress));
+        train_config
+    } else if let Some(ref conf_file) = config.configfile {
         println!("Loading training configuration from '{}'...", conf_file);
         TrainingConfig::load(conf_file)?
     } else {
         println!("Using default training configuration");
         TrainingConfig::default()
     };
+    // Kept in the configuration so that the saved network records it
+    let seed = match &resumed {
+        Some((_, progress)) => progress.seed,
+        None => config
+            .seed
+            .or(train_config.seed)
+            .unwrap_or_else(|| thread_rng().gen()),
+    };
+    train_config.seed = Some(seed);
+    random::seed(seed);
 
     println!("\nTraining Configuration:");
     println!("  Learning rate: {}", train_config.learning_rate);
@@ -24,35 +54,94 @@ pub fn run_train(config: &Config) -> Result<(), String> {
     println!("  Batch size: {}", train_config.batch_size);
     println!("  Patience: {}", train_config.patience);
     println!("  Train ratio: {}", train_config.train_ratio);
-    println!("  Architecture: {:?}", train_config.hidden_layers);
-    if train_config.lr_decay_enabled {
+    println!("  Loss: {}", train_config.loss);
+    println!("  Class weights: {}", train_config.class_weights);
+    if train_config.min_per_class > 0 {
+        println!("  Min per class: {}", train_config.min_per_class);
+    }
+    if train_config.architecture.is_empty() {
+        println!("  Architecture: {:?}", train_config.hidden_layers);
+    } else {
+        println!("  Architecture: {}", train_config.format_architecture());
+    }
+    println!("  Threads: {}", rayon::current_num_threads());
+    println!("  Seed: {}", seed);
+    println!("  Precision: {}", train_config.precision);
+    println!("  LR schedule: {}", train_config.create_schedule()?.name());
+    if train_config.clip_grad_norm > 0.0 || train_config.clip_grad_value > 0.0 {
         println!(
-            "  LR decay: enabled (rate={}, step={})",
-            train_config.lr_decay_rate, train_config.lr_decay_step
+            "  Gradient clipping: norm={}, value={}",
+            train_config.clip_grad_norm, train_config.clip_grad_value
+        );
+    }
+    let regularization = train_config.regularization();
+    if regularization != Regularization::default() {
+        println!(
+            "  Regularization: l1={}, l2={}, max_norm={}, biases {}",
+            regularization.l1,
+            regularization.l2,
+            regularization.max_norm,
+            if regularization.include_biases {
+                "included"
+            } else {
+                "excluded"
+            }
         );
     }
     println!();
 
-    let mut network = if std::path::Path::new(&config.loadfile).exists() {
-        println!("Loading existing network from '{}'...", config.loadfile);
-        let mut net = Network::load(&config.loadfile)?;
+    match train_config.precision {
+        Precision::F64 => train::<f64>(config, &train_config, seed, resumed),
+        Precision::F32 => train::<f32>(config, &train_config, seed, resumed),
+    }
+}
 
-        let dropout_rates = vec![0.3, 0.2, 0.1, 0.05, 0.0];
-        println!("  Reconfiguring dropout rates: {:?}", dropout_rates);
-        for (idx, layer) in net.0.iter_mut().enumerate() {
-            if idx < dropout_rates.len() {
-                layer.set_dropout(dropout_rates[idx]);
+/// The rest of `run_train`, with the network and the data in precision `T`
+fn train<T: Float>(
+    config: &Config,
+    train_config: &TrainingConfig,
+    seed: u64,
+    resumed: Option<(Network, Progress)>,
+) -> Result<(), String> {
+    let mut save_format = ModelFormat::Text;
+    let (mut network, progress) = if let Some((network, progress)) = resumed {
+        // The network comes from the checkpoint, LOADFILE only gives the
+        // format to save in
+        if std::path::Path::new(&config.loadfile).exists() {
+            let content = std::fs::read(&config.loadfile)
+                .map_err(|e| format!("Failed to read {}: {}", config.loadfile, e))?;
+            save_format = ModelFormat::detect(&content);
+        }
+        (network.convert(), progress.convert())
+    } else if std::path::Path::new(&config.loadfile).exists() {
+        println!("Loading existing network from '{}'...", config.loadfile);
+        let (mut net, format) = Network::<T>::load_with_format(&config.loadfile)?;
+        save_format = format;
+
+        // Older files have no dropout layers: their rates were listed in
+        // the metadata, or fixed before files had metadata
+        if !net.layers.iter().any(|layer| layer.kind() == "dropout") {
+            let dropout_rates = match net.metadata.dropout_rates()? {
+                Some(rates) => Some(rates),
+                None if net.metadata.is_empty() => Some(vec![0.3, 0.2, 0.1, 0.05, 0.0]),
+                None => None,
+            };
+            if let Some(dropout_rates) = dropout_rates.filter(|r| r.iter().any(|&r| r > 0.0)) {
+                println!("  Adding dropout layers: {:?}", dropout_rates);
+                net.add_dropout(&dropout_rates);
             }
         }
+        net.metadata.remove(metadata::DROPOUT_RATES);
 
-        net
+        (net, Progress::new(seed))
     } else {
         println!(
             "Creating new network (file '{}' not found)...",
             config.loadfile
         );
-        create_chess_network(&train_config)?
+        (create_chess_network(train_config)?, Progress::new(seed))
     };
+    network.check_batch_size(train_config.batch_size)?;
 
     println!("Reading training data from '{}'...", config.chessfile);
     let raw_data = read_training_file(&config.chessfile)?;
@@ -64,10 +153,12 @@ pub fn run_train(config: &Config) -> Result<(), String> {
 
     // Shuffle the dataset to ensure random distribution in train/val split
     println!("Shuffling dataset...");
-    #[allow(deprecated)]
-    use rand::SeedableRng;
-    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    let mut rng = StdRng::seed_from_u64(42);
     training_data.shuffle(&mut rng);
+    let training_data: Dataset<T> = training_data
+        .iter()
+        .map(|(inputs, targets)| (float::cast_all(inputs), float::cast_all(targets)))
+        .collect();
 
     let (train_set, val_set) = split_dataset(&training_data, train_config.train_ratio);
     println!(
@@ -76,18 +167,56 @@ pub fn run_train(config: &Config) -> Result<(), String> {
         val_set.len()
     );
 
-    println!("\nStarting training with Softmax + Cross-Entropy...");
-    train_network_softmax(&mut network, &train_set, &val_set, &train_config)?;
-
     let save_path = config.savefile.as_ref().unwrap_or(&config.loadfile);
+    let checkpoint_path = format!("{}.ckpt", save_path);
+
+    println!("\nStarting training with {} loss...", train_config.loss);
+    let last_network = train_network(
+        &mut network,
+        &train_set,
+        &val_set,
+        train_config,
+        progress,
+        &checkpoint_path,
+    )?;
+
+    if let Some(ref name) = config.format {
+        save_format = ModelFormat::from_name(name)?;
+    }
+
+    let mut final_network = config
+        .final_savefile
+        .as_ref()
+        .map(|_| last_network.unwrap_or_else(|| network.clone()));
+    let trained_at = format_utc_now();
+    for net in std::iter::once(&mut network).chain(final_network.as_mut()) {
+        describe_network(&mut net.metadata, train_config);
+        net.metadata.set(metadata::TRAINED_AT, &trained_at);
+        net.metadata
+            .set(metadata::DATASET_HASH, &dataset_hash(&raw_data));
+    }
+
     println!("\nSaving trained network to '{}'...", save_path);
-    network.save(save_path)?;
+    network.save_as(save_path, save_format)?;
     println!("✓ Network saved successfully");
 
+    if let (Some(final_path), Some(final_network)) = (&config.final_savefile, &final_network) {
+        println!("Saving last epoch network to '{}'...", final_path);
+        final_network.save_as(final_path, save_format)?;
+        println!("✓ Network saved successfully");
+    }
+
     Ok(())
 }
 
-fn create_chess_network(train_config: &TrainingConfig) -> Result<Network, String> {
+fn create_chess_network<T: Float>(train_config: &TrainingConfig) -> Result<Network<T>, String> {
+    if !train_config.architecture.is_empty() {
+        let network = network_from_architecture(train_config)?;
+        println!("  Architecture: {}", train_config.format_architecture());
+        println!("  Total parameters: {}", network.count_parameters());
+        return Ok(network);
+    }
+
     let input_size = 833; // 64 cases * 13 états + 1 (active_color)
     let output_size = 5; // Nothing, Check White, Check Black, Checkmate White, Checkmate Black
 
@@ -98,14 +227,18 @@ fn create_chess_network(train_config: &TrainingConfig) -> Result<Network, String
 
     let dropout_rates = vec![0.4, 0.3, 0.2, 0.1, 0.0];
 
-    println!("  Using He initialization with ReLU activation");
+    println!(
+        "  Using He initialization with {} activation ({} output)",
+        train_config.hidden_activation, train_config.output_activation
+    );
     println!("  Dropout rates: {:?}", dropout_rates);
 
     let network = Network::new_random_he(
         input_size as u32,
-        layers.iter().map(|&x| x as u32).collect(),
-        dropout_rates,
-        "linear",
+        layers.clone(),
+        dropout_rates.clone(),
+        train_config.hidden_activation,
+        train_config.output_activation,
     );
 
     println!("  Total parameters: {}", network.count_parameters());
@@ -113,7 +246,88 @@ fn create_chess_network(train_config: &TrainingConfig) -> Result<Network, String
     Ok(network)
 }
 
-fn read_training_file(path: &str) -> Result<Vec<(String, String)>, String> {
+/// Network built from the `architecture` key, followed by the output layer
+pub fn network_from_architecture<T: Float>(
+    train_config: &TrainingConfig,
+) -> Result<Network<T>, String> {
+    let mut specs = train_config.architecture.clone();
+    specs.push(LayerSpec::Dense {
+        size: CLASS_LABELS.len(),
+        activation: Some(train_config.output_activation),
+    });
+    let input = Shape::Planes {
+        height: 8,
+        width: 8,
+        channels: fen::BOARD_PLANES,
+        extra: fen::INPUT_SIZE - 64 * fen::BOARD_PLANES,
+    };
+    Network::from_architecture(input, &specs, train_config.hidden_activation)
+}
+
+/// Record how the network's inputs and outputs are encoded and which
+/// configuration produced it.
+pub fn describe_network(metadata: &mut Metadata, train_config: &TrainingConfig) {
+    metadata.set(metadata::INPUT_ENCODING, fen::INPUT_ENCODING);
+    metadata.set(metadata::INPUT_SIZE, &fen::INPUT_SIZE.to_string());
+    metadata.set_labels(&CLASS_LABELS);
+
+    metadata.remove_prefix(metadata::TRAINING_PREFIX);
+    for line in train_config.to_string().lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            let key = format!("{}{}", metadata::TRAINING_PREFIX, key.trim());
+            metadata.set(&key, value.trim());
+        }
+    }
+}
+
+/// FNV-1a hash of the training examples, to tell which dataset a model saw
+fn dataset_hash(raw_data: &[(String, String)]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (fen, label) in raw_data {
+        for byte in fen
+            .bytes()
+            .chain([b' '])
+            .chain(label.bytes())
+            .chain([b'\n'])
+        {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("fnv1a64:{:016x}", hash)
+}
+
+/// Current time as `YYYY-MM-DDTHH:MM:SSZ`
+fn format_utc_now() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+pub fn read_training_file(path: &str) -> Result<Vec<(String, String)>, String> {
     let content =
         fs::read_to_string(path).map_err(|e| format!("Cannot read file {}: {}", path, e))?;
 
@@ -146,9 +360,7 @@ fn read_training_file(path: &str) -> Result<Vec<(String, String)>, String> {
     Ok(data)
 }
 
-fn convert_to_training_data(
-    raw_data: &Vec<(String, String)>,
-) -> Result<Vec<(Vec<f64>, Vec<f64>)>, String> {
+pub fn convert_to_training_data(raw_data: &[(String, String)]) -> Result<Dataset, String> {
     let mut training_data = Vec::new();
 
     for (i, (fen, label)) in raw_data.iter().enumerate() {
@@ -182,141 +394,240 @@ fn label_to_targets(label: &str) -> Result<Vec<f64>, String> {
     }
 }
 
-fn split_dataset<T: Clone>(data: &Vec<T>, train_ratio: f64) -> (Vec<T>, Vec<T>) {
+fn split_dataset<T: Clone>(data: &[T], train_ratio: f64) -> (Vec<T>, Vec<T>) {
     let split_idx = (data.len() as f64 * train_ratio) as usize;
     let train_set = data[..split_idx].to_vec();
     let val_set = data[split_idx..].to_vec();
     (train_set, val_set)
 }
 
-fn train_network_softmax(
-    network: &mut Network,
-    train_set: &Vec<(Vec<f64>, Vec<f64>)>,
-    val_set: &Vec<(Vec<f64>, Vec<f64>)>,
+/// Train `network` on `train_set` from `progress`, writing a checkpoint to
+/// `checkpoint_path` every `checkpoint_every` epochs and at the end of the
+/// run. With `restore_best_weights` the network ends up with the weights of
+/// the epoch with the best validation loss, and the weights of the last
+/// epoch are returned when they differ.
+fn train_network<T: Float>(
+    network: &mut Network<T>,
+    train_set: &Dataset<T>,
+    val_set: &Dataset<T>,
     train_config: &TrainingConfig,
-) -> Result<(), String> {
+    mut progress: Progress<T>,
+    checkpoint_path: &str,
+) -> Result<Option<Network<T>>, String> {
     let epochs = train_config.epochs;
     let patience_limit = train_config.patience;
 
-    let mut best_val_loss = f64::INFINITY;
-    let mut patience = 0;
+    let mut optimizer = train_config.create_optimizer()?;
+    println!("Optimizer: {}", optimizer.name());
+    let loss = create_weighted_loss(train_set, train_config)?;
+    let regularization = train_config.regularization();
+    let clipping = train_config.gradient_clipping();
+    let mut schedule = train_config.create_schedule()?;
+    if progress.epoch > 0 {
+        optimizer.load_state(std::mem::take(&mut progress.optimizer))?;
+        schedule.load_state(&progress.schedule)?;
+        println!("Resuming at epoch {}", progress.epoch);
+    }
+    let sampler = StratifiedSampler::new(train_set, CLASS_LABELS.len());
+
+    let mut last_epoch = progress.epoch.saturating_sub(1);
 
-    for epoch in 0..epochs {
-        let learning_rate = train_config.get_learning_rate(epoch);
+    for epoch in progress.epoch..epochs {
+        last_epoch = epoch;
+        let learning_rate = train_config.learning_rate * schedule.factor(epoch);
         let mut train_loss = 0.0;
-
-        let mut shuffled_train_set = train_set.clone();
-        let mut rng = thread_rng();
-        shuffled_train_set.shuffle(&mut rng);
+        let mut grad_norm_sum = 0.0;
+        let mut grad_norm_max: f64 = 0.0;
+
+        let mut rng = progress.epoch_rng(epoch);
+        let batches = sampler.batches(
+            train_config.batch_size,
+            train_config.min_per_class,
+            &mut rng,
+        );
+        random::seed(rng.gen());
 
         network.set_training_mode(true);
 
-        if train_config.batch_size == 1 {
-            for (inputs, targets) in &shuffled_train_set {
-                network.train_softmax_ce(inputs, targets, learning_rate);
+        for indices in &batches {
+            let batch: Dataset<T> = indices.iter().map(|&i| train_set[i].clone()).collect();
+            let grad_norm = network.train_batch(
+                &batch,
+                loss.as_ref(),
+                learning_rate,
+                optimizer.as_mut(),
+                &regularization,
+                &clipping,
+            );
+            grad_norm_sum += grad_norm;
+            grad_norm_max = grad_norm_max.max(grad_norm);
+
+            // Measured in inference mode: no dropout, and batch
+            // normalization uses (and leaves alone) its running statistics
+            network.set_training_mode(false);
+            for (inputs, targets) in &batch {
                 let outputs = network.exec(inputs.clone());
-                let loss = calculate_cross_entropy(&outputs, targets);
-                train_loss += loss;
-            }
-        } else {
-            for batch_start in (0..shuffled_train_set.len()).step_by(train_config.batch_size) {
-                let batch_end =
-                    (batch_start + train_config.batch_size).min(shuffled_train_set.len());
-
-                let batch = &shuffled_train_set[batch_start..batch_end].to_vec();
-
-                network.train_batch_softmax_ce(batch, learning_rate);
-
-                for (inputs, targets) in batch {
-                    let outputs = network.exec(inputs.clone());
-                    let loss = calculate_cross_entropy(&outputs, targets);
-                    train_loss += loss;
-                }
+                train_loss += loss.sample_value(&outputs, targets);
             }
+            network.set_training_mode(true);
         }
-        train_loss /= shuffled_train_set.len() as f64;
+        train_loss /= train_set.len() as f64;
+        train_loss += regularization.penalty(network);
+        let grad_norm_mean = grad_norm_sum / batches.len() as f64;
 
-        if let Err(e) = network.check_gradients() {
-            eprintln!("Gradient check failed at epoch {}: {}", epoch, e);
+        if let Err(e) = network.check_parameters() {
+            eprintln!("Parameter check failed at epoch {}: {}", epoch, e);
             return Err(format!("Training diverged at epoch {}: {}", epoch, e));
         }
 
         network.set_training_mode(false);
 
-        let mut val_loss = 0.0;
-        let mut correct = 0;
-        for (inputs, targets) in val_set {
-            let outputs = network.exec(inputs.clone());
-            let loss = calculate_cross_entropy(&outputs, targets);
-            val_loss += loss;
+        let validation = evaluate(network, val_set, loss.as_ref());
+        let val_loss = validation.loss;
+
+        schedule.observe(epoch, val_loss);
+
+        let summary = format!(
+            "Epoch {}: train_loss={:.6}, val_loss={:.6}, val_acc={:.2}%, val_bal_acc={:.2}%, lr={:.6}, grad_norm={:.4} (max {:.4})",
+            epoch,
+            train_loss,
+            val_loss,
+            validation.accuracy,
+            validation.balanced_accuracy(),
+            learning_rate,
+            grad_norm_mean,
+            grad_norm_max
+        );
 
-            if are_predictions_equal(&outputs, targets) {
-                correct += 1;
+        let mut stop = false;
+        if val_loss < progress.best_val_loss {
+            progress.best_val_loss = val_loss;
+            progress.patience = 0;
+            if train_config.restore_best_weights {
+                progress.best = Some((epoch, network.clone()));
             }
+        } else {
+            progress.patience += 1;
+            stop = progress.patience >= patience_limit;
         }
-        val_loss /= val_set.len() as f64;
-        let val_accuracy = (correct as f64 / val_set.len() as f64) * 100.0;
 
-        if val_loss < best_val_loss {
-            best_val_loss = val_loss;
-            patience = 0;
-        } else {
-            patience += 1;
-            if patience >= patience_limit {
-                println!(
-                    "Epoch {}: train_loss={:.6}, val_loss={:.6}, val_acc={:.2}%, lr={:.6}",
-                    epoch, train_loss, val_loss, val_accuracy, learning_rate
-                );
-                println!("Early stopping at epoch {} (patience reached)", epoch);
-                break;
-            }
+        println!("{}", summary);
+        if stop {
+            println!("Early stopping at epoch {} (patience reached)", epoch);
         }
 
-        println!(
-            "Epoch {}: train_loss={:.6}, val_loss={:.6}, val_acc={:.2}%, lr={:.6}",
-            epoch, train_loss, val_loss, val_accuracy, learning_rate
-        );
+        progress.epoch = epoch + 1;
+        // The last epoch of the run, early stop included, always gets one
+        let every = train_config.checkpoint_every;
+        let last = stop || progress.epoch == epochs;
+        if every > 0 && (last || progress.epoch.is_multiple_of(every)) {
+            progress.optimizer = optimizer.state();
+            progress.schedule = schedule.state();
+            checkpoint::save(checkpoint_path, train_config, network, &progress)?;
+            println!("  Checkpoint saved to '{}'", checkpoint_path);
+        }
+        if stop {
+            break;
+        }
     }
 
-    Ok(())
-}
+    let mut last_network = None;
+    if let Some((epoch, best_network)) = progress.best {
+        if epoch != last_epoch {
+            println!(
+                "\nRestoring the weights of epoch {} (best val_loss={:.6})",
+                epoch, progress.best_val_loss
+            );
+            last_network = Some(std::mem::replace(network, best_network));
+        }
+    }
+
+    println!("\nValidation recall per class:");
+    let validation = evaluate(network, val_set, loss.as_ref());
+    for (label, recall) in CLASS_LABELS.iter().zip(&validation.recalls) {
+        match recall {
+            Some(recall) => println!("  {}: {:.2}%", label, recall * 100.0),
+            None => println!("  {}: no example", label),
+        }
+    }
 
-fn softmax(outputs: &Vec<f64>) -> Vec<f64> {
-    let max = outputs.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-    let exp_values: Vec<f64> = outputs.iter().map(|&x| (x - max).exp()).collect();
-    let sum: f64 = exp_values.iter().sum();
-    exp_values.iter().map(|&x| x / sum).collect()
+    Ok(last_network)
 }
 
-fn calculate_cross_entropy(outputs: &Vec<f64>, targets: &Vec<f64>) -> f64 {
-    let epsilon = 1e-15;
+/// Loss and accuracies of a network on the validation set
+struct Evaluation {
+    loss: f64,
+    /// Percentage of right predictions
+    accuracy: f64,
+    /// Per-class recalls, see `class_recalls`
+    recalls: Vec<Option<f64>>,
+}
 
-    let softmax_outputs = softmax(outputs);
+impl Evaluation {
+    /// Mean recall of the classes with examples, in percent
+    fn balanced_accuracy(&self) -> f64 {
+        let present: Vec<f64> = self.recalls.iter().flatten().copied().collect();
+        present.iter().sum::<f64>() / present.len() as f64 * 100.0
+    }
+}
 
-    softmax_outputs
-        .iter()
-        .zip(targets.iter())
-        .map(|(o, t)| {
-            let o_clipped = o.clamp(epsilon, 1.0 - epsilon);
-            -t * o_clipped.ln()
-        })
-        .sum::<f64>()
+fn evaluate<T: Float>(
+    network: &Network<T>,
+    val_set: &Dataset<T>,
+    loss: &dyn Loss<T>,
+) -> Evaluation {
+    let mut total_loss = 0.0;
+    let mut correct = 0;
+    let mut class_correct = vec![0; CLASS_LABELS.len()];
+    for (inputs, targets) in val_set {
+        let outputs = network.exec(inputs.clone());
+        total_loss += loss.sample_value(&outputs, targets);
+
+        if are_predictions_equal(&outputs, targets) {
+            correct += 1;
+            class_correct[sampler::class_of(targets)] += 1;
+        }
+    }
+    Evaluation {
+        loss: total_loss / val_set.len() as f64,
+        accuracy: (correct as f64 / val_set.len() as f64) * 100.0,
+        recalls: class_recalls(
+            &class_correct,
+            &sampler::class_counts(val_set, CLASS_LABELS.len()),
+        ),
+    }
 }
 
-fn are_predictions_equal(outputs: &Vec<f64>, targets: &Vec<f64>) -> bool {
-    let predicted_class = outputs
-        .iter()
-        .enumerate()
-        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-        .map(|(idx, _)| idx)
-        .unwrap_or(0);
+/// The configured loss, wrapped with the `class_weights` if any (`auto`
+/// weights come from the class counts of `train_set`)
+pub fn create_weighted_loss<T: Float>(
+    train_set: &Dataset<T>,
+    train_config: &TrainingConfig,
+) -> Result<Box<dyn Loss<T>>, String> {
+    let loss = train_config.create_loss()?;
+    let weights = match &train_config.class_weights {
+        ClassWeights::None => return Ok(loss),
+        ClassWeights::Auto => {
+            let counts = sampler::class_counts(train_set, CLASS_LABELS.len());
+            println!("Class counts: {:?}", counts);
+            sampler::inverse_frequency_weights(&counts)
+        }
+        ClassWeights::Manual(weights) => weights.clone(),
+    };
+    println!("Class weights: {:?}", weights);
+    Ok(Box::new(ClassWeighted { loss, weights }))
+}
 
-    let target_class = targets
+/// Fraction of the examples of each class predicted right, `None` for
+/// classes without examples
+fn class_recalls(correct: &[usize], counts: &[usize]) -> Vec<Option<f64>> {
+    correct
         .iter()
-        .enumerate()
-        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-        .map(|(idx, _)| idx)
-        .unwrap_or(0);
+        .zip(counts)
+        .map(|(&correct, &count)| (count > 0).then(|| correct as f64 / count as f64))
+        .collect()
+}
 
-    predicted_class == target_class
+fn are_predictions_equal<T: Float>(outputs: &[T], targets: &[T]) -> bool {
+    sampler::class_of(outputs) == sampler::class_of(targets)
 }
@@ -16,10 +16,22 @@ fn main() {
         }
     };
 
+    if let Some(threads) = config.threads {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+        {
+            eprintln!("Error: cannot start {} threads: {}", threads, e);
+            std::process::exit(84);
+        }
+    }
+
     // Exécuter selon le mode
     let result = match config.mode {
         Mode::Predict => chess::predictor::run_predict(&config),
         Mode::Train => chess::trainer::run_train(&config),
+        Mode::Convert => chess::converter::run_convert(&config),
+        Mode::CheckGradients => chess::diagnostics::run_check_gradients(&config),
     };
 
     // Gérer les erreurs
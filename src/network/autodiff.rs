@@ -0,0 +1,611 @@
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::matrix::{axpy, Matrix};
+use crate::network::datastruct::r#trait::{cache_ref, Cache, NetworkElement};
+use crate::network::float::Float;
+
+/// Handle to a value recorded on a `Tape`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Var(usize);
+
+/// Operation that produced a value, with its operands.
+///
+/// The right operand of `Add`, `Sub` and `Mul` is broadcast: it has the
+/// shape of the left one, or a single row (`1×C`), a single column (`R×1`)
+/// or a single value.
+enum Op {
+    Leaf,
+    Add(Var, Var),
+    Sub(Var, Var),
+    Mul(Var, Var),
+    /// `a · wᵀ`, a batch times dense weights (one neuron per row)
+    MulTransposed(Var, Var),
+    Scale(Var, f64),
+    AddScalar(Var),
+    Powf(Var, f64),
+    /// `max(a, floor)`
+    ClampMin(Var, f64),
+    Exp(Var),
+    Activation(Var, Activation),
+    /// Mean of every row, `R×1`
+    RowMean(Var),
+    /// Sum of every row, `R×1`
+    RowSum(Var),
+    /// Mean of every column, `1×C`
+    ColumnMean(Var),
+    /// Sum of every value, `1×1`
+    Sum(Var),
+    LogSoftmax(Var),
+    /// Outputs of a whole network element, its cache kept in
+    /// `Tape::element_caches`
+    Element(Var, usize),
+}
+
+struct Node<T> {
+    value: Matrix<T>,
+    op: Op,
+}
+
+/// Reverse-mode automatic differentiation.
+///
+/// Every operation computes its value right away and records how it was
+/// obtained; `backward` then walks the tape from the end and applies the
+/// chain rule, so a computation written with these ops (a layer, a loss)
+/// gets its gradients without a hand-written backward pass.
+pub struct Tape<T = f64> {
+    nodes: Vec<Node<T>>,
+    element_caches: Vec<Cache>,
+}
+
+impl<T> Default for Tape<T> {
+    fn default() -> Self {
+        Tape {
+            nodes: vec![],
+            element_caches: vec![],
+        }
+    }
+}
+
+/// dL/d value of the tape's variables, for those the output depends on,
+/// and the parameter gradients of every recorded element
+pub struct TapeGrads<T = f64> {
+    grads: Vec<Option<Matrix<T>>>,
+    elements: Vec<Vec<Vec<T>>>,
+}
+
+impl<T> TapeGrads<T> {
+    pub fn take(&mut self, var: Var) -> Option<Matrix<T>> {
+        self.grads[var.0].take()
+    }
+}
+
+/// Index of the broadcast operand `b` matching position (r, c) of the result
+fn broadcast_index<T>(b: &Matrix<T>, r: usize, c: usize) -> usize {
+    let r = if b.rows == 1 { 0 } else { r };
+    let c = if b.cols == 1 { 0 } else { c };
+    r * b.cols + c
+}
+
+fn log_softmax<T: Float>(row: &[T]) -> Vec<T> {
+    let max = row.iter().fold(T::neg_infinity(), |a, &b| a.max(b));
+    let log_sum = row.iter().map(|&x| (x - max).exp()).sum::<T>().ln() + max;
+    row.iter().map(|&x| x - log_sum).collect()
+}
+
+impl<T: Float> Tape<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, value: Matrix<T>, op: Op) -> Var {
+        self.nodes.push(Node { value, op });
+        Var(self.nodes.len() - 1)
+    }
+
+    /// Input or parameter of the computation
+    pub fn leaf(&mut self, value: Matrix<T>) -> Var {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn value(&self, var: Var) -> &Matrix<T> {
+        &self.nodes[var.0].value
+    }
+
+    fn map(&mut self, a: Var, op: Op, f: impl Fn(T) -> T) -> Var {
+        let a = self.value(a);
+        let value = Matrix::new(a.rows, a.cols, a.data.iter().map(|&x| f(x)).collect());
+        self.push(value, op)
+    }
+
+    fn broadcast(&mut self, a: Var, b: Var, op: Op, f: impl Fn(T, T) -> T) -> Var {
+        let (x, y) = (self.value(a), self.value(b));
+        assert!(
+            (y.rows == x.rows || y.rows == 1) && (y.cols == x.cols || y.cols == 1),
+            "cannot broadcast a {}x{} operand over {}x{}",
+            y.rows,
+            y.cols,
+            x.rows,
+            x.cols
+        );
+        let mut value = Matrix::zeros(x.rows, x.cols);
+        for r in 0..x.rows {
+            for (c, out) in value.row_mut(r).iter_mut().enumerate() {
+                *out = f(x.data[r * x.cols + c], y.data[broadcast_index(y, r, c)]);
+            }
+        }
+        self.push(value, op)
+    }
+
+    pub fn add(&mut self, a: Var, b: Var) -> Var {
+        self.broadcast(a, b, Op::Add(a, b), |x, y| x + y)
+    }
+
+    pub fn sub(&mut self, a: Var, b: Var) -> Var {
+        self.broadcast(a, b, Op::Sub(a, b), |x, y| x - y)
+    }
+
+    /// Element-wise product
+    pub fn mul(&mut self, a: Var, b: Var) -> Var {
+        self.broadcast(a, b, Op::Mul(a, b), |x, y| x * y)
+    }
+
+    pub fn mul_transposed(&mut self, a: Var, w: Var) -> Var {
+        let value = self.value(a).mul_transposed(self.value(w));
+        self.push(value, Op::MulTransposed(a, w))
+    }
+
+    pub fn scale(&mut self, a: Var, factor: f64) -> Var {
+        let scale = T::cast(factor);
+        self.map(a, Op::Scale(a, factor), |x| x * scale)
+    }
+
+    pub fn add_scalar(&mut self, a: Var, value: f64) -> Var {
+        let value = T::cast(value);
+        self.map(a, Op::AddScalar(a), |x| x + value)
+    }
+
+    pub fn powf(&mut self, a: Var, exponent: f64) -> Var {
+        let power = T::cast(exponent);
+        self.map(a, Op::Powf(a, exponent), |x| x.powf(power))
+    }
+
+    pub fn clamp_min(&mut self, a: Var, floor: f64) -> Var {
+        let floor_value = T::cast(floor);
+        self.map(a, Op::ClampMin(a, floor), |x| x.max(floor_value))
+    }
+
+    pub fn exp(&mut self, a: Var) -> Var {
+        self.map(a, Op::Exp(a), T::exp)
+    }
+
+    pub fn activation(&mut self, a: Var, activation: Activation) -> Var {
+        self.map(a, Op::Activation(a, activation), |x| activation.apply(x))
+    }
+
+    pub fn row_mean(&mut self, a: Var) -> Var {
+        let x = self.value(a);
+        let means = (0..x.rows)
+            .map(|r| x.row(r).iter().copied().sum::<T>() / T::cast(x.cols as f64))
+            .collect();
+        let value = Matrix::new(x.rows, 1, means);
+        self.push(value, Op::RowMean(a))
+    }
+
+    pub fn row_sum(&mut self, a: Var) -> Var {
+        let x = self.value(a);
+        let sums = (0..x.rows)
+            .map(|r| x.row(r).iter().copied().sum())
+            .collect();
+        let value = Matrix::new(x.rows, 1, sums);
+        self.push(value, Op::RowSum(a))
+    }
+
+    pub fn column_mean(&mut self, a: Var) -> Var {
+        let x = self.value(a);
+        let count = T::cast(x.rows as f64);
+        let means = x.column_sums().into_iter().map(|s| s / count).collect();
+        let value = Matrix::new(1, x.cols, means);
+        self.push(value, Op::ColumnMean(a))
+    }
+
+    pub fn sum(&mut self, a: Var) -> Var {
+        let value = Matrix::new(1, 1, vec![self.value(a).data.iter().copied().sum()]);
+        self.push(value, Op::Sum(a))
+    }
+
+    /// Row-wise `log(softmax(a))`
+    pub fn log_softmax(&mut self, a: Var) -> Var {
+        let x = self.value(a);
+        let mut value = Matrix::zeros(x.rows, x.cols);
+        for r in 0..x.rows {
+            value.row_mut(r).copy_from_slice(&log_softmax(x.row(r)));
+        }
+        self.push(value, Op::LogSoftmax(a))
+    }
+
+    /// Outputs of `element` for `a`. The element keeps its own backward
+    /// pass: `backward_elements` calls it, given the same elements in the
+    /// same order.
+    pub fn element(&mut self, a: Var, element: &dyn NetworkElement<T>) -> Var {
+        let (value, cache) = element.forward(self.value(a));
+        self.element_caches.push(cache);
+        self.push(value, Op::Element(a, self.element_caches.len() - 1))
+    }
+
+    /// Gradients of every variable given `seed` = dL/d `output` (a 1×1
+    /// loss is seeded with 1)
+    pub fn backward(&self, output: Var, seed: Matrix<T>) -> TapeGrads<T> {
+        self.backward_elements(output, seed, &[])
+    }
+
+    /// `backward` for a tape holding `Tape::element` ops, `elements` being
+    /// the recorded elements
+    pub fn backward_elements(
+        &self,
+        output: Var,
+        seed: Matrix<T>,
+        elements: &[&dyn NetworkElement<T>],
+    ) -> TapeGrads<T> {
+        assert_eq!(
+            elements.len(),
+            self.element_caches.len(),
+            "the tape recorded {} elements, got {}",
+            self.element_caches.len(),
+            elements.len()
+        );
+        let mut grads: Vec<Option<Matrix<T>>> = (0..self.nodes.len()).map(|_| None).collect();
+        grads[output.0] = Some(seed);
+        let mut element_grads: Vec<Vec<Vec<T>>> = elements
+            .iter()
+            .map(|element| {
+                element
+                    .parameters()
+                    .iter()
+                    .map(|(_, values)| vec![T::zero(); values.len()])
+                    .collect()
+            })
+            .collect();
+
+        for index in (0..=output.0).rev() {
+            let Some(grad) = grads[index].take() else {
+                continue;
+            };
+            let node = &self.nodes[index];
+            let contributions = match node.op {
+                Op::Element(a, element) => {
+                    let (input_grads, param_grads) = elements[element].backward(
+                        self.value(a),
+                        &self.element_caches[element],
+                        &grad,
+                        true,
+                    );
+                    element_grads[element] = param_grads;
+                    input_grads.map(|g| vec![(a, g)]).unwrap_or_default()
+                }
+                _ => self.local_grads(node, &grad),
+            };
+            for (var, contribution) in contributions {
+                match &mut grads[var.0] {
+                    Some(existing) => axpy(&mut existing.data, T::one(), &contribution.data),
+                    slot => *slot = Some(contribution),
+                }
+            }
+            grads[index] = Some(grad);
+        }
+        TapeGrads {
+            grads,
+            elements: element_grads,
+        }
+    }
+
+    /// Contributions of dL/d `node` to the gradients of its operands
+    fn local_grads(&self, node: &Node<T>, grad: &Matrix<T>) -> Vec<(Var, Matrix<T>)> {
+        let same_shape = |f: &dyn Fn(usize) -> T| {
+            Matrix::new(grad.rows, grad.cols, (0..grad.data.len()).map(f).collect())
+        };
+        match node.op {
+            Op::Leaf => vec![],
+            Op::Add(a, b) | Op::Sub(a, b) => {
+                let sign = if matches!(node.op, Op::Add(..)) {
+                    T::one()
+                } else {
+                    -T::one()
+                };
+                let mut grad_b = Matrix::zeros(self.value(b).rows, self.value(b).cols);
+                Self::reduce_into(&mut grad_b, grad, |_, g| sign * g);
+                vec![(a, grad.clone()), (b, grad_b)]
+            }
+            Op::Mul(a, b) => {
+                let (x, y) = (self.value(a), self.value(b));
+                let grad_a = same_shape(&|i| {
+                    grad.data[i] * y.data[broadcast_index(y, i / grad.cols, i % grad.cols)]
+                });
+                let mut grad_b = Matrix::zeros(y.rows, y.cols);
+                Self::reduce_into(&mut grad_b, grad, |i, g| g * x.data[i]);
+                vec![(a, grad_a), (b, grad_b)]
+            }
+            Op::MulTransposed(a, w) => vec![
+                (a, grad.mul(self.value(w))),
+                (w, grad.transpose_mul(self.value(a))),
+            ],
+            Op::Scale(a, factor) => {
+                let factor = T::cast(factor);
+                vec![(a, same_shape(&|i| grad.data[i] * factor))]
+            }
+            Op::AddScalar(a) => vec![(a, grad.clone())],
+            Op::Powf(a, exponent) => {
+                let x = self.value(a);
+                let (power, lowered) = (T::cast(exponent), T::cast(exponent - 1.0));
+                vec![(
+                    a,
+                    same_shape(&|i| grad.data[i] * power * x.data[i].powf(lowered)),
+                )]
+            }
+            Op::ClampMin(a, floor) => {
+                let (x, floor) = (self.value(a), T::cast(floor));
+                vec![(
+                    a,
+                    same_shape(&|i| {
+                        if x.data[i] > floor {
+                            grad.data[i]
+                        } else {
+                            T::zero()
+                        }
+                    }),
+                )]
+            }
+            Op::Exp(a) => vec![(a, same_shape(&|i| grad.data[i] * node.value.data[i]))],
+            Op::Activation(a, activation) => {
+                let x = self.value(a);
+                vec![(
+                    a,
+                    same_shape(&|i| {
+                        grad.data[i] * activation.derivative(x.data[i], node.value.data[i])
+                    }),
+                )]
+            }
+            Op::RowMean(a) | Op::RowSum(a) => {
+                let x = self.value(a);
+                let scale = match node.op {
+                    Op::RowMean(_) => T::one() / T::cast(x.cols as f64),
+                    _ => T::one(),
+                };
+                let grad_a = Matrix::new(
+                    x.rows,
+                    x.cols,
+                    (0..x.data.len())
+                        .map(|i| grad.data[i / x.cols] * scale)
+                        .collect(),
+                );
+                vec![(a, grad_a)]
+            }
+            Op::ColumnMean(a) => {
+                let x = self.value(a);
+                let scale = T::one() / T::cast(x.rows as f64);
+                let grad_a = Matrix::new(
+                    x.rows,
+                    x.cols,
+                    (0..x.data.len())
+                        .map(|i| grad.data[i % x.cols] * scale)
+                        .collect(),
+                );
+                vec![(a, grad_a)]
+            }
+            Op::Sum(a) => {
+                let x = self.value(a);
+                vec![(
+                    a,
+                    Matrix::new(x.rows, x.cols, vec![grad.data[0]; x.data.len()]),
+                )]
+            }
+            Op::LogSoftmax(a) => {
+                // dx = g - softmax(x) · Σ g, row by row
+                let mut grad_a = grad.clone();
+                for r in 0..grad.rows {
+                    let total: T = grad.row(r).iter().copied().sum();
+                    for (g, log_p) in grad_a.row_mut(r).iter_mut().zip(node.value.row(r)) {
+                        *g -= log_p.exp() * total;
+                    }
+                }
+                vec![(a, grad_a)]
+            }
+            Op::Element(..) => unreachable!("elements are run by backward_elements"),
+        }
+    }
+
+    /// Sum `f(index, grad)` of the result into the broadcast operand's
+    /// gradient
+    fn reduce_into(target: &mut Matrix<T>, grad: &Matrix<T>, f: impl Fn(usize, T) -> T) {
+        for r in 0..grad.rows {
+            for c in 0..grad.cols {
+                let i = r * grad.cols + c;
+                let j = broadcast_index(target, r, c);
+                target.data[j] += f(i, grad.data[i]);
+            }
+        }
+    }
+}
+
+/// Network element whose forward pass is written with tape ops. Its
+/// `NetworkElement` impl delegates to `forward` and `backward` below and
+/// never writes the backward pass by hand.
+///
+/// Layer normalization, batch normalization, residual blocks and the
+/// losses are written this way; a residual block records its body with
+/// `Tape::element`, so any layer fits inside. Dense, sparse and
+/// convolution layers keep hand-written passes: recording them would copy
+/// their weights onto the tape at every forward pass, single positions at
+/// inference included. The encoder keeps its own as well;
+/// `Network::backward` chains all of them once for every loss.
+pub trait Differentiable<T: Float = f64> {
+    /// Record the outputs for `inputs`, returning them with the parameter
+    /// leaves. The leaves come first in `parameters()` order, then the
+    /// parameters of `elements()`.
+    fn record(&self, tape: &mut Tape<T>, inputs: Var) -> (Var, Vec<Var>);
+
+    /// Elements recorded with `Tape::element`, in recording order
+    fn elements(&self) -> Vec<&dyn NetworkElement<T>> {
+        vec![]
+    }
+}
+
+/// The forward tape of a `Differentiable` element
+struct TapeCache<T> {
+    tape: Tape<T>,
+    inputs: Var,
+    outputs: Var,
+    parameters: Vec<Var>,
+}
+
+pub fn forward<T: Float>(
+    element: &dyn Differentiable<T>,
+    inputs: &Matrix<T>,
+) -> (Matrix<T>, Cache) {
+    let mut tape = Tape::new();
+    let input_var = tape.leaf(inputs.clone());
+    let (outputs, parameters) = element.record(&mut tape, input_var);
+    (
+        tape.value(outputs).clone(),
+        Box::new(TapeCache {
+            tape,
+            inputs: input_var,
+            outputs,
+            parameters,
+        }),
+    )
+}
+
+/// `NetworkElement::backward` from the tape recorded by `forward`
+pub fn backward<T: Float>(
+    element: &dyn Differentiable<T>,
+    cache: &Cache,
+    output_grads: &Matrix<T>,
+    need_input_grads: bool,
+) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+    let cache: &TapeCache<T> = cache_ref(cache);
+    let mut grads =
+        cache
+            .tape
+            .backward_elements(cache.outputs, output_grads.clone(), &element.elements());
+    let mut take = |var: Var| {
+        grads.take(var).unwrap_or_else(|| {
+            let value = cache.tape.value(var);
+            Matrix::zeros(value.rows, value.cols)
+        })
+    };
+    let mut param_grads: Vec<Vec<T>> = cache.parameters.iter().map(|&p| take(p).data).collect();
+    let input_grads = need_input_grads.then(|| take(cache.inputs));
+    param_grads.extend(grads.elements.into_iter().flatten());
+    (input_grads, param_grads)
+}
+
+/// Inputs given to `forward`
+pub fn inputs<T: Float>(cache: &Cache) -> &Matrix<T> {
+    let cache: &TapeCache<T> = cache_ref(cache);
+    cache.tape.value(cache.inputs)
+}
+
+/// Caches of the elements recorded with `Tape::element`, in recording
+/// order
+pub fn element_caches<T: Float>(cache: &Cache) -> &[Cache] {
+    let cache: &TapeCache<T> = cache_ref(cache);
+    &cache.tape.element_caches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compare the tape gradient of every leaf value against central
+    /// differences of `f`
+    fn check(leaves: &[Matrix], f: impl Fn(&mut Tape, &[Var]) -> Var) {
+        let loss = |values: &[Matrix]| -> f64 {
+            let mut tape = Tape::new();
+            let vars: Vec<Var> = values.iter().map(|v| tape.leaf(v.clone())).collect();
+            let output = f(&mut tape, &vars);
+            tape.value(output).data[0]
+        };
+
+        let mut tape = Tape::new();
+        let vars: Vec<Var> = leaves.iter().map(|v| tape.leaf(v.clone())).collect();
+        let output = f(&mut tape, &vars);
+        let mut grads = tape.backward(output, Matrix::new(1, 1, vec![1.0]));
+
+        let h = 1e-6;
+        for (l, &var) in vars.iter().enumerate() {
+            let grad = grads.take(var).unwrap();
+            for i in 0..leaves[l].data.len() {
+                let mut plus = leaves.to_vec();
+                plus[l].data[i] += h;
+                let mut minus = leaves.to_vec();
+                minus[l].data[i] -= h;
+                let numeric = (loss(&plus) - loss(&minus)) / (2.0 * h);
+                assert!(
+                    (numeric - grad.data[i]).abs() < 1e-5,
+                    "leaf {} value {}: tape {} numeric {}",
+                    l,
+                    i,
+                    grad.data[i],
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_ops_match_finite_differences() {
+        let x = Matrix::new(2, 3, vec![0.3, -1.2, 0.7, 1.0, 0.5, -0.4]);
+        let w = Matrix::new(2, 3, vec![0.5, 0.1, -0.3, 0.2, -0.7, 0.9]);
+        let bias = Matrix::new(1, 2, vec![0.1, -0.2]);
+        let gains = Matrix::new(1, 3, vec![1.5, -0.5, 2.0]);
+        let targets = Matrix::new(2, 2, vec![1.0, 0.0, 0.0, 1.0]);
+
+        // Dense layer with a softmax cross-entropy
+        check(&[x.clone(), w.clone(), bias], |tape, v| {
+            let z = tape.mul_transposed(v[0], v[1]);
+            let z = tape.add(z, v[2]);
+            let y = tape.activation(z, Activation::Gelu);
+            let t = tape.leaf(targets.clone());
+            let log_probs = tape.log_softmax(y);
+            let picked = tape.mul(log_probs, t);
+            let total = tape.sum(picked);
+            tape.scale(total, -1.0)
+        });
+
+        // Column broadcasts, powers and clamps
+        check(&[x.clone(), gains], |tape, v| {
+            let mean = tape.row_mean(v[0]);
+            let centered = tape.sub(v[0], mean);
+            let squares = tape.mul(centered, centered);
+            let var = tape.row_mean(squares);
+            let shifted = tape.add_scalar(var, 1.0);
+            let inv_std = tape.powf(shifted, -0.5);
+            let normalized = tape.mul(centered, inv_std);
+            let normalized = tape.clamp_min(normalized, 0.0);
+            let weighted = tape.mul(normalized, v[1]);
+            let column_means = tape.column_mean(weighted);
+            let weighted = tape.sub(weighted, column_means);
+            let scaled = tape.scale(weighted, 0.3);
+            let exps = tape.exp(shifted);
+            let total = tape.add(scaled, exps);
+            let rows = tape.row_sum(total);
+            tape.sum(rows)
+        });
+
+        check(&[x, w], |tape, v| {
+            let errors = tape.sub(v[0], v[1]);
+            let squares = tape.mul(errors, errors);
+            tape.sum(squares)
+        });
+    }
+
+    #[test]
+    fn test_shared_operand_accumulates() {
+        let mut tape = Tape::new();
+        let x = tape.leaf(Matrix::new(1, 2, vec![3.0, -2.0]));
+        let squares = tape.mul(x, x);
+        let doubled = tape.add(squares, x);
+        let total = tape.sum(doubled);
+        let mut grads = tape.backward(total, Matrix::new(1, 1, vec![1.0]));
+        assert_eq!(grads.take(x).unwrap().data, vec![7.0, -3.0]);
+    }
+}
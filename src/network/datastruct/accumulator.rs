@@ -0,0 +1,157 @@
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::network::Network;
+use crate::network::datastruct::r#trait::LinearInput;
+use crate::network::float::Float;
+
+/// Active features switched off and on between two positions: for a move,
+/// the piece leaving its square and the one landing on it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureDelta {
+    pub removed: Vec<usize>,
+    pub added: Vec<usize>,
+}
+
+impl FeatureDelta {
+    pub fn with_removed(mut self, feature: usize) -> Self {
+        self.removed.push(feature);
+        self
+    }
+
+    pub fn with_added(mut self, feature: usize) -> Self {
+        self.added.push(feature);
+        self
+    }
+
+    /// Features switched off or on
+    pub fn nb_changes(&self) -> usize {
+        self.removed.len() + self.added.len()
+    }
+}
+
+/// Incremental evaluator for one-hot inputs (NNUE accumulator).
+///
+/// Keeps the first-layer pre-activations of the current position. A move
+/// only changes a handful of features, so `update` adds and subtracts the
+/// weights of those features instead of recomputing the whole first layer,
+/// and `evaluate` runs the rest of the network from the kept sums.
+pub struct Accumulator<'a, T: Float = f64> {
+    network: &'a Network<T>,
+    first: &'a dyn LinearInput<T>,
+    sums: Vec<T>,
+}
+
+impl<'a, T: Float> Accumulator<'a, T> {
+    /// Accumulator for the position whose active features are `features`;
+    /// the first layer must be a dense or sparse_dense layer
+    pub fn new(network: &'a Network<T>, features: &[usize]) -> Result<Self, String> {
+        let layer = network.layers.first().ok_or("empty network")?;
+        let first = layer.as_linear_input().ok_or_else(|| {
+            format!(
+                "cannot accumulate a {} first layer, it must be dense or sparse_dense",
+                layer.kind()
+            )
+        })?;
+        let mut accumulator = Accumulator {
+            network,
+            first,
+            sums: vec![],
+        };
+        accumulator.refresh(features);
+        Ok(accumulator)
+    }
+
+    /// Recompute the sums from scratch
+    pub fn refresh(&mut self, features: &[usize]) {
+        self.sums = self.first.biases().to_vec();
+        for &feature in features {
+            self.first.add_input(&mut self.sums, feature, T::one());
+        }
+    }
+
+    /// Move to a position differing by `delta`
+    pub fn update(&mut self, delta: &FeatureDelta) {
+        for &feature in &delta.removed {
+            self.first.add_input(&mut self.sums, feature, -T::one());
+        }
+        for &feature in &delta.added {
+            self.first.add_input(&mut self.sums, feature, T::one());
+        }
+    }
+
+    /// Network outputs for the current position
+    pub fn evaluate(&self) -> Vec<T> {
+        let activation = self.first.activation();
+        let hidden = self.sums.iter().map(|&z| activation.apply(z)).collect();
+        let mut current = Matrix::new(1, self.sums.len(), hidden);
+        for layer in &self.network.layers[1..] {
+            current = layer.forward(&current).0;
+        }
+        current.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::activation::Activation;
+    use crate::network::datastruct::layer::Layer;
+    use crate::network::datastruct::layer_norm::LayerNorm;
+    use crate::network::datastruct::metadata::Metadata;
+    use crate::network::datastruct::r#trait::NetworkElement;
+    use crate::network::datastruct::sparse::SparseDense;
+
+    fn one_hot(features: &[usize], size: usize) -> Vec<f64> {
+        let mut inputs = vec![0.0; size];
+        for &feature in features {
+            inputs[feature] = 1.0;
+        }
+        inputs
+    }
+
+    #[test]
+    fn test_updates_match_full_evaluation() {
+        for sparse in [false, true] {
+            let first = Layer::new_random_he(6, 10, Activation::Relu);
+            let first: Box<dyn NetworkElement> = if sparse {
+                Box::new(SparseDense::from_dense(&first))
+            } else {
+                Box::new(first)
+            };
+            let network = Network {
+                layers: vec![
+                    first,
+                    Box::new(LayerNorm::new(6)),
+                    Box::new(Layer::new_random_he(3, 6, Activation::Linear)),
+                ],
+                metadata: Metadata::default(),
+            };
+
+            let mut features = vec![0, 4, 7];
+            let mut accumulator = Accumulator::new(&network, &features).unwrap();
+            let moves = [
+                FeatureDelta::default().with_removed(4).with_added(5),
+                FeatureDelta::default()
+                    .with_removed(0)
+                    .with_removed(7)
+                    .with_added(9),
+                FeatureDelta::default().with_added(1),
+            ];
+            for delta in &moves {
+                accumulator.update(delta);
+                features.retain(|f| !delta.removed.contains(f));
+                features.extend(&delta.added);
+
+                let expected = network.exec(one_hot(&features, 10));
+                for (a, b) in accumulator.evaluate().iter().zip(&expected) {
+                    assert!((a - b).abs() < 1e-12);
+                }
+            }
+        }
+
+        let network = Network {
+            layers: vec![Box::new(LayerNorm::<f64>::new(4))],
+            metadata: Metadata::default(),
+        };
+        assert!(Accumulator::new(&network, &[0]).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use crate::network::float::Float;
+use std::fmt;
+use std::str::FromStr;
+
+const SELU_LAMBDA: f64 = 1.050_700_987_355_480_5;
+const SELU_ALPHA: f64 = 1.673_263_242_354_377_2;
+/// sqrt(2 / π), for the tanh approximation of GELU
+const GELU_K: f64 = 0.797_884_560_802_865_4;
+const GELU_C: f64 = 0.044_715;
+
+pub const DEFAULT_LEAKY_RELU_ALPHA: f64 = 0.01;
+pub const DEFAULT_ELU_ALPHA: f64 = 1.0;
+
+/// Activation function of a layer, paired with its derivative.
+///
+/// Written in files by name, parameterised variants as `name(value)`, e.g.
+/// `leaky_relu(0.01)`. Unknown names are an error rather than a silent
+/// fallback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation {
+    Sigmoid,
+    Relu,
+    LeakyRelu(f64),
+    Tanh,
+    Linear,
+    Elu(f64),
+    Selu,
+    /// Tanh approximation
+    Gelu,
+    /// Also known as SiLU: `x * sigmoid(x)`
+    Swish,
+    Softplus,
+    Mish,
+    /// Clamp to [-1, 1]
+    HardTanh,
+}
+
+impl Activation {
+    /// Names accepted by `from_name`, for error and help messages
+    pub const NAMES: &'static str =
+        "sigmoid, relu, leaky_relu[(alpha)], tanh, linear, elu[(alpha)], selu, gelu, swish, softplus, mish, hard_tanh";
+
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        let name = name.trim().to_lowercase();
+        let (base, parameter) = match name.split_once('(') {
+            Some((base, rest)) => {
+                let value = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("missing ')' in activation '{}'", name))?;
+                let value: f64 = value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|v: &f64| v.is_finite())
+                    .ok_or_else(|| format!("invalid parameter in activation '{}'", name))?;
+                (base.trim(), Some(value))
+            }
+            None => (name.as_str(), None),
+        };
+
+        let activation = match (base, parameter) {
+            ("sigmoid", None) => Activation::Sigmoid,
+            ("relu", None) => Activation::Relu,
+            ("leaky_relu" | "leakyrelu", alpha) => {
+                Activation::LeakyRelu(alpha.unwrap_or(DEFAULT_LEAKY_RELU_ALPHA))
+            }
+            ("tanh", None) => Activation::Tanh,
+            ("linear", None) => Activation::Linear,
+            ("elu", alpha) => Activation::Elu(alpha.unwrap_or(DEFAULT_ELU_ALPHA)),
+            ("selu", None) => Activation::Selu,
+            ("gelu", None) => Activation::Gelu,
+            ("swish" | "silu", None) => Activation::Swish,
+            ("softplus", None) => Activation::Softplus,
+            ("mish", None) => Activation::Mish,
+            ("hard_tanh" | "hardtanh", None) => Activation::HardTanh,
+            (_, Some(_)) if Self::from_name(base).is_ok() => {
+                return Err(format!("activation '{}' takes no parameter", base))
+            }
+            _ => {
+                return Err(format!(
+                    "unknown activation '{}' (expected one of: {})",
+                    name,
+                    Self::NAMES
+                ))
+            }
+        };
+        Ok(activation)
+    }
+
+    pub fn apply<T: Float>(self, x: T) -> T {
+        let zero = T::zero();
+        let one = T::one();
+        match self {
+            Activation::Sigmoid => sigmoid(x),
+            Activation::Relu => x.max(zero),
+            Activation::LeakyRelu(alpha) => {
+                if x > zero {
+                    x
+                } else {
+                    T::cast(alpha) * x
+                }
+            }
+            Activation::Tanh => x.tanh(),
+            Activation::Linear => x,
+            Activation::Elu(alpha) => {
+                if x > zero {
+                    x
+                } else {
+                    T::cast(alpha) * x.exp_m1()
+                }
+            }
+            Activation::Selu => {
+                if x > zero {
+                    T::cast(SELU_LAMBDA) * x
+                } else {
+                    T::cast(SELU_LAMBDA * SELU_ALPHA) * x.exp_m1()
+                }
+            }
+            Activation::Gelu => {
+                let inner = T::cast(GELU_K) * (x + T::cast(GELU_C) * x * x * x);
+                T::cast(0.5) * x * (one + inner.tanh())
+            }
+            Activation::Swish => x * sigmoid(x),
+            Activation::Softplus => softplus(x),
+            Activation::Mish => x * softplus(x).tanh(),
+            Activation::HardTanh => x.max(-one).min(one),
+        }
+    }
+
+    /// dy/dx at pre-activation `x`, where `y = self.apply(x)` is passed in
+    /// so functions defined through their output don't recompute it.
+    pub fn derivative<T: Float>(self, x: T, y: T) -> T {
+        let zero = T::zero();
+        let one = T::one();
+        match self {
+            Activation::Sigmoid => y * (one - y),
+            Activation::Relu => {
+                if x > zero {
+                    one
+                } else {
+                    zero
+                }
+            }
+            Activation::LeakyRelu(alpha) => {
+                if x > zero {
+                    one
+                } else {
+                    T::cast(alpha)
+                }
+            }
+            Activation::Tanh => one - y * y,
+            Activation::Linear => one,
+            Activation::Elu(alpha) => {
+                if x > zero {
+                    one
+                } else {
+                    y + T::cast(alpha)
+                }
+            }
+            Activation::Selu => {
+                if x > zero {
+                    T::cast(SELU_LAMBDA)
+                } else {
+                    y + T::cast(SELU_LAMBDA * SELU_ALPHA)
+                }
+            }
+            Activation::Gelu => {
+                let (k, c, half) = (T::cast(GELU_K), T::cast(GELU_C), T::cast(0.5));
+                let t = (k * (x + c * x * x * x)).tanh();
+                half * (one + t) + half * x * (one - t * t) * k * (one + T::cast(3.0) * c * x * x)
+            }
+            Activation::Swish => {
+                let s = sigmoid(x);
+                y + s * (one - y)
+            }
+            Activation::Softplus => sigmoid(x),
+            Activation::Mish => {
+                let t = softplus(x).tanh();
+                t + x * (one - t * t) * sigmoid(x)
+            }
+            Activation::HardTanh => {
+                if x >= -one && x <= one {
+                    one
+                } else {
+                    zero
+                }
+            }
+        }
+    }
+
+    /// Variance gain for He/LeCun initialisation: 2 for rectifier-like
+    /// functions, 1 otherwise.
+    pub fn init_gain(self) -> f64 {
+        match self {
+            Activation::Relu
+            | Activation::Elu(_)
+            | Activation::Gelu
+            | Activation::Swish
+            | Activation::Mish => 2.0,
+            Activation::LeakyRelu(alpha) => 2.0 / (1.0 + alpha * alpha),
+            _ => 1.0,
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Activation::Sigmoid => write!(f, "sigmoid"),
+            Activation::Relu => write!(f, "relu"),
+            Activation::LeakyRelu(alpha) => write!(f, "leaky_relu({})", alpha),
+            Activation::Tanh => write!(f, "tanh"),
+            Activation::Linear => write!(f, "linear"),
+            Activation::Elu(alpha) => write!(f, "elu({})", alpha),
+            Activation::Selu => write!(f, "selu"),
+            Activation::Gelu => write!(f, "gelu"),
+            Activation::Swish => write!(f, "swish"),
+            Activation::Softplus => write!(f, "softplus"),
+            Activation::Mish => write!(f, "mish"),
+            Activation::HardTanh => write!(f, "hard_tanh"),
+        }
+    }
+}
+
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        Self::from_name(name)
+    }
+}
+
+fn sigmoid<T: Float>(x: T) -> T {
+    T::one() / (T::one() + (-x).exp())
+}
+
+/// `ln(1 + e^x)` without overflowing for large `x`
+fn softplus<T: Float>(x: T) -> T {
+    x.max(T::zero()) + (-x.abs()).exp().ln_1p()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Activation; 12] = [
+        Activation::Sigmoid,
+        Activation::Relu,
+        Activation::LeakyRelu(0.1),
+        Activation::Tanh,
+        Activation::Linear,
+        Activation::Elu(0.5),
+        Activation::Selu,
+        Activation::Gelu,
+        Activation::Swish,
+        Activation::Softplus,
+        Activation::Mish,
+        Activation::HardTanh,
+    ];
+
+    #[test]
+    fn test_derivatives_match_finite_differences() {
+        let h = 1e-6;
+        for activation in ALL {
+            for &x in &[-3.0f64, -1.5, -0.3, 0.2, 0.7, 2.5] {
+                let numeric = (activation.apply(x + h) - activation.apply(x - h)) / (2.0 * h);
+                let analytic = activation.derivative(x, activation.apply(x));
+                assert!(
+                    (numeric - analytic).abs() < 1e-5,
+                    "{} at {}: {} vs {}",
+                    activation,
+                    x,
+                    analytic,
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        for activation in ALL {
+            assert_eq!(
+                Activation::from_name(&activation.to_string()),
+                Ok(activation)
+            );
+        }
+        assert_eq!(
+            Activation::from_name("leaky_relu"),
+            Ok(Activation::LeakyRelu(DEFAULT_LEAKY_RELU_ALPHA))
+        );
+        assert_eq!(Activation::from_name("SiLU"), Ok(Activation::Swish));
+        assert!(Activation::from_name("sigmoide").is_err());
+        assert!(Activation::from_name("relu(0.1)").is_err());
+        assert!(Activation::from_name("elu(x)").is_err());
+    }
+}
@@ -0,0 +1,656 @@
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::batch_norm::BatchNorm;
+use crate::network::datastruct::conv::{Conv2D, Flatten};
+use crate::network::datastruct::dropout::Dropout;
+use crate::network::datastruct::layer::Layer;
+use crate::network::datastruct::layer_norm::LayerNorm;
+use crate::network::datastruct::r#trait::NetworkElement;
+use crate::network::datastruct::residual::Residual;
+use crate::network::datastruct::sparse::SparseDense;
+use crate::network::datastruct::transformer::{Embedding, Encoder, Pool};
+use crate::network::float::Float;
+use std::fmt;
+
+/// What flows between two layers: a plain vector, `height × width` planes
+/// stored channels last followed by `extra` plain values, or a sequence of
+/// tokens of `dim` values each
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Flat(usize),
+    Planes {
+        height: usize,
+        width: usize,
+        channels: usize,
+        extra: usize,
+    },
+    Tokens {
+        count: usize,
+        dim: usize,
+    },
+}
+
+impl Shape {
+    pub fn size(&self) -> usize {
+        match *self {
+            Shape::Flat(size) => size,
+            Shape::Planes {
+                height,
+                width,
+                channels,
+                extra,
+            } => height * width * channels + extra,
+            Shape::Tokens { count, dim } => count * dim,
+        }
+    }
+}
+
+/// One entry of an architecture specification such as
+/// `dense(256), dropout(0.3), layernorm, dense(64, tanh)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerSpec {
+    /// Fully connected layer; without an activation the network's default
+    /// hidden activation is used
+    Dense {
+        size: usize,
+        activation: Option<Activation>,
+    },
+    /// Fully connected layer summing the weights of the non-zero inputs
+    /// only, for the one-hot board
+    Sparse {
+        size: usize,
+        activation: Option<Activation>,
+    },
+    Dropout(f64),
+    LayerNorm,
+    BatchNorm,
+    /// `conv2d(channels, kernel[, stride=S][, padding=P][, activation=A])`
+    Conv2D {
+        channels: usize,
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+        activation: Option<Activation>,
+    },
+    Flatten,
+    /// `embedding(dim)`: one token per square of the planes
+    Embedding(usize),
+    /// `encoder(heads, feed_forward[, activation])`
+    Encoder {
+        heads: usize,
+        feed_forward: usize,
+        activation: Option<Activation>,
+    },
+    /// Mean of the tokens
+    Pool,
+    /// `residual(layers...[, projection])`: the layers plus a shortcut,
+    /// the identity or a learned projection
+    Residual {
+        body: Vec<LayerSpec>,
+        projection: bool,
+    },
+}
+
+impl LayerSpec {
+    /// Parse a comma-separated list of layers
+    pub fn parse_list(spec: &str) -> Result<Vec<LayerSpec>, String> {
+        split_top_level(spec.trim().trim_start_matches('[').trim_end_matches(']'))?
+            .into_iter()
+            .filter(|item| !item.is_empty())
+            .map(LayerSpec::parse)
+            .collect()
+    }
+
+    pub fn parse(item: &str) -> Result<LayerSpec, String> {
+        let (name, args) = match item.split_once('(') {
+            Some((name, rest)) => {
+                let args = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("missing ')' in layer '{}'", item))?;
+                (name.trim(), split_top_level(args)?)
+            }
+            None => (item.trim(), vec![]),
+        };
+
+        let spec = match (name.to_lowercase().as_str(), args.as_slice()) {
+            ("dense", [size]) => LayerSpec::Dense {
+                size: parse_size(size, item)?,
+                activation: None,
+            },
+            ("dense", [size, activation]) => LayerSpec::Dense {
+                size: parse_size(size, item)?,
+                activation: Some(Activation::from_name(activation)?),
+            },
+            ("sparse", [size]) => LayerSpec::Sparse {
+                size: parse_size(size, item)?,
+                activation: None,
+            },
+            ("sparse", [size, activation]) => LayerSpec::Sparse {
+                size: parse_size(size, item)?,
+                activation: Some(Activation::from_name(activation)?),
+            },
+            ("dropout", [rate]) => {
+                let rate: f64 = rate
+                    .parse()
+                    .map_err(|_| format!("invalid dropout rate in '{}'", item))?;
+                if !(0.0..1.0).contains(&rate) {
+                    return Err(format!(
+                        "invalid dropout rate {} in '{}' (must be 0 <= rate < 1)",
+                        rate, item
+                    ));
+                }
+                LayerSpec::Dropout(rate)
+            }
+            ("layernorm", []) => LayerSpec::LayerNorm,
+            ("batchnorm", []) => LayerSpec::BatchNorm,
+            ("conv2d", [channels, kernel, options @ ..]) => {
+                let mut spec = LayerSpec::Conv2D {
+                    channels: parse_size(channels, item)?,
+                    kernel: parse_size(kernel, item)?,
+                    stride: 1,
+                    padding: 0,
+                    activation: None,
+                };
+                let LayerSpec::Conv2D {
+                    stride,
+                    padding,
+                    activation,
+                    ..
+                } = &mut spec
+                else {
+                    unreachable!()
+                };
+                for option in options {
+                    match option.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                        Some(("stride", value)) => *stride = parse_size(value, item)?,
+                        Some(("padding", value)) => {
+                            *padding = value
+                                .parse()
+                                .map_err(|_| format!("invalid padding in layer '{}'", item))?
+                        }
+                        Some(("activation", value)) => {
+                            *activation = Some(Activation::from_name(value)?)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "unknown option '{}' in layer '{}' (expected stride, padding or activation)",
+                                option, item
+                            ))
+                        }
+                    }
+                }
+                spec
+            }
+            ("flatten", []) => LayerSpec::Flatten,
+            ("embedding", [dim]) => LayerSpec::Embedding(parse_size(dim, item)?),
+            ("encoder", [heads, feed_forward, activation @ ..]) if activation.len() <= 1 => {
+                LayerSpec::Encoder {
+                    heads: parse_size(heads, item)?,
+                    feed_forward: parse_size(feed_forward, item)?,
+                    activation: activation
+                        .first()
+                        .map(|a| Activation::from_name(a))
+                        .transpose()?,
+                }
+            }
+            ("pool", []) => LayerSpec::Pool,
+            ("residual", [items @ .., last]) => {
+                let projection = last.eq_ignore_ascii_case("projection");
+                let items = if projection {
+                    items
+                } else {
+                    &args[..]
+                };
+                if items.is_empty() {
+                    return Err(format!("residual block '{}' has no layers", item));
+                }
+                LayerSpec::Residual {
+                    body: items
+                        .iter()
+                        .map(|item| LayerSpec::parse(item))
+                        .collect::<Result<_, _>>()?,
+                    projection,
+                }
+            }
+            (
+                "dense" | "sparse" | "dropout" | "layernorm" | "batchnorm" | "conv2d" | "flatten"
+                | "embedding" | "encoder" | "pool" | "residual",
+                _,
+            ) => {
+                return Err(format!("wrong number of arguments in layer '{}'", item))
+            }
+            _ => {
+                return Err(format!(
+                    "unknown layer '{}' (expected dense, sparse, dropout, layernorm, batchnorm, conv2d, flatten, embedding, encoder, pool or residual)",
+                    item
+                ))
+            }
+        };
+        Ok(spec)
+    }
+
+    /// Whether the layer reads board planes rather than a plain vector
+    pub fn needs_planes(&self) -> bool {
+        matches!(self, LayerSpec::Conv2D { .. } | LayerSpec::Embedding(_))
+    }
+
+    /// Create the layer, randomly initialised, for inputs of the given
+    /// shape. Returns the layer and the shape of its outputs.
+    pub fn build<T: Float>(
+        &self,
+        input: Shape,
+        hidden_activation: Activation,
+    ) -> Result<(Box<dyn NetworkElement<T>>, Shape), String> {
+        let nb_inputs = input.size();
+        Ok(match (self, input) {
+            (
+                LayerSpec::Dense { .. } | LayerSpec::Sparse { .. } | LayerSpec::Residual { .. },
+                Shape::Planes { .. },
+            ) => return Err(format!("'{}' needs a flatten layer before it", self)),
+            (LayerSpec::Dense { .. } | LayerSpec::Sparse { .. }, Shape::Tokens { .. }) => {
+                return Err(format!(
+                    "'{}' needs a pool or flatten layer before it",
+                    self
+                ))
+            }
+            (&LayerSpec::Dense { size, activation }, _) => (
+                Box::new(Layer::new_random_he(
+                    size as u32,
+                    nb_inputs as u32,
+                    activation.unwrap_or(hidden_activation),
+                )),
+                Shape::Flat(size),
+            ),
+            (&LayerSpec::Sparse { size, activation }, _) => (
+                Box::new(SparseDense::new_random_he(
+                    size,
+                    nb_inputs,
+                    activation.unwrap_or(hidden_activation),
+                )),
+                Shape::Flat(size),
+            ),
+            (&LayerSpec::Dropout(rate), _) => (Box::new(Dropout::new(nb_inputs, rate)), input),
+            (LayerSpec::LayerNorm, _) => (Box::new(LayerNorm::new(nb_inputs)), input),
+            (LayerSpec::BatchNorm, _) => (Box::new(BatchNorm::new(nb_inputs)), input),
+            (
+                &LayerSpec::Conv2D {
+                    channels,
+                    kernel,
+                    stride,
+                    padding,
+                    activation,
+                },
+                Shape::Planes {
+                    height,
+                    width,
+                    channels: in_channels,
+                    extra,
+                },
+            ) => {
+                let conv = Conv2D::new_random_he(
+                    (height, width, in_channels),
+                    extra,
+                    channels,
+                    kernel,
+                    stride,
+                    padding,
+                    activation.unwrap_or(hidden_activation),
+                )?;
+                let (height, width) = conv.output_dims();
+                let shape = Shape::Planes {
+                    height,
+                    width,
+                    channels,
+                    extra,
+                };
+                (Box::new(conv), shape)
+            }
+            (LayerSpec::Conv2D { .. }, _) => {
+                return Err(format!(
+                    "'{}' needs board planes as input (only convolutions can come before it)",
+                    self
+                ))
+            }
+            (LayerSpec::Flatten, _) => (
+                Box::new(Flatten { size: nb_inputs }),
+                Shape::Flat(nb_inputs),
+            ),
+            (
+                &LayerSpec::Embedding(dim),
+                Shape::Planes {
+                    height,
+                    width,
+                    channels,
+                    extra,
+                },
+            ) => (
+                Box::new(Embedding::new_random(height * width, channels, extra, dim)),
+                Shape::Tokens {
+                    count: height * width,
+                    dim,
+                },
+            ),
+            (LayerSpec::Embedding(_), _) => {
+                return Err(format!(
+                    "'{}' needs board planes as input (it must come first or after convolutions)",
+                    self
+                ))
+            }
+            (
+                &LayerSpec::Encoder {
+                    heads,
+                    feed_forward,
+                    activation,
+                },
+                Shape::Tokens { count, dim },
+            ) => (
+                Box::new(Encoder::new_random(
+                    count,
+                    dim,
+                    heads,
+                    feed_forward,
+                    activation.unwrap_or(hidden_activation),
+                )?),
+                input,
+            ),
+            (LayerSpec::Pool, Shape::Tokens { count, dim }) => {
+                (Box::new(Pool { tokens: count, dim }), Shape::Flat(dim))
+            }
+            (LayerSpec::Encoder { .. } | LayerSpec::Pool, _) => {
+                return Err(format!("'{}' needs tokens from an embedding layer", self))
+            }
+            (LayerSpec::Residual { body, projection }, _) => {
+                let mut elements = vec![];
+                let mut shape = input;
+                for spec in body {
+                    let (element, output) = spec.build(shape, hidden_activation)?;
+                    elements.push(element);
+                    shape = output;
+                }
+                (Box::new(Residual::new(elements, *projection)?), shape)
+            }
+        })
+    }
+}
+
+impl fmt::Display for LayerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayerSpec::Dense {
+                size,
+                activation: None,
+            } => write!(f, "dense({})", size),
+            LayerSpec::Dense {
+                size,
+                activation: Some(activation),
+            } => write!(f, "dense({}, {})", size, activation),
+            LayerSpec::Sparse {
+                size,
+                activation: None,
+            } => write!(f, "sparse({})", size),
+            LayerSpec::Sparse {
+                size,
+                activation: Some(activation),
+            } => write!(f, "sparse({}, {})", size, activation),
+            LayerSpec::Dropout(rate) => write!(f, "dropout({})", rate),
+            LayerSpec::LayerNorm => write!(f, "layernorm"),
+            LayerSpec::BatchNorm => write!(f, "batchnorm"),
+            LayerSpec::Conv2D {
+                channels,
+                kernel,
+                stride,
+                padding,
+                activation,
+            } => {
+                write!(f, "conv2d({}, {}", channels, kernel)?;
+                if *stride != 1 {
+                    write!(f, ", stride={}", stride)?;
+                }
+                if *padding != 0 {
+                    write!(f, ", padding={}", padding)?;
+                }
+                if let Some(activation) = activation {
+                    write!(f, ", activation={}", activation)?;
+                }
+                write!(f, ")")
+            }
+            LayerSpec::Flatten => write!(f, "flatten"),
+            LayerSpec::Embedding(dim) => write!(f, "embedding({})", dim),
+            LayerSpec::Encoder {
+                heads,
+                feed_forward,
+                activation,
+            } => {
+                write!(f, "encoder({}, {}", heads, feed_forward)?;
+                if let Some(activation) = activation {
+                    write!(f, ", {}", activation)?;
+                }
+                write!(f, ")")
+            }
+            LayerSpec::Pool => write!(f, "pool"),
+            LayerSpec::Residual { body, projection } => {
+                write!(f, "residual(")?;
+                for (index, spec) in body.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", spec)?;
+                }
+                if *projection {
+                    write!(f, ", projection")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn parse_size(value: &str, item: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&size| size > 0)
+        .ok_or_else(|| format!("invalid size in layer '{}'", item))
+}
+
+/// Split on the commas that are not inside parentheses
+pub fn split_top_level(s: &str) -> Result<Vec<&str>, String> {
+    let mut items = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("unbalanced ')' in '{}'", s))?
+            }
+            ',' if depth == 0 => {
+                items.push(s[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("unbalanced '(' in '{}'", s));
+    }
+    items.push(s[start..].trim());
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::network::Network;
+
+    #[test]
+    fn test_parse_list() {
+        let specs = LayerSpec::parse_list(
+            "sparse(64), batchnorm, dropout(0.3), layernorm, dense(32, leaky_relu(0.1))",
+        )
+        .unwrap();
+        assert_eq!(
+            specs,
+            vec![
+                LayerSpec::Sparse {
+                    size: 64,
+                    activation: None
+                },
+                LayerSpec::BatchNorm,
+                LayerSpec::Dropout(0.3),
+                LayerSpec::LayerNorm,
+                LayerSpec::Dense {
+                    size: 32,
+                    activation: Some(Activation::LeakyRelu(0.1))
+                },
+            ]
+        );
+
+        let text = specs
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(LayerSpec::parse_list(&text).unwrap(), specs);
+
+        assert!(LayerSpec::parse_list("dense(0)").is_err());
+        assert!(LayerSpec::parse_list("dropout(1.0)").is_err());
+        assert!(LayerSpec::parse_list("conv(3)").is_err());
+        assert!(LayerSpec::parse_list("conv2d(8, 3, dilation=2)").is_err());
+        assert!(LayerSpec::parse_list("dense(3").is_err());
+    }
+
+    #[test]
+    fn test_residual_blocks() {
+        let specs =
+            LayerSpec::parse_list("dense(8), residual(dense(8), dense(8, linear)), residual(dense(16), layernorm, projection)")
+                .unwrap();
+        assert_eq!(
+            specs[2],
+            LayerSpec::Residual {
+                body: vec![
+                    LayerSpec::Dense {
+                        size: 16,
+                        activation: None
+                    },
+                    LayerSpec::LayerNorm
+                ],
+                projection: true
+            }
+        );
+        let text = specs
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(LayerSpec::parse_list(&text).unwrap(), specs);
+
+        let network =
+            Network::<f64>::from_architecture(Shape::Flat(4), &specs, Activation::Relu).unwrap();
+        assert_eq!(network.nb_outputs(), 16);
+        // Identity shortcut between different widths
+        assert!(Network::<f64>::from_architecture(
+            Shape::Flat(4),
+            &LayerSpec::parse_list("residual(dense(8))").unwrap(),
+            Activation::Relu
+        )
+        .is_err());
+        assert!(LayerSpec::parse_list("residual(projection)").is_err());
+    }
+
+    #[test]
+    fn test_convolution_shapes() {
+        let specs = LayerSpec::parse_list(
+            "conv2d(16, 3, padding=1), conv2d(8, 2, stride=2, activation=tanh), flatten, dense(10)",
+        )
+        .unwrap();
+        assert_eq!(
+            specs[1],
+            LayerSpec::Conv2D {
+                channels: 8,
+                kernel: 2,
+                stride: 2,
+                padding: 0,
+                activation: Some(Activation::Tanh)
+            }
+        );
+        assert_eq!(
+            specs[1].to_string(),
+            "conv2d(8, 2, stride=2, activation=tanh)"
+        );
+
+        let mut shape = Shape::Planes {
+            height: 8,
+            width: 8,
+            channels: 13,
+            extra: 1,
+        };
+        let mut sizes = vec![];
+        for spec in &specs {
+            let (layer, output) = spec.build::<f64>(shape, Activation::Relu).unwrap();
+            assert_eq!(layer.nb_inputs(), shape.size());
+            sizes.push(layer.nb_outputs());
+            shape = output;
+        }
+        assert_eq!(sizes, vec![8 * 8 * 16 + 1, 4 * 4 * 8 + 1, 129, 10]);
+
+        let planes = Shape::Planes {
+            height: 8,
+            width: 8,
+            channels: 13,
+            extra: 1,
+        };
+        assert!(specs[3]
+            .build::<f64>(
+                specs[0].build::<f64>(planes, Activation::Relu).unwrap().1,
+                Activation::Relu
+            )
+            .is_err());
+        assert!(specs[0]
+            .build::<f64>(Shape::Flat(833), Activation::Relu)
+            .is_err());
+    }
+
+    #[test]
+    fn test_transformer_shapes() {
+        let specs = LayerSpec::parse_list(
+            "embedding(8), encoder(2, 16), encoder(4, 16, gelu), pool, dense(5)",
+        )
+        .unwrap();
+        assert_eq!(
+            specs[2],
+            LayerSpec::Encoder {
+                heads: 4,
+                feed_forward: 16,
+                activation: Some(Activation::Gelu)
+            }
+        );
+        let text = specs
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(LayerSpec::parse_list(&text).unwrap(), specs);
+
+        let board = Shape::Planes {
+            height: 8,
+            width: 8,
+            channels: 13,
+            extra: 1,
+        };
+        let network = Network::<f64>::from_architecture(board, &specs, Activation::Relu).unwrap();
+        assert_eq!(network.nb_inputs(), 833);
+        assert_eq!(network.layers[1].nb_outputs(), 64 * 8);
+        assert_eq!(network.nb_outputs(), 5);
+
+        for wrong in [
+            "embedding(8), dense(5)",
+            "embedding(6), encoder(4, 8)",
+            "encoder(2, 8)",
+        ] {
+            let specs = LayerSpec::parse_list(wrong).unwrap();
+            assert!(Network::<f64>::from_architecture(board, &specs, Activation::Relu).is_err());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::network::autodiff::{self, Differentiable, Tape, Var};
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::r#trait::{Cache, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+
+pub const DEFAULT_EPSILON: f64 = 1e-5;
+pub const DEFAULT_MOMENTUM: f64 = 0.1;
+
+/// Batch normalization: each feature is normalized over the batch, then
+/// scaled by `gamma` and shifted by `beta`.
+///
+/// In training mode the statistics of the current batch are used and folded
+/// into the running mean/variance after each step; at inference the running
+/// statistics are used. `Network::train_batch` never splits the training
+/// batch of a network holding this layer, so the statistics are those of
+/// the whole batch. A single sample has no variance, so it always uses the
+/// running statistics.
+pub struct BatchNorm<T = f64> {
+    pub gamma: Vec<T>,
+    pub beta: Vec<T>,
+    pub running_mean: Vec<T>,
+    pub running_var: Vec<T>,
+    pub epsilon: f64,
+    pub momentum: f64,
+    pub training_mode: bool,
+}
+
+impl<T: Float> BatchNorm<T> {
+    pub fn new(size: usize) -> Self {
+        BatchNorm {
+            gamma: vec![T::one(); size],
+            beta: vec![T::zero(); size],
+            running_mean: vec![T::zero(); size],
+            running_var: vec![T::one(); size],
+            epsilon: DEFAULT_EPSILON,
+            momentum: DEFAULT_MOMENTUM,
+            training_mode: false,
+        }
+    }
+
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let size: usize = record.attribute("size")?;
+        let momentum: f64 = record.attribute("momentum")?;
+        if !(0.0..=1.0).contains(&momentum) {
+            return Err(format!(
+                "batchnorm: invalid momentum {} (must be 0 <= momentum <= 1)",
+                momentum
+            ));
+        }
+        Ok(BatchNorm {
+            gamma: record.tensor("gamma", size)?,
+            beta: record.tensor("beta", size)?,
+            running_mean: record.tensor("running_mean", size)?,
+            running_var: record.tensor("running_var", size)?,
+            epsilon: record.attribute("epsilon")?,
+            momentum,
+            training_mode: false,
+        })
+    }
+
+    /// Whether a batch of `rows` samples is normalized with its own
+    /// statistics
+    fn uses_batch_statistics(&self, rows: usize) -> bool {
+        self.training_mode && rows > 1
+    }
+
+    fn row_vector(values: &[T]) -> Matrix<T> {
+        Matrix::new(1, values.len(), values.to_vec())
+    }
+}
+
+impl<T: Float> Differentiable<T> for BatchNorm<T> {
+    /// `gamma ⊙ (x - mean) / sqrt(var + eps) + beta`, column by column
+    fn record(&self, tape: &mut Tape<T>, inputs: Var) -> (Var, Vec<Var>) {
+        let gamma = tape.leaf(Self::row_vector(&self.gamma));
+        let beta = tape.leaf(Self::row_vector(&self.beta));
+
+        let (centered, var) = if self.uses_batch_statistics(tape.value(inputs).rows) {
+            let mean = tape.column_mean(inputs);
+            let centered = tape.sub(inputs, mean);
+            let squares = tape.mul(centered, centered);
+            (centered, tape.column_mean(squares))
+        } else {
+            let mean = tape.leaf(Self::row_vector(&self.running_mean));
+            let centered = tape.sub(inputs, mean);
+            (centered, tape.leaf(Self::row_vector(&self.running_var)))
+        };
+        let shifted = tape.add_scalar(var, self.epsilon);
+        let inv_std = tape.powf(shifted, -0.5);
+        let normalized = tape.mul(centered, inv_std);
+        let scaled = tape.mul(normalized, gamma);
+        (tape.add(scaled, beta), vec![gamma, beta])
+    }
+}
+
+impl<T: Float> NetworkElement<T> for BatchNorm<T> {
+    fn kind(&self) -> &'static str {
+        "batchnorm"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.gamma.len()
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.gamma.len()
+    }
+
+    fn set_training_mode(&mut self, training: bool) {
+        self.training_mode = training;
+    }
+
+    fn couples_batch(&self) -> bool {
+        true
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        autodiff::forward(self, inputs)
+    }
+
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        autodiff::backward(self, cache, output_grads, need_input_grads)
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![
+            (ParamKind::Scale, &self.gamma),
+            (ParamKind::Bias, &self.beta),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![
+            (ParamKind::Scale, &mut self.gamma),
+            (ParamKind::Bias, &mut self.beta),
+        ]
+    }
+
+    /// `[count, mean..., var...]` of the batch, when it was used
+    fn batch_statistics(&self, cache: &Cache) -> Option<Vec<T>> {
+        let inputs = autodiff::inputs::<T>(cache);
+        if !self.uses_batch_statistics(inputs.rows) {
+            return None;
+        }
+        let n = T::cast(inputs.rows as f64);
+        let mean: Vec<T> = inputs.column_sums().iter().map(|&s| s / n).collect();
+        let mut var = vec![T::zero(); inputs.cols];
+        for r in 0..inputs.rows {
+            for ((v, &x), &m) in var.iter_mut().zip(inputs.row(r)).zip(&mean) {
+                *v += (x - m) * (x - m) / n;
+            }
+        }
+
+        let mut statistics = vec![n];
+        statistics.extend(mean);
+        statistics.extend(var);
+        Some(statistics)
+    }
+
+    /// Pool the statistics of every chunk into those of the whole batch, then
+    /// move the running statistics towards them (unbiased variance)
+    fn update_statistics(&mut self, statistics: &[Vec<T>]) {
+        let size = self.gamma.len();
+        let total: T = statistics.iter().map(|s| s[0]).sum();
+        if total < T::cast(2.0) {
+            return;
+        }
+
+        let mut mean = vec![T::zero(); size];
+        for s in statistics {
+            for (m, &chunk_mean) in mean.iter_mut().zip(&s[1..=size]) {
+                *m += s[0] * chunk_mean / total;
+            }
+        }
+        let mut var = vec![T::zero(); size];
+        for s in statistics {
+            for c in 0..size {
+                let delta = s[1 + c] - mean[c];
+                var[c] += s[0] * (s[1 + size + c] + delta * delta) / total;
+            }
+        }
+
+        let unbiased = total / (total - T::one());
+        let momentum = T::cast(self.momentum);
+        let keep = T::one() - momentum;
+        for c in 0..size {
+            self.running_mean[c] = keep * self.running_mean[c] + momentum * mean[c];
+            self.running_var[c] = keep * self.running_var[c] + momentum * var[c] * unbiased;
+        }
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("batchnorm")
+            .with_attribute("size", self.gamma.len())
+            .with_attribute("epsilon", self.epsilon)
+            .with_attribute("momentum", self.momentum)
+            .with_tensor("gamma", &self.gamma)
+            .with_tensor("beta", &self.beta)
+            .with_tensor("running_mean", &self.running_mean)
+            .with_tensor("running_var", &self.running_var)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::gradient_check::assert_gradients;
+
+    fn batch() -> Matrix {
+        Matrix::new(3, 2, vec![1.0, -2.0, 3.0, 0.5, 2.0, 4.0])
+    }
+
+    #[test]
+    fn test_training_backward_matches_finite_differences() {
+        let mut norm = BatchNorm::<f64>::new(2);
+        norm.gamma = vec![1.5, -0.5];
+        norm.beta = vec![0.2, 0.1];
+        norm.set_training_mode(true);
+        let inputs = batch();
+        let weights = Matrix::new(3, 2, vec![1.0, 2.0, -1.0, 0.5, 0.3, -2.0]);
+        let loss = |x: &Matrix| -> f64 {
+            let (out, _) = norm.forward(x);
+            out.data
+                .iter()
+                .zip(weights.data.iter())
+                .map(|(o, w)| o * w)
+                .sum()
+        };
+
+        let (_, cache) = norm.forward(&inputs);
+        let (input_grads, _) = norm.backward(&inputs, &cache, &weights, true);
+        let input_grads = input_grads.unwrap();
+
+        let h = 1e-6;
+        for i in 0..inputs.data.len() {
+            let mut plus = inputs.clone();
+            plus.data[i] += h;
+            let mut minus = inputs.clone();
+            minus.data[i] -= h;
+            let numeric = (loss(&plus) - loss(&minus)) / (2.0 * h);
+            assert!((numeric - input_grads.data[i]).abs() < 1e-5);
+        }
+        assert_gradients(&mut norm, &inputs);
+    }
+
+    #[test]
+    fn test_running_statistics() {
+        let mut norm = BatchNorm::<f64>::new(2);
+        norm.momentum = 1.0;
+        norm.set_training_mode(true);
+        let inputs = Matrix::new(4, 2, vec![1.0, -2.0, 3.0, 0.5, 2.0, 4.0, 6.0, 1.5]);
+
+        // The same batch in one chunk or split in two gives the same update
+        let (_, whole) = norm.forward(&inputs);
+        let (_, first) = norm.forward(&Matrix::new(2, 2, inputs.data[..4].to_vec()));
+        let (_, last) = norm.forward(&Matrix::new(2, 2, inputs.data[4..].to_vec()));
+        let (_, single) = norm.forward(&Matrix::new(1, 2, inputs.data[..2].to_vec()));
+        assert!(norm.batch_statistics(&single).is_none());
+
+        let mut pooled = BatchNorm::<f64>::new(2);
+        pooled.momentum = 1.0;
+        pooled.update_statistics(&[
+            norm.batch_statistics(&first).unwrap(),
+            norm.batch_statistics(&last).unwrap(),
+        ]);
+        norm.update_statistics(&[norm.batch_statistics(&whole).unwrap()]);
+
+        assert_eq!(norm.running_mean, vec![3.0, 1.0]);
+        // Unbiased variance of 1, 3, 2, 6
+        assert!((norm.running_var[0] - 14.0 / 3.0).abs() < 1e-12);
+        for (a, b) in norm.running_var.iter().zip(pooled.running_var.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+
+        // Inference uses the running statistics
+        norm.set_training_mode(false);
+        let (outputs, _) = norm.forward(&Matrix::new(1, 2, vec![3.0, 1.0]));
+        assert!(outputs.data.iter().all(|x| x.abs() < 1e-12));
+    }
+}
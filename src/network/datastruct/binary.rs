@@ -0,0 +1,419 @@
+//! Binary `.nn` model format.
+//!
+//! ```text
+//! magic      "MTNN"
+//! version    u16
+//! precision  u8       4 = f32 weights, 8 = f64 weights
+//! nb_entries u32      metadata, since version 2
+//! per entry:
+//!   key         u32 length + UTF-8
+//!   value       u32 length + UTF-8
+//! nb_layers  u32
+//! per layer, since version 3 (see `record`):
+//!   kind        u8 length + UTF-8
+//!   nb_attrs    u32
+//!   per attribute:
+//!     key         u32 length + UTF-8
+//!     value       u32 length + UTF-8
+//!   nb_tensors  u32
+//!   per tensor:
+//!     name        u32 length + UTF-8
+//!     len         u32
+//!     values      len floats
+//! ```
+//!
+//! Residual blocks are written as flat records too: a `children=N`
+//! attribute says how many of the following records belong to them.
+//!
+//! Every integer and float is little-endian. Versions 1 and 2 only held
+//! dense layers (activation name, nb_inputs, nb_neurons, row-major weights,
+//! biases) and are still read; version 1 had no metadata section.
+
+use crate::network::datastruct::metadata::Metadata;
+use crate::network::datastruct::network::Network;
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use std::fmt;
+
+pub const MAGIC: &[u8; 4] = b"MTNN";
+pub const VERSION: u16 = 3;
+
+/// Width of the floats stored in a binary model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    F32,
+    F64,
+}
+
+impl Precision {
+    /// `f32` or `f64`
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "f32" => Ok(Precision::F32),
+            "f64" => Ok(Precision::F64),
+            _ => Err(format!(
+                "unknown precision '{}' (expected f32 or f64)",
+                name
+            )),
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Precision::F32 => 4,
+            Precision::F64 => 8,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, String> {
+        match tag {
+            4 => Ok(Precision::F32),
+            8 => Ok(Precision::F64),
+            _ => Err(format!("unknown float precision tag {}", tag)),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Precision::F32 => write!(f, "f32"),
+            Precision::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+/// On-disk representation of a network
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModelFormat {
+    Text,
+    Binary(Precision),
+}
+
+impl ModelFormat {
+    /// `text`, `f32`/`binary32` or `f64`/`binary`/`binary64`
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "text" | "txt" => Ok(ModelFormat::Text),
+            "f32" | "binary32" => Ok(ModelFormat::Binary(Precision::F32)),
+            "f64" | "binary" | "binary64" => Ok(ModelFormat::Binary(Precision::F64)),
+            _ => Err(format!(
+                "unknown model format '{}' (expected text, f32 or f64)",
+                name
+            )),
+        }
+    }
+
+    /// Format of a model file, from its first bytes
+    pub fn detect(content: &[u8]) -> Self {
+        if content.starts_with(MAGIC) {
+            let precision = content
+                .get(6)
+                .and_then(|&tag| Precision::from_tag(tag).ok())
+                .unwrap_or(Precision::F64);
+            ModelFormat::Binary(precision)
+        } else {
+            ModelFormat::Text
+        }
+    }
+}
+
+/// Any network can be written in either precision, whatever its own
+pub fn encode<T: Float>(network: &Network<T>, precision: Precision) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.push(precision.tag());
+
+    write_u32(&mut out, network.metadata.entries().len());
+    for (key, value) in network.metadata.entries() {
+        write_str(&mut out, key);
+        write_str(&mut out, value);
+    }
+
+    let records: Vec<LayerRecord> = network
+        .layers
+        .iter()
+        .flat_map(|layer| layer.serialize().flatten())
+        .collect();
+    write_u32(&mut out, records.len());
+
+    for record in records {
+        out.push(record.kind.len() as u8);
+        out.extend_from_slice(record.kind.as_bytes());
+        write_u32(&mut out, record.attributes.len());
+        for (key, value) in &record.attributes {
+            write_str(&mut out, key);
+            write_str(&mut out, value);
+        }
+        write_u32(&mut out, record.tensors.len());
+        for (name, values) in &record.tensors {
+            write_str(&mut out, name);
+            write_u32(&mut out, values.len());
+            write_floats(&mut out, values, precision);
+        }
+    }
+    out
+}
+
+/// The network in precision `T`, whatever the precision of the file
+pub fn decode<T: Float>(content: &[u8]) -> Result<Network<T>, String> {
+    let mut reader = Reader::new(content);
+
+    if reader.bytes(4)? != MAGIC {
+        return Err(String::from("not a binary network file (bad magic)"));
+    }
+    let version = u16::from_le_bytes(reader.bytes(2)?.try_into().unwrap());
+    if version == 0 || version > VERSION {
+        return Err(format!(
+            "unsupported binary network version {} (expected at most {})",
+            version, VERSION
+        ));
+    }
+    let precision = Precision::from_tag(reader.u8()?)?;
+
+    let mut metadata = Metadata::default();
+    if version >= 2 {
+        for _ in 0..reader.u32()? {
+            let key = reader.string()?;
+            let value = reader.string()?;
+            metadata.set(&key, &value);
+        }
+    }
+
+    let nb_records = reader.u32()?;
+    let mut records = Vec::with_capacity(nb_records);
+    for record_idx in 0..nb_records {
+        let record = if version >= 3 {
+            reader.record(precision)
+        } else {
+            reader.dense_record(precision)
+        }
+        .map_err(|e| format!("layer {}: {}", record_idx, e))?;
+        records.push(record);
+    }
+
+    let records = LayerRecord::nest(records)
+        .map_err(|(record_idx, message)| format!("layer {}: {}", record_idx, message))?;
+    let mut layers = Vec::with_capacity(records.len());
+    for (record_idx, record) in records {
+        layers.push(
+            record
+                .build()
+                .map_err(|e| format!("layer {}: {}", record_idx, e))?,
+        );
+    }
+
+    if reader.pos != content.len() {
+        return Err(format!(
+            "{} unexpected trailing bytes after the last layer",
+            content.len() - reader.pos
+        ));
+    }
+    if layers.is_empty() {
+        return Err(String::from("network file contains no layers"));
+    }
+    let network = Network { layers, metadata };
+    network
+        .check_widths()
+        .map_err(|(layer_idx, message)| format!("layer {}: {}", layer_idx, message))?;
+    Ok(network)
+}
+
+pub fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+pub fn write_floats(out: &mut Vec<u8>, values: &[f64], precision: Precision) {
+    for &v in values {
+        match precision {
+            Precision::F32 => out.extend_from_slice(&(v as f32).to_le_bytes()),
+            Precision::F64 => out.extend_from_slice(&v.to_le_bytes()),
+        }
+    }
+}
+
+/// Cursor over little-endian binary content
+pub struct Reader<'a> {
+    content: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(content: &'a [u8]) -> Self {
+        Reader { content, pos: 0 }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.content.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.content.len() {
+            return Err(format!(
+                "unexpected end of file at byte {} (needed {} more bytes)",
+                self.content.len(),
+                end - self.content.len()
+            ));
+        }
+        let bytes = &self.content[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()) as usize)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()?;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| String::from("string is not valid UTF-8"))
+    }
+
+    fn kind(&mut self) -> Result<String, String> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| String::from("layer name is not UTF-8"))
+    }
+
+    fn record(&mut self, precision: Precision) -> Result<LayerRecord, String> {
+        let mut record = LayerRecord::new(&self.kind()?);
+        for _ in 0..self.u32()? {
+            let key = self.string()?;
+            let value = self.string()?;
+            record.attributes.push((key, value));
+        }
+        for _ in 0..self.u32()? {
+            let name = self.string()?;
+            let len = self.u32()?;
+            let values = self.floats(len, precision)?;
+            record.tensors.push((name, values));
+        }
+        Ok(record)
+    }
+
+    /// Dense layer as written by versions 1 and 2
+    fn dense_record(&mut self, precision: Precision) -> Result<LayerRecord, String> {
+        let activation = self.kind()?;
+        let nb_inputs = self.u32()?;
+        let nb_neurons = self.u32()?;
+        let weights = self.floats(nb_neurons * nb_inputs, precision)?;
+        let biases = self.floats(nb_neurons, precision)?;
+        Ok(LayerRecord::new("dense")
+            .with_attribute("activation", activation)
+            .with_attribute("inputs", nb_inputs)
+            .with_attribute("outputs", nb_neurons)
+            .with_tensor("weights", &weights)
+            .with_tensor("biases", &biases))
+    }
+
+    pub fn floats(&mut self, count: usize, precision: Precision) -> Result<Vec<f64>, String> {
+        let width = precision.tag() as usize;
+        let bytes = self.bytes(count * width)?;
+        Ok(bytes
+            .chunks_exact(width)
+            .map(|b| match precision {
+                Precision::F32 => f32::from_le_bytes(b.try_into().unwrap()) as f64,
+                Precision::F64 => f64::from_le_bytes(b.try_into().unwrap()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::activation::Activation;
+    use crate::network::datastruct::layer_norm::LayerNorm;
+
+    #[test]
+    fn test_round_trip() {
+        let mut network: Network = Network::new_random_he(
+            6,
+            vec![4, 3],
+            vec![0.5],
+            Activation::LeakyRelu(0.2),
+            Activation::Linear,
+        );
+        network.layers.insert(1, Box::new(LayerNorm::new(4)));
+        network.metadata.set("input_size", "6");
+
+        let decoded: Network = decode(&encode(&network, Precision::F64)).unwrap();
+        assert_eq!(decoded.to_string(), network.to_string());
+        assert_eq!(decoded.metadata, network.metadata);
+
+        let decoded: Network = decode(&encode(&network, Precision::F32)).unwrap();
+        assert_eq!(decoded.get_architecture(), vec![4, 4, 4, 3]);
+        for (a, b) in decoded.layers.iter().zip(network.layers.iter()) {
+            let (a, b) = (a.serialize(), b.serialize());
+            assert_eq!(a.attributes, b.attributes);
+            for ((_, x), (_, y)) in a.tensors.iter().zip(b.tensors.iter()) {
+                for (x, y) in x.iter().zip(y.iter()) {
+                    assert!((x - y).abs() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect_and_truncated() {
+        let network: Network =
+            Network::new_random_he(3, vec![2], vec![], Activation::Relu, Activation::Linear);
+        let bytes = encode(&network, Precision::F32);
+        assert_eq!(
+            ModelFormat::detect(&bytes),
+            ModelFormat::Binary(Precision::F32)
+        );
+        assert_eq!(ModelFormat::detect(b"relu 0 1 2"), ModelFormat::Text);
+        assert!(decode::<f64>(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_reads_version_1() {
+        let network: Network =
+            Network::new_random_he(3, vec![2], vec![], Activation::Relu, Activation::Relu);
+        let record = network.layers[0].serialize();
+
+        // Version 1: no metadata section, dense layers only
+        let mut v1 = MAGIC.to_vec();
+        v1.extend_from_slice(&1u16.to_le_bytes());
+        v1.push(Precision::F64.tag());
+        write_u32(&mut v1, 1);
+        v1.push(4);
+        v1.extend_from_slice(b"relu");
+        write_u32(&mut v1, 3);
+        write_u32(&mut v1, 2);
+        write_floats(
+            &mut v1,
+            &record.tensor("weights", 6).unwrap(),
+            Precision::F64,
+        );
+        write_floats(
+            &mut v1,
+            &record.tensor("biases", 2).unwrap(),
+            Precision::F64,
+        );
+
+        let decoded: Network = decode(&v1).unwrap();
+        assert_eq!(decoded.to_string(), network.to_string());
+    }
+}
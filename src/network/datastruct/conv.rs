@@ -0,0 +1,423 @@
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::r#trait::{cache_ref, Cache, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+use crate::network::random;
+use rand_distr::{Distribution, Normal};
+
+/// 2D convolution over `height × width` planes.
+///
+/// Planes are stored channels last: square by square, with the value of
+/// every channel for that square next to each other. This is how
+/// `FenPosition::to_inputs` lays out its 13 one-hot planes, so a first
+/// convolution reads the board directly. The `extra` values after the
+/// planes (the side to move) are passed through unchanged.
+///
+/// Each output position is computed from its `kernel × kernel` patch, so
+/// the layer is a dense product over the unrolled patches (im2col).
+pub struct Conv2D<T = f64> {
+    pub height: usize,
+    pub width: usize,
+    pub in_channels: usize,
+    pub kernel: usize,
+    pub stride: usize,
+    pub padding: usize,
+    pub extra: usize,
+    pub activation: Activation,
+    /// `channels × (kernel · kernel · in_channels)`, patches ordered by
+    /// kernel row, kernel column, then input channel
+    pub weights: Matrix<T>,
+    pub biases: Vec<T>,
+}
+
+/// Unrolled patches (one row per sample and output position) and dy/dz of
+/// every output
+struct ConvCache<T> {
+    patches: Matrix<T>,
+    derivatives: Matrix<T>,
+}
+
+impl<T: Float> Conv2D<T> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_random_he(
+        (height, width, in_channels): (usize, usize, usize),
+        extra: usize,
+        channels: usize,
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+        activation: Activation,
+    ) -> Result<Self, String> {
+        let patch_size = kernel * kernel * in_channels;
+        let mut rng = random::rng();
+        let std_dev = (activation.init_gain() / patch_size as f64).sqrt();
+        let normal = Normal::new(0.0, std_dev).map_err(|e| e.to_string())?;
+
+        let conv = Conv2D {
+            height,
+            width,
+            in_channels,
+            kernel,
+            stride,
+            padding,
+            extra,
+            activation,
+            weights: Matrix::new(
+                channels,
+                patch_size,
+                (0..channels * patch_size)
+                    .map(|_| T::cast(normal.sample(&mut rng)))
+                    .collect(),
+            ),
+            biases: vec![T::zero(); channels],
+        };
+        conv.check_shape()?;
+        Ok(conv)
+    }
+
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let in_channels: usize = record.attribute("in_channels")?;
+        let channels: usize = record.attribute("channels")?;
+        let kernel: usize = record.attribute("kernel")?;
+        let patch_size = kernel * kernel * in_channels;
+        let conv = Conv2D {
+            height: record.attribute("height")?,
+            width: record.attribute("width")?,
+            in_channels,
+            kernel,
+            stride: record.attribute("stride")?,
+            padding: record.attribute("padding")?,
+            extra: record.attribute("extra")?,
+            activation: record.attribute("activation")?,
+            weights: Matrix::new(
+                channels,
+                patch_size,
+                record.tensor("weights", channels * patch_size)?,
+            ),
+            biases: record.tensor("biases", channels)?,
+        };
+        conv.check_shape()?;
+        Ok(conv)
+    }
+
+    fn check_shape(&self) -> Result<(), String> {
+        if self.kernel == 0 || self.stride == 0 || self.in_channels == 0 {
+            return Err(String::from(
+                "conv2d: kernel, stride and input channels must be positive",
+            ));
+        }
+        if self.kernel > self.height + 2 * self.padding
+            || self.kernel > self.width + 2 * self.padding
+        {
+            return Err(format!(
+                "conv2d: kernel {} does not fit {}x{} planes with padding {}",
+                self.kernel, self.height, self.width, self.padding
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn channels(&self) -> usize {
+        self.weights.rows
+    }
+
+    /// Height and width of the output planes
+    pub fn output_dims(&self) -> (usize, usize) {
+        (
+            (self.height + 2 * self.padding - self.kernel) / self.stride + 1,
+            (self.width + 2 * self.padding - self.kernel) / self.stride + 1,
+        )
+    }
+
+    fn nb_positions(&self) -> usize {
+        let (out_height, out_width) = self.output_dims();
+        out_height * out_width
+    }
+
+    /// Input index read by every patch value of output position `position`
+    /// (`None` in the padding)
+    fn patch_indices(&self, position: usize) -> impl Iterator<Item = Option<usize>> + '_ {
+        let (_, out_width) = self.output_dims();
+        let top = (position / out_width * self.stride) as isize - self.padding as isize;
+        let left = (position % out_width * self.stride) as isize - self.padding as isize;
+        (0..self.kernel).flat_map(move |ky| {
+            (0..self.kernel).flat_map(move |kx| {
+                let (y, x) = (top + ky as isize, left + kx as isize);
+                let square = ((0..self.height as isize).contains(&y)
+                    && (0..self.width as isize).contains(&x))
+                .then(|| (y as usize * self.width + x as usize) * self.in_channels);
+                (0..self.in_channels).map(move |c| square.map(|square| square + c))
+            })
+        })
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Conv2D<T> {
+    fn kind(&self) -> &'static str {
+        "conv2d"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.height * self.width * self.in_channels + self.extra
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.nb_positions() * self.channels() + self.extra
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        let positions = self.nb_positions();
+        let planes_size = self.nb_inputs() - self.extra;
+
+        let mut patches = Matrix::zeros(inputs.rows * positions, self.weights.cols);
+        for b in 0..inputs.rows {
+            let sample = inputs.row(b);
+            for p in 0..positions {
+                for (value, index) in patches
+                    .row_mut(b * positions + p)
+                    .iter_mut()
+                    .zip(self.patch_indices(p))
+                {
+                    if let Some(index) = index {
+                        *value = sample[index];
+                    }
+                }
+            }
+        }
+
+        // (B · positions) × channels: row-major, that is each sample's
+        // output planes, channels last
+        let mut z = patches.mul_transposed(&self.weights);
+        let mut derivatives = Matrix::zeros(z.rows, z.cols);
+        for r in 0..z.rows {
+            for ((out, d), &bias) in z
+                .row_mut(r)
+                .iter_mut()
+                .zip(derivatives.row_mut(r).iter_mut())
+                .zip(self.biases.iter())
+            {
+                let pre = *out + bias;
+                *out = self.activation.apply(pre);
+                *d = self.activation.derivative(pre, *out);
+            }
+        }
+
+        let conv_size = positions * self.channels();
+        let mut outputs = Matrix::zeros(inputs.rows, self.nb_outputs());
+        for b in 0..inputs.rows {
+            let row = outputs.row_mut(b);
+            row[..conv_size].copy_from_slice(&z.data[b * conv_size..(b + 1) * conv_size]);
+            row[conv_size..].copy_from_slice(&inputs.row(b)[planes_size..]);
+        }
+        (
+            outputs,
+            Box::new(ConvCache {
+                patches,
+                derivatives,
+            }),
+        )
+    }
+
+    /// Same as a dense layer over the patches; the patch gradients are then
+    /// scattered back onto the squares they were read from (col2im)
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let cache: &ConvCache<T> = cache_ref(cache);
+        let positions = self.nb_positions();
+        let conv_size = positions * self.channels();
+        let planes_size = self.nb_inputs() - self.extra;
+
+        let mut deltas = Matrix::zeros(cache.derivatives.rows, cache.derivatives.cols);
+        for b in 0..output_grads.rows {
+            deltas.data[b * conv_size..(b + 1) * conv_size]
+                .copy_from_slice(&output_grads.row(b)[..conv_size]);
+        }
+        for (delta, &d) in deltas.data.iter_mut().zip(cache.derivatives.data.iter()) {
+            *delta *= d;
+        }
+
+        let input_grads = need_input_grads.then(|| {
+            let patch_grads = deltas.mul(&self.weights);
+            let mut input_grads = Matrix::zeros(output_grads.rows, self.nb_inputs());
+            for b in 0..output_grads.rows {
+                let row = input_grads.row_mut(b);
+                for p in 0..positions {
+                    for (&g, index) in patch_grads
+                        .row(b * positions + p)
+                        .iter()
+                        .zip(self.patch_indices(p))
+                    {
+                        if let Some(index) = index {
+                            row[index] += g;
+                        }
+                    }
+                }
+                row[planes_size..].copy_from_slice(&output_grads.row(b)[conv_size..]);
+            }
+            input_grads
+        });
+
+        let weight_grads = deltas.transpose_mul(&cache.patches);
+        (input_grads, vec![weight_grads.data, deltas.column_sums()])
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![
+            (ParamKind::Weight, &self.weights.data),
+            (ParamKind::Bias, &self.biases),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![
+            (ParamKind::Weight, &mut self.weights.data),
+            (ParamKind::Bias, &mut self.biases),
+        ]
+    }
+
+    fn clip_weight_norms(&mut self, max_norm: f64) {
+        self.weights.clip_row_norms(max_norm);
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("conv2d")
+            .with_attribute("height", self.height)
+            .with_attribute("width", self.width)
+            .with_attribute("in_channels", self.in_channels)
+            .with_attribute("channels", self.channels())
+            .with_attribute("kernel", self.kernel)
+            .with_attribute("stride", self.stride)
+            .with_attribute("padding", self.padding)
+            .with_attribute("extra", self.extra)
+            .with_attribute("activation", self.activation)
+            .with_tensor("weights", &self.weights.data)
+            .with_tensor("biases", &self.biases)
+    }
+}
+
+/// Marks the end of the convolutions: the planes become a plain vector for
+/// the dense head. Planes are already stored flat, so values are unchanged.
+pub struct Flatten {
+    pub size: usize,
+}
+
+impl Flatten {
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        Ok(Flatten {
+            size: record.attribute("size")?,
+        })
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Flatten {
+    fn kind(&self) -> &'static str {
+        "flatten"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.size
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.size
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        (inputs.clone(), Box::new(()))
+    }
+
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        _cache: &Cache,
+        output_grads: &Matrix<T>,
+        _need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        (Some(output_grads.clone()), vec![])
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("flatten").with_attribute("size", self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_shape_and_passthrough() {
+        let conv =
+            Conv2D::<f64>::new_random_he((8, 8, 13), 1, 4, 3, 2, 1, Activation::Relu).unwrap();
+        assert_eq!(conv.output_dims(), (4, 4));
+        assert_eq!(conv.nb_inputs(), 833);
+        assert_eq!(conv.nb_outputs(), 4 * 4 * 4 + 1);
+
+        let mut inputs = Matrix::zeros(2, 833);
+        inputs.row_mut(0)[832] = 1.0;
+        let (outputs, _) = conv.forward(&inputs);
+        assert_eq!(outputs.row(0)[64], 1.0);
+        assert_eq!(outputs.row(1)[64], 0.0);
+
+        assert!(Conv2D::<f64>::new_random_he((8, 8, 13), 1, 4, 9, 1, 0, Activation::Relu).is_err());
+    }
+
+    #[test]
+    fn test_backward_matches_finite_differences() {
+        let mut conv =
+            Conv2D::<f64>::new_random_he((3, 4, 2), 1, 2, 2, 1, 1, Activation::Tanh).unwrap();
+        conv.biases = vec![0.1, -0.2];
+        let inputs = Matrix::new(
+            2,
+            conv.nb_inputs(),
+            (0..2 * conv.nb_inputs())
+                .map(|i| ((i * 7 % 11) as f64 - 5.0) / 5.0)
+                .collect(),
+        );
+        let weights = Matrix::new(
+            2,
+            conv.nb_outputs(),
+            (0..2 * conv.nb_outputs())
+                .map(|i| ((i * 5 % 9) as f64 - 4.0) / 4.0)
+                .collect(),
+        );
+        let loss = |conv: &Conv2D, x: &Matrix| -> f64 {
+            let (out, _) = conv.forward(x);
+            out.data
+                .iter()
+                .zip(weights.data.iter())
+                .map(|(o, w)| o * w)
+                .sum()
+        };
+
+        let (_, cache) = conv.forward(&inputs);
+        let (input_grads, param_grads) = conv.backward(&inputs, &cache, &weights, true);
+        let input_grads = input_grads.unwrap();
+
+        let h = 1e-6;
+        for i in 0..inputs.data.len() {
+            let mut plus = inputs.clone();
+            plus.data[i] += h;
+            let mut minus = inputs.clone();
+            minus.data[i] -= h;
+            let numeric = (loss(&conv, &plus) - loss(&conv, &minus)) / (2.0 * h);
+            assert!((numeric - input_grads.data[i]).abs() < 1e-5);
+        }
+        for (i, analytic) in param_grads[0].iter().enumerate() {
+            let original = conv.weights.data[i];
+            conv.weights.data[i] = original + h;
+            let plus = loss(&conv, &inputs);
+            conv.weights.data[i] = original - h;
+            let minus = loss(&conv, &inputs);
+            conv.weights.data[i] = original;
+            assert!(((plus - minus) / (2.0 * h) - analytic).abs() < 1e-5);
+        }
+    }
+}
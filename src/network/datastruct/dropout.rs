@@ -0,0 +1,103 @@
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::r#trait::{cache_ref, Cache, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::random;
+use rand::Rng;
+
+/// Inverted dropout: in training mode each value is zeroed with probability
+/// `rate` and the kept ones are scaled by `1 / (1 - rate)`, so inference is
+/// the identity.
+pub struct Dropout {
+    pub size: usize,
+    pub rate: f64,
+    pub training_mode: bool,
+}
+
+impl Dropout {
+    pub fn new(size: usize, rate: f64) -> Self {
+        Dropout {
+            size,
+            rate,
+            training_mode: false,
+        }
+    }
+
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let rate: f64 = record.attribute("rate")?;
+        if !(0.0..1.0).contains(&rate) {
+            return Err(format!(
+                "dropout: invalid rate {} (must be 0 <= rate < 1)",
+                rate
+            ));
+        }
+        Ok(Dropout::new(record.attribute("size")?, rate))
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Dropout {
+    fn kind(&self) -> &'static str {
+        "dropout"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.size
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.size
+    }
+
+    fn set_training_mode(&mut self, training: bool) {
+        self.training_mode = training;
+    }
+
+    /// The cache is the scale applied to each value (0 or `1 / keep_prob`),
+    /// `None` when nothing was dropped
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        if !self.training_mode || self.rate == 0.0 {
+            return (inputs.clone(), Box::new(None::<Vec<T>>));
+        }
+
+        let mut rng = random::rng();
+        let keep_prob = 1.0 - self.rate;
+        let scale = T::cast(1.0 / keep_prob);
+        let mask: Vec<T> = (0..inputs.data.len())
+            .map(|_| {
+                if rng.gen::<f64>() < keep_prob {
+                    scale
+                } else {
+                    T::zero()
+                }
+            })
+            .collect();
+
+        let mut outputs = inputs.clone();
+        for (out, &m) in outputs.data.iter_mut().zip(mask.iter()) {
+            *out *= m;
+        }
+        (outputs, Box::new(Some(mask)))
+    }
+
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        _need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let mut input_grads = output_grads.clone();
+        if let Some(mask) = cache_ref::<Option<Vec<T>>>(cache) {
+            for (g, &m) in input_grads.data.iter_mut().zip(mask.iter()) {
+                *g *= m;
+            }
+        }
+        (Some(input_grads), vec![])
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("dropout")
+            .with_attribute("size", self.size)
+            .with_attribute("rate", self.rate)
+    }
+}
@@ -1,145 +1,220 @@
-use crate::network::datastruct::perceptron::{get_derivative, Perceptron};
-use rand::thread_rng;
-use rand::Rng;
-
-pub struct Layer {
-    pub neurons: Vec<Perceptron>,
-    pub dropout_rate: f64,
-    pub training_mode: bool,
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::parse_error::ParseError;
+use crate::network::datastruct::perceptron::Perceptron;
+use crate::network::datastruct::r#trait::{cache_ref, Cache, LinearInput, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+
+/// Fully connected layer.
+///
+/// Weights are one dense row-major matrix (`nb_neurons × nb_inputs`, one
+/// neuron per row) plus a bias vector, so forward and backward passes are
+/// plain matrix products instead of a walk over individual perceptrons.
+pub struct Layer<T = f64> {
+    pub activation: Activation,
+    pub weights: Matrix<T>,
+    pub biases: Vec<T>,
 }
 
-impl Layer {
-    #[allow(dead_code)]
-    pub fn neurons(&self) -> &Vec<Perceptron> {
-        &self.neurons
-    }
+impl<T: Float> Layer<T> {
+    /// Parse a layer block, one neuron per line. Blank lines are ignored.
+    pub fn new(config: String) -> Result<Self, ParseError> {
+        let mut neurons: Vec<Perceptron<T>> = vec![];
+
+        for (line_idx, line) in config.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let at = |e: ParseError| e.at_neuron(neurons.len()).at_line(line_idx + 1);
+
+            let neuron = Perceptron::new(String::from(line)).map_err(at)?;
+            if let Some(first) = neurons.first() {
+                if neuron.activation != first.activation {
+                    return Err(at(ParseError::new(format!(
+                        "activation '{}' differs from the layer's '{}'",
+                        neuron.activation, first.activation
+                    ))
+                    .at_token(0, 1)));
+                }
+                if neuron.weights.len() != first.weights.len() {
+                    return Err(at(ParseError::new(format!(
+                        "{} weights, but neuron 0 of this layer has {}",
+                        neuron.weights.len(),
+                        first.weights.len()
+                    ))));
+                }
+            }
+            neurons.push(neuron);
+        }
 
-    #[allow(dead_code)]
-    pub fn neurons_mut(&mut self) -> &mut Vec<Perceptron> {
-        &mut self.neurons
+        if neurons.is_empty() {
+            return Err(ParseError::new(String::from("layer has no neurons")).at_line(1));
+        }
+        Self::from_perceptrons(neurons).map_err(ParseError::new)
     }
-}
 
-impl Layer {
-    pub fn new(config: String) -> Result<Self, String> {
+    /// Pack neurons into the layer matrix. Every neuron of a layer must share
+    /// the same activation and the same number of weights.
+    pub fn from_perceptrons(neurons: Vec<Perceptron<T>>) -> Result<Self, String> {
+        let first = neurons.first().ok_or("empty layer")?;
+        let activation = first.activation;
+        let nb_inputs = first.weights.len();
+
+        let mut data = Vec::with_capacity(neurons.len() * nb_inputs);
+        let mut biases = Vec::with_capacity(neurons.len());
+        for (idx, neuron) in neurons.iter().enumerate() {
+            if neuron.activation != activation {
+                return Err(format!(
+                    "neuron {} uses '{}' but the layer uses '{}'",
+                    idx, neuron.activation, activation
+                ));
+            }
+            if neuron.weights.len() != nb_inputs {
+                return Err(format!(
+                    "neuron {} has {} weights, expected {}",
+                    idx,
+                    neuron.weights.len(),
+                    nb_inputs
+                ));
+            }
+            data.extend_from_slice(&neuron.weights);
+            biases.push(neuron.biais);
+        }
+
         Ok(Layer {
-            neurons: config
-                .split("\n")
-                .map(|line| Perceptron::new(String::from(line)).unwrap())
-                .collect(),
-            dropout_rate: 0.0,
-            training_mode: false,
+            activation,
+            weights: Matrix::new(neurons.len(), nb_inputs, data),
+            biases,
         })
     }
 
-    #[allow(dead_code)]
-    pub fn new_random_with_activation(
-        nb_perceptron: u32,
-        nb_weight: u32,
-        w_range: &(f64, f64),
-        b_range: &(f64, f64),
-        activation: &str,
-    ) -> Layer {
-        Layer {
-            neurons: (0..nb_perceptron)
-                .map(|_| {
-                    Perceptron::new_random_with_activation(nb_weight, w_range, b_range, activation)
-                })
-                .collect(),
-            dropout_rate: 0.0,
-            training_mode: false,
-        }
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let nb_inputs: usize = record.attribute("inputs")?;
+        let nb_outputs: usize = record.attribute("outputs")?;
+        Ok(Layer {
+            activation: record.attribute("activation")?,
+            weights: Matrix::new(
+                nb_outputs,
+                nb_inputs,
+                record.tensor("weights", nb_outputs * nb_inputs)?,
+            ),
+            biases: record.tensor("biases", nb_outputs)?,
+        })
     }
 
-    pub fn new_random_he(nb_perceptron: u32, nb_weight: u32, activation: &str) -> Layer {
-        Layer {
-            neurons: (0..nb_perceptron)
+    pub fn new_random_he(nb_perceptron: u32, nb_weight: u32, activation: Activation) -> Self {
+        Self::from_perceptrons(
+            (0..nb_perceptron)
                 .map(|_| Perceptron::new_random_he(nb_weight, activation))
                 .collect(),
-            dropout_rate: 0.0,
-            training_mode: false,
-        }
+        )
+        .unwrap()
     }
+}
 
-    pub fn set_dropout(&mut self, rate: f64) {
-        self.dropout_rate = rate;
+impl<T: Float> NetworkElement<T> for Layer<T> {
+    fn kind(&self) -> &'static str {
+        "dense"
     }
 
-    pub fn set_training_mode(&mut self, training: bool) {
-        self.training_mode = training;
+    fn nb_inputs(&self) -> usize {
+        self.weights.cols
     }
 
-    pub fn exec(&self, inputs: Vec<f64>) -> Vec<f64> {
-        self.neurons.iter().map(|p| p.exec(&inputs)).collect()
+    fn nb_outputs(&self) -> usize {
+        self.weights.rows
     }
 
-    pub fn forward(&self, inputs: &Vec<f64>) -> Vec<f64> {
-        let outputs: Vec<f64> = self.neurons.iter().map(|p| p.exec(&inputs)).collect();
-
-        if self.training_mode && self.dropout_rate > 0.0 {
-            let mut rng = thread_rng();
-            let keep_prob = 1.0 - self.dropout_rate;
+    /// Outputs of the batch, caching dy/dz for every output (z being the
+    /// pre-activation)
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        let mut outputs = inputs.mul_transposed(&self.weights);
+        let mut derivatives = Matrix::zeros(outputs.rows, outputs.cols);
+        for r in 0..outputs.rows {
+            for ((out, d), &b) in outputs
+                .row_mut(r)
+                .iter_mut()
+                .zip(derivatives.row_mut(r).iter_mut())
+                .zip(self.biases.iter())
+            {
+                let z = *out + b;
+                *out = self.activation.apply(z);
+                *d = self.activation.derivative(z, *out);
+            }
+        }
+        (outputs, Box::new(derivatives))
+    }
 
-            outputs
-                .iter()
-                .map(|&output| {
-                    if rng.gen::<f64>() < keep_prob {
-                        output / keep_prob
-                    } else {
-                        0.0
-                    }
-                })
-                .collect()
-        } else {
-            outputs
+    /// With `δ = output_grads ⊙ f'`: weight gradients are `δᵀ × inputs`,
+    /// bias gradients the column sums of `δ`, input gradients `δ × weights`
+    fn backward(
+        &self,
+        inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let derivatives: &Matrix<T> = cache_ref(cache);
+        let mut deltas = output_grads.clone();
+        for (delta, &d) in deltas.data.iter_mut().zip(derivatives.data.iter()) {
+            *delta *= d;
         }
+
+        let input_grads = need_input_grads.then(|| deltas.mul(&self.weights));
+        let weight_grads = deltas.transpose_mul(inputs);
+        (input_grads, vec![weight_grads.data, deltas.column_sums()])
     }
 
-    #[allow(dead_code)]
-    pub fn backward_output(&self, outputs: &Vec<f64>, targets: &Vec<f64>) -> Vec<f64> {
-        let mut deltas: Vec<f64> = vec![];
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![
+            (ParamKind::Weight, &self.weights.data),
+            (ParamKind::Bias, &self.biases),
+        ]
+    }
 
-        for index in 0..outputs.len() {
-            let derivative = get_derivative(&self.neurons[index].func_id);
-            let output_error = targets[index] - outputs[index];
-            let output_delta = output_error * derivative(outputs[index]);
-            deltas.push(output_delta);
-        }
-        deltas
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![
+            (ParamKind::Weight, &mut self.weights.data),
+            (ParamKind::Bias, &mut self.biases),
+        ]
     }
 
-    pub fn backward_hidden(
-        &self,
-        outputs: &Vec<f64>,
-        next_deltas: &Vec<f64>,
-        next_layer: &Layer,
-    ) -> Vec<f64> {
-        let mut deltas: Vec<f64> = vec![];
-        for i in 0..self.neurons.len() {
-            let derivative = get_derivative(&self.neurons[i].func_id);
-            let mut error_sum = 0.;
-            for j in 0..next_layer.neurons.len() {
-                error_sum += next_layer.neurons[j].weights[i] * next_deltas[j];
-            }
-            deltas.push(error_sum * derivative(outputs[i]));
-        }
-        deltas
+    fn clip_weight_norms(&mut self, max_norm: f64) {
+        self.weights.clip_row_norms(max_norm);
     }
 
-    pub fn update_weights(&mut self, deltas: &Vec<f64>, inputs: &Vec<f64>, learning_rate: f64) {
-        for i in 0..self.neurons.len() {
-            for j in 0..self.neurons[i].weights.len() {
-                self.neurons[i].weights[j] += learning_rate * deltas[i] * inputs[j];
-            }
-            self.neurons[i].biais += learning_rate * deltas[i];
+    fn as_linear_input(&self) -> Option<&dyn LinearInput<T>> {
+        Some(self)
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("dense")
+            .with_attribute("activation", self.activation)
+            .with_attribute("inputs", self.nb_inputs())
+            .with_attribute("outputs", self.nb_outputs())
+            .with_tensor("weights", &self.weights.data)
+            .with_tensor("biases", &self.biases)
+    }
+}
+
+impl<T: Float> LinearInput<T> for Layer<T> {
+    fn biases(&self) -> &[T] {
+        &self.biases
+    }
+
+    /// Walks the input's column, one weight per neuron row
+    fn add_input(&self, sums: &mut [T], input: usize, scale: T) {
+        for (sum, neuron) in sums
+            .iter_mut()
+            .zip(self.weights.data.chunks(self.weights.cols))
+        {
+            *sum += scale * neuron[input];
         }
     }
 
-    pub fn to_string(&self) -> String {
-        self.neurons
-            .iter()
-            .map(|p| p.to_string())
-            .collect::<Vec<_>>()
-            .join("\n")
+    fn activation(&self) -> Activation {
+        self.activation
     }
 }
@@ -0,0 +1,141 @@
+use crate::network::autodiff::{self, Differentiable, Tape, Var};
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::r#trait::{Cache, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+
+pub const DEFAULT_EPSILON: f64 = 1e-5;
+
+/// Layer normalization: each sample is normalized over its own features,
+/// then scaled by `gamma` and shifted by `beta`. Behaves the same in
+/// training and inference.
+pub struct LayerNorm<T = f64> {
+    pub gamma: Vec<T>,
+    pub beta: Vec<T>,
+    pub epsilon: f64,
+}
+
+impl<T: Float> LayerNorm<T> {
+    pub fn new(size: usize) -> Self {
+        LayerNorm {
+            gamma: vec![T::one(); size],
+            beta: vec![T::zero(); size],
+            epsilon: DEFAULT_EPSILON,
+        }
+    }
+
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let size: usize = record.attribute("size")?;
+        Ok(LayerNorm {
+            gamma: record.tensor("gamma", size)?,
+            beta: record.tensor("beta", size)?,
+            epsilon: record.attribute("epsilon")?,
+        })
+    }
+}
+
+impl<T: Float> Differentiable<T> for LayerNorm<T> {
+    /// `gamma ⊙ (x - mean) / sqrt(var + eps) + beta`, row by row
+    fn record(&self, tape: &mut Tape<T>, inputs: Var) -> (Var, Vec<Var>) {
+        let gamma = tape.leaf(Matrix::new(1, self.gamma.len(), self.gamma.clone()));
+        let beta = tape.leaf(Matrix::new(1, self.beta.len(), self.beta.clone()));
+
+        let mean = tape.row_mean(inputs);
+        let centered = tape.sub(inputs, mean);
+        let squares = tape.mul(centered, centered);
+        let var = tape.row_mean(squares);
+        let shifted = tape.add_scalar(var, self.epsilon);
+        let inv_std = tape.powf(shifted, -0.5);
+        let normalized = tape.mul(centered, inv_std);
+        let scaled = tape.mul(normalized, gamma);
+        (tape.add(scaled, beta), vec![gamma, beta])
+    }
+}
+
+impl<T: Float> NetworkElement<T> for LayerNorm<T> {
+    fn kind(&self) -> &'static str {
+        "layernorm"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.gamma.len()
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.gamma.len()
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        autodiff::forward(self, inputs)
+    }
+
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        autodiff::backward(self, cache, output_grads, need_input_grads)
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![
+            (ParamKind::Scale, &self.gamma),
+            (ParamKind::Bias, &self.beta),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![
+            (ParamKind::Scale, &mut self.gamma),
+            (ParamKind::Bias, &mut self.beta),
+        ]
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("layernorm")
+            .with_attribute("size", self.gamma.len())
+            .with_attribute("epsilon", self.epsilon)
+            .with_tensor("gamma", &self.gamma)
+            .with_tensor("beta", &self.beta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backward_matches_finite_differences() {
+        let mut norm = LayerNorm::<f64>::new(4);
+        norm.gamma = vec![0.5, -1.0, 2.0, 1.5];
+        norm.beta = vec![0.1, 0.0, -0.2, 0.3];
+        let inputs = Matrix::new(2, 4, vec![0.3, -1.2, 2.0, 0.7, 1.0, 1.5, -0.5, 0.0]);
+        // Loss: weighted sum of the outputs
+        let weights = Matrix::new(2, 4, vec![1.0, 2.0, -1.0, 0.5, -0.3, 0.8, 1.2, -2.0]);
+        let loss = |x: &Matrix| -> f64 {
+            let (out, _) = norm.forward(x);
+            out.data
+                .iter()
+                .zip(weights.data.iter())
+                .map(|(o, w)| o * w)
+                .sum()
+        };
+
+        let (_, cache) = norm.forward(&inputs);
+        let (input_grads, _) = norm.backward(&inputs, &cache, &weights, true);
+        let input_grads = input_grads.unwrap();
+
+        let h = 1e-6;
+        for i in 0..inputs.data.len() {
+            let mut plus = inputs.clone();
+            plus.data[i] += h;
+            let mut minus = inputs.clone();
+            minus.data[i] -= h;
+            let numeric = (loss(&plus) - loss(&minus)) / (2.0 * h);
+            assert!((numeric - input_grads.data[i]).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::network::float::Float;
+
+/// Dense row-major matrix used for layer weights and mini-batches.
+///
+/// A batch is stored with one sample per row, and a dense layer stores one
+/// neuron per row, so every hot loop below walks memory contiguously.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix<T = f64> {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<T>,
+}
+
+impl<T: Float> Matrix<T> {
+    pub fn new(rows: usize, cols: usize, data: Vec<T>) -> Self {
+        assert_eq!(
+            rows * cols,
+            data.len(),
+            "matrix data does not match its shape"
+        );
+        Matrix { rows, cols, data }
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Matrix {
+            rows,
+            cols,
+            data: vec![T::zero(); rows * cols],
+        }
+    }
+
+    /// Build a matrix from equally sized rows (e.g. the samples of a batch)
+    pub fn from_rows(rows: &[Vec<T>]) -> Self {
+        let cols = rows.first().map_or(0, |r| r.len());
+        let mut data = Vec::with_capacity(rows.len() * cols);
+        for row in rows {
+            assert_eq!(row.len(), cols, "rows must have the same length");
+            data.extend_from_slice(row);
+        }
+        Matrix {
+            rows: rows.len(),
+            cols,
+            data,
+        }
+    }
+
+    pub fn row(&self, index: usize) -> &[T] {
+        &self.data[index * self.cols..(index + 1) * self.cols]
+    }
+
+    pub fn row_mut(&mut self, index: usize) -> &mut [T] {
+        &mut self.data[index * self.cols..(index + 1) * self.cols]
+    }
+
+    /// `self * v`
+    pub fn mul_vec(&self, v: &[T]) -> Vec<T> {
+        assert_eq!(self.cols, v.len());
+        (0..self.rows).map(|r| dot(self.row(r), v)).collect()
+    }
+
+    /// `self * otherᵀ`, e.g. a batch (B×in) times the weights (out×in)
+    pub fn mul_transposed(&self, other: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(self.cols, other.cols);
+        let mut result = Matrix::zeros(self.rows, other.rows);
+        for r in 0..self.rows {
+            let a = self.row(r);
+            for (c, out) in result.row_mut(r).iter_mut().enumerate() {
+                *out = dot(a, other.row(c));
+            }
+        }
+        result
+    }
+
+    /// `self * other`, e.g. deltas (B×out) times the weights (out×in)
+    pub fn mul(&self, other: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(self.cols, other.rows);
+        let mut result = Matrix::zeros(self.rows, other.cols);
+        for r in 0..self.rows {
+            let out = result.row_mut(r);
+            for (k, &a) in self.row(r).iter().enumerate() {
+                if a != T::zero() {
+                    axpy(out, a, other.row(k));
+                }
+            }
+        }
+        result
+    }
+
+    /// `selfᵀ * other`, e.g. deltas (B×out) against the inputs (B×in)
+    pub fn transpose_mul(&self, other: &Matrix<T>) -> Matrix<T> {
+        assert_eq!(self.rows, other.rows);
+        let mut result = Matrix::zeros(self.cols, other.cols);
+        for b in 0..self.rows {
+            let x = other.row(b);
+            for (r, &a) in self.row(b).iter().enumerate() {
+                if a != T::zero() {
+                    axpy(result.row_mut(r), a, x);
+                }
+            }
+        }
+        result
+    }
+
+    /// Sum of every row, i.e. a vector of length `cols`
+    pub fn column_sums(&self) -> Vec<T> {
+        let mut sums = vec![T::zero(); self.cols];
+        for r in 0..self.rows {
+            axpy(&mut sums, T::one(), self.row(r));
+        }
+        sums
+    }
+
+    /// Scale down every row whose L2 norm exceeds `max_norm` to that norm
+    pub fn clip_row_norms(&mut self, max_norm: f64) {
+        let max_norm = T::cast(max_norm);
+        for r in 0..self.rows {
+            let row = self.row_mut(r);
+            let norm = dot(row, row).sqrt();
+            if norm > max_norm {
+                let scale = max_norm / norm;
+                row.iter_mut().for_each(|x| *x *= scale);
+            }
+        }
+    }
+
+    /// Same as `clip_row_norms` for columns
+    pub fn clip_column_norms(&mut self, max_norm: f64) {
+        let max_norm = T::cast(max_norm);
+        let mut norms = vec![T::zero(); self.cols];
+        for r in 0..self.rows {
+            for (n, &x) in norms.iter_mut().zip(self.row(r)) {
+                *n += x * x;
+            }
+        }
+        let scales: Vec<T> = norms
+            .iter()
+            .map(|n| max_norm / n.sqrt().max(max_norm))
+            .collect();
+        for r in 0..self.rows {
+            for (x, scale) in self.row_mut(r).iter_mut().zip(&scales) {
+                *x *= *scale;
+            }
+        }
+    }
+}
+
+pub fn dot<T: Float>(a: &[T], b: &[T]) -> T {
+    a.iter()
+        .zip(b.iter())
+        .fold(T::zero(), |acc, (&x, &y)| acc + x * y)
+}
+
+/// `y += a * x`
+pub fn axpy<T: Float>(y: &mut [T], a: T, x: &[T]) {
+    for (y, &x) in y.iter_mut().zip(x.iter()) {
+        *y += a * x;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_products() {
+        let a = Matrix::new(2, 3, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = Matrix::new(2, 3, vec![1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+
+        assert_eq!(a.mul_vec(&[1.0, 1.0, 1.0]), vec![6.0, 15.0]);
+        assert_eq!(a.mul_transposed(&b).data, vec![4.0, 2.0, 10.0, 5.0]);
+        assert_eq!(
+            a.transpose_mul(&b).data,
+            vec![1.0, 4.0, 1.0, 2.0, 5.0, 2.0, 3.0, 6.0, 3.0]
+        );
+
+        let c = Matrix::new(3, 2, vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(a.mul(&c).data, vec![4.0, 5.0, 10.0, 11.0]);
+        assert_eq!(a.column_sums(), vec![5.0, 7.0, 9.0]);
+    }
+
+    #[test]
+    fn test_clip_norms() {
+        let mut a: Matrix = Matrix::new(2, 2, vec![3.0, 4.0, 0.6, 0.8]);
+        a.clip_row_norms(2.0);
+        let mut b: Matrix = Matrix::new(2, 2, vec![3.0, 0.6, 4.0, 0.8]);
+        b.clip_column_norms(2.0);
+        for (x, y) in a.data.iter().zip(&[1.2, 1.6, 0.6, 0.8]) {
+            assert!((x - y).abs() < 1e-12);
+        }
+        for (x, y) in b.data.iter().zip(&[1.2, 0.6, 1.6, 0.8]) {
+            assert!((x - y).abs() < 1e-12);
+        }
+    }
+}
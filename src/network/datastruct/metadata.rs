@@ -0,0 +1,138 @@
+use crate::network::datastruct::parse_error::ParseError;
+use std::fmt;
+
+pub const INPUT_ENCODING: &str = "input_encoding";
+pub const INPUT_SIZE: &str = "input_size";
+pub const LABELS: &str = "labels";
+pub const DROPOUT_RATES: &str = "dropout_rates";
+pub const TRAINED_AT: &str = "trained_at";
+pub const DATASET_HASH: &str = "dataset_hash";
+/// Prefix of the entries copied from the training configuration
+pub const TRAINING_PREFIX: &str = "training.";
+
+/// First line of the metadata block of a text network file
+pub const TEXT_HEADER: &str = "#metadata";
+
+/// Free-form `key = value` description stored alongside the weights.
+///
+/// Entries keep their insertion order so files stay diffable. Well-known keys
+/// are listed above; anything else is carried through untouched.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Insert or replace `key`
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = String::from(value),
+            None => self.entries.push((String::from(key), String::from(value))),
+        }
+    }
+
+    pub fn remove_prefix(&mut self, prefix: &str) {
+        self.entries.retain(|(k, _)| !k.starts_with(prefix));
+    }
+
+    pub fn labels(&self) -> Option<Vec<String>> {
+        self.get(LABELS).map(|v| {
+            v.split(',')
+                .map(|label| label.trim().to_string())
+                .filter(|label| !label.is_empty())
+                .collect()
+        })
+    }
+
+    pub fn set_labels(&mut self, labels: &[&str]) {
+        self.set(LABELS, &labels.join(", "));
+    }
+
+    /// Dropout rates of the dense layers, as recorded by files saved before
+    /// dropout became a layer of its own
+    pub fn dropout_rates(&self) -> Result<Option<Vec<f64>>, String> {
+        match self.get(DROPOUT_RATES) {
+            None => Ok(None),
+            Some(value) => value
+                .split(',')
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(|x| {
+                    x.parse()
+                        .map_err(|_| format!("Invalid {} metadata: {}", DROPOUT_RATES, value))
+                })
+                .collect::<Result<Vec<f64>, String>>()
+                .map(Some),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+
+    /// Parse a text metadata block, `#metadata` header line included
+    pub fn parse(content: &str) -> Result<Self, ParseError> {
+        let mut metadata = Metadata::default();
+        for (line_num, line) in content.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                ParseError::new(format!("expected KEY = VALUE in metadata, got '{}'", line))
+                    .at_line(line_num + 1)
+            })?;
+            metadata.set(key.trim(), value.trim());
+        }
+        Ok(metadata)
+    }
+}
+
+/// Text metadata block, header line included
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", TEXT_HEADER)?;
+        for (key, value) in &self.entries {
+            write!(f, "\n{} = {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut metadata = Metadata::default();
+        metadata.set(INPUT_SIZE, "833");
+        metadata.set_labels(&["Nothing", "Check White"]);
+        metadata.set(DROPOUT_RATES, "0.3, 0");
+        metadata.set(INPUT_SIZE, "834");
+
+        let parsed = Metadata::parse(&metadata.to_string()).unwrap();
+
+        assert_eq!(parsed, metadata);
+        assert_eq!(parsed.get(INPUT_SIZE), Some("834"));
+        assert_eq!(
+            parsed.labels().unwrap(),
+            vec!["Nothing".to_string(), "Check White".to_string()]
+        );
+        assert_eq!(parsed.dropout_rates().unwrap(), Some(vec![0.3, 0.0]));
+    }
+}
@@ -1,3 +1,19 @@
+pub mod accumulator;
+pub mod activation;
+pub mod architecture;
+pub mod batch_norm;
+pub mod binary;
+pub mod conv;
+pub mod dropout;
 pub mod layer;
+pub mod layer_norm;
+pub mod matrix;
+pub mod metadata;
 pub mod network;
+pub mod parse_error;
 pub mod perceptron;
+pub mod record;
+pub mod residual;
+pub mod sparse;
+pub mod r#trait;
+pub mod transformer;
@@ -1,280 +1,570 @@
+use std::fmt;
 use std::fs;
 use std::vec;
 
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::architecture::{LayerSpec, Shape};
+use crate::network::datastruct::binary::{self, ModelFormat};
+use crate::network::datastruct::dropout::Dropout;
 use crate::network::datastruct::layer::Layer;
+use crate::network::datastruct::matrix::{axpy, dot, Matrix};
+use crate::network::datastruct::metadata::{self, Metadata};
+use crate::network::datastruct::parse_error::ParseError;
+use crate::network::datastruct::r#trait::{Cache, NetworkElement};
+use crate::network::datastruct::record::{self, LayerRecord};
+use crate::network::float::Float;
+use crate::network::loss::Loss;
+use crate::network::optimizer::{Optimizer, ParamKind};
+use crate::network::random;
+use crate::network::regularization::Regularization;
+use rand::Rng;
+use rayon::prelude::*;
+
+/// Examples in each chunk a training batch is split into: fixed, so the
+/// chunks do not depend on the thread count, and small enough that a batch
+/// keeps every core busy. Chunks only change the order in which gradients
+/// are summed: networks with batch normalization are not split.
+const BATCH_CHUNK_SIZE: usize = 8;
+
+/// Stack of layers computing in precision `T` (`f64` or `f32`)
+pub struct Network<T: Float = f64> {
+    pub layers: Vec<Box<dyn NetworkElement<T>>>,
+    pub metadata: Metadata,
+}
 
-pub struct Network(pub Vec<Layer>);
+/// Parameter gradients of every layer, in `parameters()` order, with the
+/// batch statistics each layer collected (one entry per chunk)
+pub struct Gradients<T = f64> {
+    pub layers: Vec<Vec<Vec<T>>>,
+    pub statistics: Vec<Vec<Vec<T>>>,
+}
 
-impl Network {
-    pub fn new(config: String) -> Result<Self, String> {
-        Ok(Network(
-            config
-                .split("\n---\n")
-                .map(|line| Layer::new(String::from(line)).unwrap())
-                .collect(),
-        ))
+impl<T: Float> Gradients<T> {
+    pub fn add(&mut self, other: &Gradients<T>) {
+        for (layer, other_layer) in self.layers.iter_mut().zip(other.layers.iter()) {
+            for (g, other_g) in layer.iter_mut().zip(other_layer.iter()) {
+                axpy(g, T::one(), other_g);
+            }
+        }
+        for (stats, other_stats) in self.statistics.iter_mut().zip(other.statistics.iter()) {
+            stats.extend(other_stats.iter().cloned());
+        }
     }
 
-    pub fn new_random_he(
-        mut nb_input: u32,
-        nb_perceptron: Vec<u32>,
-        dropout_rates: Vec<f64>,
-        output_activation: &str,
-    ) -> Network {
-        let num_layers = nb_perceptron.len();
-        let mut layers: Vec<Layer> = nb_perceptron
+    pub fn scale(&mut self, factor: f64) {
+        let factor = T::cast(factor);
+        self.layers
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .for_each(|g| *g *= factor);
+    }
+
+    /// L2 norm of all the parameter gradients together
+    pub fn norm(&self) -> f64 {
+        self.layers
             .iter()
-            .enumerate()
-            .map(|(idx, &nb)| {
-                let activation = if idx == num_layers - 1 {
-                    output_activation
-                } else {
-                    "relu" // Use ReLU for hidden layers
-                };
+            .flatten()
+            .map(|g| dot(g, g).as_f64())
+            .sum::<f64>()
+            .sqrt()
+    }
+}
 
-                let layer = Layer::new_random_he(nb, nb_input, activation);
-                nb_input = nb;
-                layer
-            })
-            .collect();
+/// Limits on the averaged gradients of a step, 0 disabling a limit
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GradientClipping {
+    /// Largest global norm: larger gradients are scaled down as a whole,
+    /// keeping their direction
+    pub norm: f64,
+    /// Largest absolute value of a single gradient
+    pub value: f64,
+}
 
-        for (idx, layer) in layers.iter_mut().enumerate() {
-            if idx < dropout_rates.len() {
-                layer.set_dropout(dropout_rates[idx]);
-            }
+impl GradientClipping {
+    pub fn apply<T: Float>(&self, gradients: &mut Gradients<T>, norm: f64) {
+        if self.norm > 0.0 && norm > self.norm {
+            gradients.scale(self.norm / norm);
         }
+        if self.value > 0.0 {
+            let value = T::cast(self.value);
+            gradients
+                .layers
+                .iter_mut()
+                .flatten()
+                .flatten()
+                .for_each(|g| *g = g.clamp(-value, value));
+        }
+    }
+}
 
-        Network(layers)
+/// Exact copy through the binary format of the network's own precision,
+/// which keeps every layer's parameters and statistics
+impl<T: Float> Clone for Network<T> {
+    fn clone(&self) -> Self {
+        binary::decode(&binary::encode(self, T::PRECISION))
+            .expect("a network decodes its own encoding")
     }
+}
 
-    pub fn set_training_mode(&mut self, training: bool) {
-        for layer in &mut self.0 {
-            layer.set_training_mode(training);
+impl<T: Float> Network<T> {
+    /// Parse a text network file: an optional metadata block, then one block
+    /// per layer, blocks being separated by `---` lines. Dense layers are
+    /// plain neuron lines, other kinds start with an `@kind` header.
+    pub fn new(config: String) -> Result<Self, ParseError> {
+        let blocks = Self::split_blocks(&config);
+
+        let mut blocks = blocks.into_iter().peekable();
+        let mut metadata = Metadata::default();
+        if let Some((line, block)) = blocks.next_if(|(_, b)| b.starts_with(metadata::TEXT_HEADER)) {
+            metadata = Metadata::parse(&block).map_err(|e| e.at_line(line))?;
         }
-    }
 
-    pub fn exec(&self, inputs: Vec<f64>) -> Vec<f64> {
-        let mut current_inputs = inputs;
-        for layer in &self.0 {
-            current_inputs = layer.exec(current_inputs);
+        let mut records = vec![];
+        let mut first_lines = vec![];
+        for (line, block) in blocks {
+            let block_idx = records.len();
+            let record = if block.starts_with(record::HEADER_PREFIX) {
+                LayerRecord::parse_text(&block)
+            } else {
+                Layer::<T>::new(block).map(|layer| layer.serialize())
+            }
+            .map_err(|e| e.at_layer(block_idx).at_line(line))?;
+            records.push(record);
+            first_lines.push(line);
         }
-        current_inputs
-    }
 
-    pub fn forward(&self, inputs: &Vec<f64>) -> Vec<Vec<f64>> {
-        let mut all_outputs: Vec<Vec<f64>> = vec![inputs.to_vec()];
+        let records = LayerRecord::nest(records).map_err(|(block_idx, message)| {
+            ParseError::new(message)
+                .at_layer(block_idx)
+                .at_line(first_lines[block_idx])
+        })?;
+        let mut layers: Vec<Box<dyn NetworkElement<T>>> = vec![];
+        let mut layer_blocks = vec![];
+        for (block_idx, record) in records {
+            let layer = record.build().map_err(|e| {
+                ParseError::new(e)
+                    .at_layer(block_idx)
+                    .at_line(first_lines[block_idx])
+            })?;
+            layers.push(layer);
+            layer_blocks.push(block_idx);
+        }
+
+        if layers.is_empty() {
+            return Err(ParseError::new(String::from(
+                "network file contains no layers",
+            )));
+        }
 
-        for layer in &self.0 {
-            let new_output = layer.forward(&all_outputs.last().unwrap());
-            all_outputs.push(new_output);
+        let network = Network { layers, metadata };
+        if let Err((layer_idx, message)) = network.check_widths() {
+            let block_idx = layer_blocks[layer_idx];
+            return Err(ParseError::new(message)
+                .at_layer(block_idx)
+                .at_line(first_lines[block_idx]));
         }
-        all_outputs
+        Ok(network)
     }
 
-    #[allow(dead_code)]
-    pub fn train(&mut self, inputs: &Vec<f64>, targets: &Vec<f64>, learning_rate: f64) {
-        let all_outputs = self.forward(inputs);
-        let mut deltas: Vec<Vec<f64>> = vec![];
-
-        deltas.push(
-            self.0
-                .last()
-                .unwrap()
-                .backward_output(all_outputs.last().unwrap(), targets),
-        );
-        for index in (0..self.0.len() - 1).rev() {
-            deltas.push(self.0[index].backward_hidden(
-                &all_outputs[index + 1],
-                deltas.last().unwrap(),
-                &self.0[index + 1],
-            ));
+    /// Blocks of a text file with the 1-based line each one starts on
+    fn split_blocks(config: &str) -> Vec<(usize, String)> {
+        let mut blocks = vec![];
+        let mut current: Option<(usize, String)> = None;
+
+        for (line_idx, line) in config.lines().enumerate() {
+            if line.trim() == "---" {
+                blocks.extend(current.take());
+                continue;
+            }
+            match current {
+                Some((_, ref mut block)) => {
+                    block.push('\n');
+                    block.push_str(line);
+                }
+                None if line.trim().is_empty() => {}
+                None => current = Some((line_idx + 1, String::from(line))),
+            }
         }
+        blocks.extend(current);
+        blocks
+    }
 
-        deltas.reverse();
+    /// Check that every layer takes as many inputs as the previous layer
+    /// has outputs (and as `input_size` in the metadata for the first).
+    /// Returns the first offending layer.
+    pub fn check_widths(&self) -> Result<(), (usize, String)> {
+        if let Some(input_size) = self.metadata.get(metadata::INPUT_SIZE) {
+            let first = self.layers[0].nb_inputs();
+            if input_size.parse::<usize>().ok() != Some(first) {
+                return Err((
+                    0,
+                    format!(
+                        "layer takes {} inputs but the metadata declares {}",
+                        first, input_size
+                    ),
+                ));
+            }
+        }
 
-        for index in 0..self.0.len() {
-            self.0[index].update_weights(&deltas[index], &all_outputs[index], learning_rate);
+        for (layer_idx, pair) in self.layers.windows(2).enumerate() {
+            if pair[1].nb_inputs() != pair[0].nb_outputs() {
+                return Err((
+                    layer_idx + 1,
+                    format!(
+                        "{} layer takes {} inputs but layer {} ({}) has {} outputs",
+                        pair[1].kind(),
+                        pair[1].nb_inputs(),
+                        layer_idx,
+                        pair[0].kind(),
+                        pair[0].nb_outputs()
+                    ),
+                ));
+            }
         }
+        Ok(())
     }
 
-    pub fn train_softmax_ce(&mut self, inputs: &Vec<f64>, targets: &Vec<f64>, learning_rate: f64) {
-        let all_outputs = self.forward(inputs);
-        let mut deltas: Vec<Vec<f64>> = vec![];
+    pub fn nb_inputs(&self) -> usize {
+        self.layers[0].nb_inputs()
+    }
 
-        let output_layer_outputs = all_outputs.last().unwrap();
-        let softmax_outputs = Self::softmax(output_layer_outputs);
-        let output_deltas: Vec<f64> = softmax_outputs
-            .iter()
-            .zip(targets.iter())
-            .map(|(s, t)| t - s)
-            .collect();
+    pub fn nb_outputs(&self) -> usize {
+        self.layers.last().map_or(0, |layer| layer.nb_outputs())
+    }
 
-        deltas.push(output_deltas);
+    /// Dense layers of `nb_perceptron` neurons with He initialisation, each
+    /// followed by a dropout layer when its rate in `dropout_rates` is > 0
+    pub fn new_random_he(
+        mut nb_input: u32,
+        nb_perceptron: Vec<u32>,
+        dropout_rates: Vec<f64>,
+        hidden_activation: Activation,
+        output_activation: Activation,
+    ) -> Self {
+        let num_layers = nb_perceptron.len();
+        let mut layers: Vec<Box<dyn NetworkElement<T>>> = vec![];
 
-        for index in (0..self.0.len() - 1).rev() {
-            deltas.push(self.0[index].backward_hidden(
-                &all_outputs[index + 1],
-                deltas.last().unwrap(),
-                &self.0[index + 1],
-            ));
-        }
+        for (idx, &nb) in nb_perceptron.iter().enumerate() {
+            let activation = if idx == num_layers - 1 {
+                output_activation
+            } else {
+                hidden_activation
+            };
 
-        deltas.reverse();
+            layers.push(Box::new(Layer::new_random_he(nb, nb_input, activation)));
+            if let Some(&rate) = dropout_rates.get(idx).filter(|&&rate| rate > 0.0) {
+                layers.push(Box::new(Dropout::new(nb as usize, rate)));
+            }
+            nb_input = nb;
+        }
 
-        for index in 0..self.0.len() {
-            self.0[index].update_weights(&deltas[index], &all_outputs[index], learning_rate);
+        Network {
+            layers,
+            metadata: Metadata::default(),
         }
     }
 
-    pub fn train_batch_softmax_ce(
-        &mut self,
-        batch: &Vec<(Vec<f64>, Vec<f64>)>,
-        learning_rate: f64,
-    ) {
-        if batch.is_empty() {
-            return;
+    /// Stack of layers described by `specs`, randomly initialised. Inputs
+    /// only keep their planes shape when a layer reads planes.
+    pub fn from_architecture(
+        input: Shape,
+        specs: &[LayerSpec],
+        hidden_activation: Activation,
+    ) -> Result<Self, String> {
+        let mut layers: Vec<Box<dyn NetworkElement<T>>> = vec![];
+        let mut shape = if specs.iter().any(LayerSpec::needs_planes) {
+            input
+        } else {
+            Shape::Flat(input.size())
+        };
+        for spec in specs {
+            let (layer, output) = spec.build(shape, hidden_activation)?;
+            shape = output;
+            layers.push(layer);
+        }
+        if layers.is_empty() {
+            return Err(String::from("architecture has no layers"));
         }
 
-        let num_layers = self.0.len();
-
-        let mut accumulated_weight_gradients: Vec<Vec<Vec<f64>>> = vec![];
-        let mut accumulated_bias_gradients: Vec<Vec<f64>> = vec![];
-
-        for layer_idx in 0..num_layers {
-            let num_neurons = self.0[layer_idx].neurons.len();
-            let num_inputs = if layer_idx == 0 {
-                batch[0].0.len()
-            } else {
-                self.0[layer_idx - 1].neurons.len()
-            };
+        Ok(Network {
+            layers,
+            metadata: Metadata::default(),
+        })
+    }
 
-            let weight_grads: Vec<Vec<f64>> = vec![vec![0.0; num_inputs]; num_neurons];
-            let bias_grads: Vec<f64> = vec![0.0; num_neurons];
+    /// Insert a dropout layer after the i-th dense layer, for every
+    /// `rates[i] > 0`
+    pub fn add_dropout(&mut self, rates: &[f64]) {
+        let mut dense_idx = 0;
+        let mut index = 0;
+        while index < self.layers.len() {
+            if self.layers[index].kind() == "dense" {
+                if let Some(&rate) = rates.get(dense_idx).filter(|&&rate| rate > 0.0) {
+                    let size = self.layers[index].nb_outputs();
+                    self.layers
+                        .insert(index + 1, Box::new(Dropout::new(size, rate)));
+                    index += 1;
+                }
+                dense_idx += 1;
+            }
+            index += 1;
+        }
+    }
 
-            accumulated_weight_gradients.push(weight_grads);
-            accumulated_bias_gradients.push(bias_grads);
+    pub fn set_training_mode(&mut self, training: bool) {
+        for layer in &mut self.layers {
+            layer.set_training_mode(training);
         }
+    }
 
-        for (inputs, targets) in batch {
-            let all_outputs = self.forward(inputs);
-            let mut deltas: Vec<Vec<f64>> = vec![];
+    pub fn exec(&self, inputs: Vec<T>) -> Vec<T> {
+        let mut current = Matrix::new(1, inputs.len(), inputs);
+        for layer in &self.layers {
+            current = layer.forward(&current).0;
+        }
+        current.data
+    }
 
-            let output_layer_outputs = all_outputs.last().unwrap();
-            let softmax_outputs = Self::softmax(output_layer_outputs);
-            let output_deltas: Vec<f64> = softmax_outputs
-                .iter()
-                .zip(targets.iter())
-                .map(|(s, t)| t - s)
-                .collect();
+    /// Forward a batch through every layer, keeping each layer's outputs
+    /// (index 0 is the input batch itself) and caches.
+    pub fn forward(&self, inputs: &Matrix<T>) -> (Vec<Matrix<T>>, Vec<Cache>) {
+        let mut all_outputs: Vec<Matrix<T>> = vec![inputs.clone()];
+        let mut caches: Vec<Cache> = Vec::with_capacity(self.layers.len());
 
-            deltas.push(output_deltas);
+        for layer in &self.layers {
+            let (outputs, cache) = layer.forward(all_outputs.last().unwrap());
+            all_outputs.push(outputs);
+            caches.push(cache);
+        }
+        (all_outputs, caches)
+    }
 
-            for index in (0..num_layers - 1).rev() {
-                deltas.push(self.0[index].backward_hidden(
-                    &all_outputs[index + 1],
-                    deltas.last().unwrap(),
-                    &self.0[index + 1],
-                ));
+    /// Backpropagate dL/d(network outputs) through every layer
+    pub fn backward(
+        &self,
+        all_outputs: &[Matrix<T>],
+        caches: &[Cache],
+        output_grads: Matrix<T>,
+    ) -> Gradients<T> {
+        let mut grads = output_grads;
+        let mut layers = Vec::with_capacity(self.layers.len());
+
+        for index in (0..self.layers.len()).rev() {
+            let (input_grads, param_grads) =
+                self.layers[index].backward(&all_outputs[index], &caches[index], &grads, index > 0);
+            layers.push(param_grads);
+            if let Some(input_grads) = input_grads {
+                grads = input_grads;
             }
+        }
+        layers.reverse();
 
-            deltas.reverse();
+        let statistics = self
+            .layers
+            .iter()
+            .zip(caches.iter())
+            .map(|(layer, cache)| layer.batch_statistics(cache).into_iter().collect())
+            .collect();
+        Gradients { layers, statistics }
+    }
 
-            for layer_idx in 0..num_layers {
-                for neuron_idx in 0..deltas[layer_idx].len() {
-                    let delta = deltas[layer_idx][neuron_idx];
-                    accumulated_bias_gradients[layer_idx][neuron_idx] += delta;
-                    for input_idx in 0..all_outputs[layer_idx].len() {
-                        let input = all_outputs[layer_idx][input_idx];
-                        accumulated_weight_gradients[layer_idx][neuron_idx][input_idx] +=
-                            delta * input;
-                    }
-                }
+    /// Let the optimizer update every parameter group, then fold the batch
+    /// statistics into the layers that track them. Groups are numbered in
+    /// layer order to key the optimizer's per-parameter state.
+    pub fn apply_gradients(
+        &mut self,
+        gradients: &Gradients<T>,
+        learning_rate: f64,
+        optimizer: &mut dyn Optimizer<T>,
+    ) {
+        optimizer.begin_step();
+        let mut slot = 0;
+        for (layer, grads) in self.layers.iter_mut().zip(gradients.layers.iter()) {
+            for ((kind, params), g) in layer.parameters_mut().into_iter().zip(grads.iter()) {
+                optimizer.update(slot, kind, params, g, learning_rate);
+                slot += 1;
             }
         }
 
-        let batch_size = batch.len() as f64;
+        for (layer, statistics) in self.layers.iter_mut().zip(gradients.statistics.iter()) {
+            if !statistics.is_empty() {
+                layer.update_statistics(statistics);
+            }
+        }
+    }
 
-        for layer_idx in 0..num_layers {
-            for neuron_idx in 0..self.0[layer_idx].neurons.len() {
-                for weight_idx in 0..self.0[layer_idx].neurons[neuron_idx].weights.len() {
-                    let avg_gradient = accumulated_weight_gradients[layer_idx][neuron_idx]
-                        [weight_idx]
-                        / batch_size;
-                    self.0[layer_idx].neurons[neuron_idx].weights[weight_idx] +=
-                        learning_rate * avg_gradient;
-                }
+    /// Mini-batch step. The batch is split into chunks of
+    /// `BATCH_CHUNK_SIZE` examples, processed in parallel by the rayon
+    /// threads; each chunk accumulates into its own gradient buffer and the
+    /// buffers are summed in chunk order before the averaged update, which
+    /// also takes the `regularization` penalty into account. The gradients are clipped just
+    /// before the update; their norm before clipping is returned.
+    ///
+    /// Neither the chunks nor their dropout masks depend on the number of
+    /// threads: each chunk seeds the generator of the thread running it from
+    /// the generator of the calling thread, so a seeded caller gets the same
+    /// weights whatever `--threads` is.
+    pub fn train_batch(
+        &mut self,
+        batch: &[(Vec<T>, Vec<T>)],
+        loss: &dyn Loss<T>,
+        learning_rate: f64,
+        optimizer: &mut dyn Optimizer<T>,
+        regularization: &Regularization,
+        clipping: &GradientClipping,
+    ) -> f64 {
+        if batch.is_empty() {
+            return 0.0;
+        }
 
-                let avg_bias_gradient =
-                    accumulated_bias_gradients[layer_idx][neuron_idx] / batch_size;
-                self.0[layer_idx].neurons[neuron_idx].biais += learning_rate * avg_bias_gradient;
-            }
+        let (seed, next_seed): (u64, u64) = {
+            let mut rng = random::rng();
+            (rng.gen(), rng.gen())
+        };
+        // Batch normalization needs the statistics of the whole batch
+        let chunk_size = if self.couples_batch() {
+            batch.len()
+        } else {
+            BATCH_CHUNK_SIZE
+        };
+        let partial_gradients: Vec<Gradients<T>> = batch
+            .par_chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                random::seed(seed.wrapping_add(index as u64));
+                self.loss_gradients(chunk, loss)
+            })
+            .collect();
+        // The calling thread may have run chunks too
+        random::seed(next_seed);
+
+        let mut partial_gradients = partial_gradients.into_iter();
+        let mut gradients = partial_gradients.next().unwrap();
+        for partial in partial_gradients {
+            gradients.add(&partial);
         }
+        gradients.scale(1.0 / batch.len() as f64);
+        regularization.add_gradients(self, &mut gradients);
+        let norm = gradients.norm();
+        clipping.apply(&mut gradients, norm);
+
+        self.apply_gradients(&gradients, learning_rate, optimizer);
+        regularization.constrain(self);
+        norm
     }
 
-    fn softmax(outputs: &Vec<f64>) -> Vec<f64> {
-        let max = outputs.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-        let exp_values: Vec<f64> = outputs.iter().map(|&x| (x - max).exp()).collect();
-        let sum: f64 = exp_values.iter().sum();
-        exp_values.iter().map(|&x| x / sum).collect()
+    /// Gradients of every layer for `loss`, summed over `batch`
+    pub fn loss_gradients(&self, batch: &[(Vec<T>, Vec<T>)], loss: &dyn Loss<T>) -> Gradients<T> {
+        let (inputs, targets) = Self::batch_matrices(batch);
+        let (all_outputs, caches) = self.forward(&inputs);
+        let output_grads = loss.gradients(all_outputs.last().unwrap(), &targets);
+        self.backward(&all_outputs, &caches, output_grads)
     }
 
-    pub fn to_string(&self) -> String {
-        self.0
-            .iter()
-            .map(|l| l.to_string())
-            .collect::<Vec<_>>()
-            .join("\n---\n")
+    /// Value of `loss` over `batch`
+    pub fn loss(&self, batch: &[(Vec<T>, Vec<T>)], loss: &dyn Loss<T>) -> f64 {
+        let (inputs, targets) = Self::batch_matrices(batch);
+        let (all_outputs, _) = self.forward(&inputs);
+        loss.value(all_outputs.last().unwrap(), &targets)
     }
 
-    pub fn save(&self, path: &str) -> Result<(), String> {
-        let content = self.to_string();
+    fn batch_matrices(batch: &[(Vec<T>, Vec<T>)]) -> (Matrix<T>, Matrix<T>) {
+        (
+            Matrix::from_rows(&batch.iter().map(|(i, _)| i.clone()).collect::<Vec<_>>()),
+            Matrix::from_rows(&batch.iter().map(|(_, t)| t.clone()).collect::<Vec<_>>()),
+        )
+    }
+
+    pub fn save_as(&self, path: &str, format: ModelFormat) -> Result<(), String> {
+        let content = match format {
+            ModelFormat::Text => self.to_string().into_bytes(),
+            ModelFormat::Binary(precision) => binary::encode(self, precision),
+        };
 
         fs::write(path, content).map_err(|e| format!("Failed to save network to {}: {}", path, e))
     }
 
+    /// Load a network saved in either the text or the binary format. The
+    /// weights are converted to `T` whatever the precision of the file.
     pub fn load(path: &str) -> Result<Self, String> {
-        let content = fs::read_to_string(path)
-            .map_err(|e| format!("Failed to load network from {}: {}", path, e))?;
+        Self::load_with_format(path).map(|(network, _)| network)
+    }
 
-        Network::new(content)
+    pub fn load_with_format(path: &str) -> Result<(Self, ModelFormat), String> {
+        let content =
+            fs::read(path).map_err(|e| format!("Failed to load network from {}: {}", path, e))?;
+
+        let format = ModelFormat::detect(&content);
+        let network = match format {
+            ModelFormat::Text => {
+                let content = String::from_utf8(content)
+                    .map_err(|_| format!("Failed to load network from {}: not UTF-8", path))?;
+                Network::new(content)
+                    .map_err(|e| format!("Failed to load network from {}: {}", path, e))?
+            }
+            ModelFormat::Binary(_) => binary::decode(&content)
+                .map_err(|e| format!("Failed to load network from {}: {}", path, e))?,
+        };
+        Ok((network, format))
+    }
+
+    /// Whether a sample's outputs depend on the rest of its training batch
+    pub fn couples_batch(&self) -> bool {
+        self.layers.iter().any(|layer| layer.couples_batch())
+    }
+
+    /// Batch normalization needs at least two examples per batch to compute
+    /// a variance
+    pub fn check_batch_size(&self, batch_size: usize) -> Result<(), String> {
+        if batch_size < 2 && self.couples_batch() {
+            return Err(format!(
+                "batch_size must be >= 2 for a network with batch normalization, got {}",
+                batch_size
+            ));
+        }
+        Ok(())
     }
 
-    pub fn check_gradients(&self) -> Result<(), String> {
-        for (layer_idx, layer) in self.0.iter().enumerate() {
-            for (neuron_idx, neuron) in layer.neurons.iter().enumerate() {
-                for (weight_idx, &weight) in neuron.weights.iter().enumerate() {
-                    if weight.is_nan() {
+    /// Scan every parameter for NaN or infinite values (an error) and
+    /// warn about very large weights
+    pub fn check_parameters(&self) -> Result<(), String> {
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            for (kind, params) in layer.parameters() {
+                let name = match kind {
+                    ParamKind::Weight => "weight",
+                    ParamKind::Bias => "bias",
+                    ParamKind::Scale => "scale",
+                };
+                for (index, &value) in params.iter().enumerate() {
+                    if value.is_nan() {
                         return Err(format!(
-                            "NaN weight at layer {}, neuron {}, weight {}",
-                            layer_idx, neuron_idx, weight_idx
+                            "NaN {} at layer {} ({}), index {}",
+                            name,
+                            layer_idx,
+                            layer.kind(),
+                            index
                         ));
                     }
-                    if weight.is_infinite() {
+                    if value.is_infinite() {
                         return Err(format!(
-                            "Infinite weight at layer {}, neuron {}, weight {}",
-                            layer_idx, neuron_idx, weight_idx
+                            "Infinite {} at layer {} ({}), index {}",
+                            name,
+                            layer_idx,
+                            layer.kind(),
+                            index
                         ));
                     }
-                    if weight.abs() > 1000.0 {
+                    if kind == ParamKind::Weight && value.abs() > T::cast(1000.0) {
                         eprintln!(
-                            "WARNING: Large weight at layer {}, neuron {}, weight {}: {}",
-                            layer_idx, neuron_idx, weight_idx, weight
+                            "WARNING: Large weight at layer {} ({}), index {}: {}",
+                            layer_idx,
+                            layer.kind(),
+                            index,
+                            value
                         );
                     }
                 }
-
-                if neuron.biais.is_nan() {
-                    return Err(format!(
-                        "NaN bias at layer {}, neuron {}",
-                        layer_idx, neuron_idx
-                    ));
-                }
-                if neuron.biais.is_infinite() {
-                    return Err(format!(
-                        "Infinite bias at layer {}, neuron {}",
-                        layer_idx, neuron_idx
-                    ));
-                }
             }
         }
         Ok(())
@@ -282,19 +572,250 @@ impl Network {
 
     #[allow(dead_code)]
     pub fn get_architecture(&self) -> Vec<usize> {
-        self.0.iter().map(|layer| layer.neurons.len()).collect()
+        self.layers.iter().map(|layer| layer.nb_outputs()).collect()
     }
 
     pub fn count_parameters(&self) -> usize {
-        self.0
+        self.layers
             .iter()
-            .map(|layer| {
-                layer
-                    .neurons
-                    .iter()
-                    .map(|neuron| neuron.weights.len() + 1)
-                    .sum::<usize>()
-            })
+            .flat_map(|layer| layer.parameters())
+            .map(|(_, params)| params.len())
             .sum()
     }
+
+    /// The same network in precision `U`. Narrowing to `f32` rounds every
+    /// value; widening to `f64` keeps the shortest decimal form of each one
+    /// (see `Float::to_decimal`), as saving and loading would.
+    pub fn convert<U: Float>(&self) -> Network<U> {
+        Network {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| {
+                    layer
+                        .serialize()
+                        .build()
+                        .expect("a layer rebuilds from its own record")
+                })
+                .collect(),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+impl<T: Float> fmt::Display for Network<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.metadata.is_empty() {
+            write!(f, "{}\n---\n", self.metadata)?;
+        }
+        for (index, layer) in self.layers.iter().enumerate() {
+            if index > 0 {
+                write!(f, "\n---\n")?;
+            }
+            write!(f, "{}", layer.serialize())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::batch_norm::BatchNorm;
+    use crate::network::datastruct::binary::Precision;
+
+    #[test]
+    fn test_clone_is_exact() {
+        let mut network = Network {
+            layers: vec![
+                Box::new(Layer::new_random_he(4, 3, Activation::Relu)),
+                Box::new(BatchNorm::new(4)),
+                Box::new(Layer::new_random_he(2, 4, Activation::Linear)),
+            ],
+            metadata: Metadata::default(),
+        };
+        network.set_training_mode(true);
+        let batch = vec![
+            (vec![0.1, 0.7, -0.3], vec![1.0, 0.0]),
+            (vec![0.9, -0.2, 0.4], vec![0.0, 1.0]),
+        ];
+        let loss = crate::network::loss::Mse;
+        network.train_batch(
+            &batch,
+            &loss,
+            0.1,
+            &mut crate::network::optimizer::Sgd,
+            &Regularization::default(),
+            &GradientClipping::default(),
+        );
+        network.set_training_mode(false);
+
+        let copy = network.clone();
+        assert_eq!(copy.to_string(), network.to_string());
+        assert_eq!(
+            copy.exec(vec![0.5, 0.5, 0.5]),
+            network.exec(vec![0.5, 0.5, 0.5])
+        );
+    }
+
+    #[test]
+    fn test_f32_training_and_conversion() {
+        let network: Network = Network::new_random_he(
+            3,
+            vec![6, 2],
+            vec![0.0, 0.0],
+            Activation::Tanh,
+            Activation::Linear,
+        );
+        let mut narrow: Network<f32> = network.convert();
+        let inputs = vec![0.2, -0.4, 0.9];
+        let expected = network.exec(inputs.clone());
+        let outputs = narrow.exec(crate::network::float::cast_all(&inputs));
+        for (a, b) in outputs.iter().zip(&expected) {
+            assert!((*a as f64 - b).abs() < 1e-5);
+        }
+
+        let batch = vec![(vec![0.1f32, 0.7, -0.3], vec![1.0f32, -1.0])];
+        let loss = crate::network::loss::Mse;
+        let before =
+            Loss::<f32>::sample_value(&loss, &narrow.exec(batch[0].0.clone()), &batch[0].1);
+        for _ in 0..20 {
+            narrow.train_batch(
+                &batch,
+                &loss,
+                0.05,
+                &mut crate::network::optimizer::Sgd,
+                &Regularization::default(),
+                &GradientClipping::default(),
+            );
+        }
+        let after = Loss::<f32>::sample_value(&loss, &narrow.exec(batch[0].0.clone()), &batch[0].1);
+        assert!(after < before);
+
+        // Widening keeps every f32 value, so the round trip is exact
+        let wide: Network = narrow.convert();
+        assert_eq!(wide.convert::<f32>().to_string(), narrow.to_string());
+        let decoded: Network<f32> = binary::decode(&binary::encode(&wide, Precision::F32)).unwrap();
+        assert_eq!(decoded.to_string(), narrow.to_string());
+    }
+
+    #[test]
+    fn test_batch_norm_uses_whole_batches() {
+        let first = Layer::new_random_he(4, 3, Activation::Linear);
+        let mut norm = BatchNorm::new(4);
+        norm.momentum = 0.5;
+        let mut expected = BatchNorm::new(4);
+        expected.momentum = 0.5;
+        expected.set_training_mode(true);
+
+        // 9 examples: more than one chunk, the last one a single example
+        let batch: Vec<_> = (0..9)
+            .map(|i| {
+                let x = i as f64 / 9.0;
+                (vec![x, 1.0 - x * x, (3.0 * x).sin()], vec![x, -x])
+            })
+            .collect();
+        let inputs: Vec<Vec<f64>> = batch.iter().map(|(i, _)| i.clone()).collect();
+        let (hidden, _) = first.forward(&Matrix::from_rows(&inputs));
+        let (_, cache) = expected.forward(&hidden);
+        expected.update_statistics(&[expected.batch_statistics(&cache).unwrap()]);
+
+        let mut network = Network {
+            layers: vec![
+                Box::new(first),
+                Box::new(norm),
+                Box::new(Layer::new_random_he(2, 4, Activation::Linear)),
+            ],
+            metadata: Metadata::default(),
+        };
+        // A single example has no variance
+        assert!(network.check_batch_size(1).is_err());
+        assert!(network.check_batch_size(9).is_ok());
+
+        network.set_training_mode(true);
+        network.train_batch(
+            &batch,
+            &crate::network::loss::Mse,
+            0.0,
+            &mut crate::network::optimizer::Sgd,
+            &Regularization::default(),
+            &GradientClipping::default(),
+        );
+        let record = network.layers[1].serialize();
+        for (name, expected) in [
+            ("running_mean", &expected.running_mean),
+            ("running_var", &expected.running_var),
+        ] {
+            let values: Vec<f64> = record.tensor(name, 4).unwrap();
+            assert!(values.iter().any(|&v| v != 0.0 && v != 1.0));
+            for (a, b) in values.iter().zip(expected) {
+                assert!((a - b).abs() < 1e-12, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seeded_training_ignores_thread_count() {
+        let train = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                random::seed(3);
+                let mut network = Network::new_random_he(
+                    3,
+                    vec![8, 2],
+                    vec![0.5, 0.0],
+                    Activation::Relu,
+                    Activation::Linear,
+                );
+                network.set_training_mode(true);
+                let batch: Vec<_> = (0..40)
+                    .map(|i| {
+                        let x = i as f64 / 40.0;
+                        (vec![x, 1.0 - x, x * x], vec![x, -x])
+                    })
+                    .collect();
+                for _ in 0..3 {
+                    network.train_batch(
+                        &batch,
+                        &crate::network::loss::Mse,
+                        0.1,
+                        &mut crate::network::optimizer::Sgd,
+                        &Regularization::default(),
+                        &GradientClipping::default(),
+                    );
+                }
+                network.to_string()
+            })
+        };
+        let single = train(1);
+        assert_eq!(train(4), single);
+        assert_eq!(train(3), single);
+    }
+
+    #[test]
+    fn test_gradient_clipping() {
+        let gradients = || Gradients {
+            layers: vec![vec![vec![3.0, 0.0], vec![-4.0]]],
+            statistics: vec![vec![]],
+        };
+        let mut clipped = gradients();
+        assert_eq!(clipped.norm(), 5.0);
+        let by_norm = GradientClipping {
+            norm: 2.5,
+            value: 0.0,
+        };
+        by_norm.apply(&mut clipped, 5.0);
+        assert_eq!(clipped.layers[0], vec![vec![1.5, 0.0], vec![-2.0]]);
+
+        let mut clipped = gradients();
+        let by_value = GradientClipping {
+            norm: 10.0,
+            value: 2.0,
+        };
+        by_value.apply(&mut clipped, 5.0);
+        assert_eq!(clipped.layers[0], vec![vec![2.0, 0.0], vec![-2.0]]);
+    }
 }
@@ -0,0 +1,154 @@
+use std::fmt;
+
+/// Error raised while reading a text network file.
+///
+/// Each parsing level fills in what it knows: the perceptron parser the
+/// token and its column, the layer parser the neuron, the network parser the
+/// layer and the line in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// 1-based line in the file
+    pub line: Option<usize>,
+    /// 1-based character column of the offending token
+    pub column: Option<usize>,
+    pub layer: Option<usize>,
+    pub neuron: Option<usize>,
+    /// 0-based index of the token within its line
+    pub token: Option<usize>,
+}
+
+impl ParseError {
+    pub fn new(message: String) -> Self {
+        ParseError {
+            message,
+            line: None,
+            column: None,
+            layer: None,
+            neuron: None,
+            token: None,
+        }
+    }
+
+    pub fn at_token(mut self, token: usize, column: usize) -> Self {
+        self.token = Some(token);
+        self.column = Some(column);
+        self
+    }
+
+    pub fn at_neuron(mut self, neuron: usize) -> Self {
+        self.neuron.get_or_insert(neuron);
+        self
+    }
+
+    pub fn at_layer(mut self, layer: usize) -> Self {
+        self.layer.get_or_insert(layer);
+        self
+    }
+
+    /// `line` is relative to the enclosing block, so nested levels add their offset
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.line = Some(self.line.map_or(line, |l| l + line - 1));
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut position = vec![];
+        if let Some(line) = self.line {
+            position.push(format!("line {}", line));
+        }
+        if let Some(column) = self.column {
+            position.push(format!("column {}", column));
+        }
+        if let Some(layer) = self.layer {
+            position.push(format!("layer {}", layer));
+        }
+        if let Some(neuron) = self.neuron {
+            position.push(format!("neuron {}", neuron));
+        }
+        if let Some(token) = self.token {
+            position.push(format!("token {}", token));
+        }
+
+        if position.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", position.join(", "), self.message)
+        }
+    }
+}
+
+impl From<ParseError> for String {
+    fn from(error: ParseError) -> String {
+        error.to_string()
+    }
+}
+
+/// Whitespace-separated tokens of `line` with their 1-based character column
+pub fn tokens_with_columns(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut start: Option<(usize, usize)> = None;
+
+    for (column, (byte, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column + 1, byte)),
+            (true, Some((col, from))) => {
+                tokens.push((col, &line[from..byte]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((col, from)) = start {
+        tokens.push((col, &line[from..]));
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::datastruct::network::Network;
+
+    fn parse_error(content: &str) -> super::ParseError {
+        Network::<f64>::new(String::from(content))
+            .err()
+            .expect("parsing should fail")
+    }
+
+    #[test]
+    fn test_invalid_weight_position() {
+        let error = parse_error(
+            "#metadata\nlabels = a, b\n---\nrelu 0 1 2\nrelu 0 1 x2\n---\nlinear 0 1 1",
+        );
+        assert_eq!(
+            (
+                error.line,
+                error.column,
+                error.layer,
+                error.neuron,
+                error.token
+            ),
+            (Some(5), Some(10), Some(0), Some(1), Some(3))
+        );
+    }
+
+    #[test]
+    fn test_structural_errors() {
+        let error = parse_error("relu 0 1 2\nrelu 0 1\n");
+        assert_eq!((error.line, error.neuron), (Some(2), Some(1)));
+
+        let error = parse_error("relu 0 1 2\nsigmoid 0 1 2\n");
+        assert_eq!((error.line, error.column), (Some(2), Some(1)));
+
+        let error = parse_error("relu 0 1 2\nrelu 0 1 2\n---\nlinear 0 1 1 1\n");
+        assert_eq!((error.line, error.layer), (Some(4), Some(1)));
+
+        let error = parse_error("#metadata\ninput_size = 3\n---\nrelu 0 1 2\n");
+        assert_eq!((error.line, error.layer), (Some(4), Some(0)));
+
+        assert!(Network::<f64>::new(String::from("\n\n")).is_err());
+        assert!(Network::<f64>::new(String::from("relu 0 1 2\n---\nlinear 0 1 1\n")).is_err());
+    }
+}
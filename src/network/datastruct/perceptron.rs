@@ -1,141 +1,80 @@
-use rand::thread_rng;
-use rand::Rng;
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::parse_error::{tokens_with_columns, ParseError};
+use crate::network::float::Float;
+use crate::network::random;
 use rand_distr::{Distribution, Normal};
-use std::str::FromStr;
+use std::fmt;
 
-pub fn function_getter(key: String) -> Result<fn(f64, f64) -> f64, String> {
-    match key.as_str() {
-        "sigmoid" => Ok(|i, b| 1. / (1. + (-(i + b)).exp())),
-        "relu" => Ok(|i, b| {
-            let x = i + b;
-            if x > 0.0 {
-                x
-            } else {
-                0.0
-            }
-        }),
-        "tanh" => Ok(|i, b| (i + b).tanh()),
-        "linear" => Ok(|i, b| i + b),
-        _ => Err(format!("unknow function '{}'", key)),
-    }
-}
-
-pub fn sigmoid_derivate(output: f64) -> f64 {
-    output * (1.0 - output)
+/// One neuron as written in a network file: `FUNC BIAS W1 W2 ...`.
+///
+/// Layers keep their weights in a single matrix; a `Perceptron` is only the
+/// per-row representation used when parsing, generating and saving them.
+#[derive(Debug)]
+pub struct Perceptron<T = f64> {
+    pub activation: Activation,
+    pub weights: Vec<T>,
+    pub biais: T,
 }
 
-pub fn relu_derivate(output: f64) -> f64 {
-    if output > 0.0 {
-        1.0
-    } else {
-        0.0
-    }
-}
+impl<T: Float> Perceptron<T> {
+    pub fn new(config: String) -> Result<Self, ParseError> {
+        let tokens = tokens_with_columns(&config);
+        let end_column = config.chars().count() + 1;
 
-pub fn tanh_derivate(output: f64) -> f64 {
-    return 1.0 - output * output;
-}
+        let &(f_column, f_token) = tokens.first().ok_or_else(|| {
+            ParseError::new(String::from("empty neuron, expected FUNC BIAS WEIGHTS..."))
+                .at_token(0, end_column)
+        })?;
+        let activation =
+            Activation::from_name(f_token).map_err(|e| ParseError::new(e).at_token(0, f_column))?;
 
-pub fn linear_derivate(_output: f64) -> f64 {
-    return 1.0;
-}
+        let &(b_column, b_token) = tokens.get(1).ok_or_else(|| {
+            ParseError::new(format!("missing bias after '{}'", f_token)).at_token(1, end_column)
+        })?;
+        let b: T = b_token.parse().map_err(|_| {
+            ParseError::new(format!("invalid bias '{}'", b_token)).at_token(1, b_column)
+        })?;
 
-pub fn get_derivative(func_id: &str) -> fn(f64) -> f64 {
-    match func_id {
-        "sigmoid" => sigmoid_derivate,
-        "relu" => relu_derivate,
-        "tanh" => tanh_derivate,
-        "linear" => linear_derivate,
-        _ => sigmoid_derivate,
-    }
-}
-#[derive(Debug)]
-pub struct Perceptron {
-    pub func: fn(f64 /* Input sum */, f64 /* Bias */) -> f64,
-    pub func_id: String,
-    pub weights: Vec<f64>,
-    pub biais: f64,
-}
+        let w = tokens
+            .iter()
+            .enumerate()
+            .skip(2)
+            .map(|(index, &(column, token))| {
+                token.parse::<T>().map_err(|_| {
+                    ParseError::new(format!("invalid weight '{}'", token)).at_token(index, column)
+                })
+            })
+            .collect::<Result<Vec<T>, _>>()?;
 
-impl Perceptron {
-    pub fn new(config: String) -> Result<Self, String> {
-        let mut tokens = config.split_whitespace();
-        let f_id = String::from(tokens.next().unwrap());
-        let f = function_getter(f_id.clone())?;
-        let b: f64 = tokens.next().unwrap().parse().unwrap();
-        let w: Vec<f64> = tokens.map(f64::from_str).map(Result::unwrap).collect();
         Ok(Perceptron {
-            func: f,
-            func_id: f_id,
+            activation,
             weights: w,
             biais: b,
         })
     }
 
-    #[allow(dead_code)]
-    pub fn new_random_with_activation(
-        nb_weight: u32,
-        w_range: &(f64, f64),
-        b_range: &(f64, f64),
-        activation: &str,
-    ) -> Self {
-        let func_id = String::from(activation);
-        let func = function_getter(func_id.clone()).unwrap();
-        Perceptron {
-            func: func,
-            func_id: func_id,
-            weights: (0..nb_weight)
-                .map(|_| {
-                    let mut rng = thread_rng();
-                    rng.gen_range(w_range.0..w_range.1)
-                })
-                .collect(),
-            biais: {
-                let mut rng = thread_rng();
-                rng.gen_range(b_range.0..b_range.1)
-            },
-        }
-    }
-
-    pub fn new_random_he(nb_weight: u32, activation: &str) -> Self {
-        let mut rng = thread_rng();
-
-        let std_dev = if activation == "relu" {
-            (2.0 / nb_weight as f64).sqrt()
-        } else {
-            (1.0 / nb_weight as f64).sqrt()
-        };
+    pub fn new_random_he(nb_weight: u32, activation: Activation) -> Self {
+        let mut rng = random::rng();
 
+        let std_dev = (activation.init_gain() / nb_weight as f64).sqrt();
         let normal = Normal::new(0.0, std_dev).unwrap();
-        let func_id = String::from(activation);
-        let func = function_getter(func_id.clone()).unwrap();
 
         Perceptron {
-            func: func,
-            func_id: func_id,
-            weights: (0..nb_weight).map(|_| normal.sample(&mut rng)).collect(),
-            biais: 0.0,
+            activation,
+            weights: (0..nb_weight)
+                .map(|_| T::cast(normal.sample(&mut rng)))
+                .collect(),
+            biais: T::zero(),
         }
     }
+}
 
-    pub fn exec(&self, inputs: &Vec<f64>) -> f64 {
-        if inputs.len() != self.weights.len() {
-            panic!();
+impl<T: Float> fmt::Display for Perceptron<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.activation, self.biais)?;
+        for w in &self.weights {
+            write!(f, " {}", w)?;
         }
-        let sum = inputs
-            .iter()
-            .zip(self.weights.iter())
-            .fold(0.0, |acc, (i, w)| acc + i * w);
-        (self.func)(sum, self.biais)
-    }
-
-    pub fn to_string(&self) -> String {
-        let weight_string = self
-            .weights
-            .iter()
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>()
-            .join(" ");
-        format!("{} {} {}", self.func_id, self.biais, weight_string)
+        Ok(())
     }
 }
@@ -0,0 +1,332 @@
+//! Format-independent description of a saved network element.
+//!
+//! Elements serialize to a `LayerRecord` (a kind, scalar attributes and
+//! named float tensors), which the text and binary formats then write out.
+//! In text files a record is a block starting with a header line:
+//!
+//! ```text
+//! @layernorm size=64 epsilon=0.00001
+//! gamma 1 1 1 ...
+//! beta 0 0 0 ...
+//! ```
+//!
+//! Dense layers keep the historical headerless format, one neuron per line.
+//!
+//! Composite elements (residual blocks) hold child records. Files stay a
+//! flat sequence of records: the parent gets a `children=N` attribute and
+//! its N children follow it, each one possibly with children of its own.
+
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::batch_norm::BatchNorm;
+use crate::network::datastruct::conv::{Conv2D, Flatten};
+use crate::network::datastruct::dropout::Dropout;
+use crate::network::datastruct::layer::Layer;
+use crate::network::datastruct::layer_norm::LayerNorm;
+use crate::network::datastruct::parse_error::{tokens_with_columns, ParseError};
+use crate::network::datastruct::r#trait::NetworkElement;
+use crate::network::datastruct::residual::Residual;
+use crate::network::datastruct::sparse::SparseDense;
+use crate::network::datastruct::transformer::{Embedding, Encoder, Pool};
+use crate::network::float::{self, Float};
+use std::fmt;
+use std::str::FromStr;
+
+/// First character of the header line of a non-dense text block
+pub const HEADER_PREFIX: char = '@';
+/// Attribute holding the number of child records in flat sequences
+pub const CHILDREN_KEY: &str = "children";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerRecord {
+    pub kind: String,
+    pub attributes: Vec<(String, String)>,
+    pub tensors: Vec<(String, Vec<f64>)>,
+    pub children: Vec<LayerRecord>,
+}
+
+impl LayerRecord {
+    pub fn new(kind: &str) -> Self {
+        LayerRecord {
+            kind: String::from(kind),
+            attributes: vec![],
+            tensors: vec![],
+            children: vec![],
+        }
+    }
+
+    pub fn with_child(mut self, child: LayerRecord) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// The record then its children, depth first, as written in files
+    pub fn flatten(mut self) -> Vec<LayerRecord> {
+        let children = std::mem::take(&mut self.children);
+        if !children.is_empty() {
+            self = self.with_attribute(CHILDREN_KEY, children.len());
+        }
+        let mut flat = vec![self];
+        for child in children {
+            flat.extend(child.flatten());
+        }
+        flat
+    }
+
+    /// Inverse of `flatten` over a whole file: every top-level record with
+    /// the index of its flat entry. Errors carry the offending flat index.
+    pub fn nest(flat: Vec<LayerRecord>) -> Result<Vec<(usize, LayerRecord)>, (usize, String)> {
+        let mut entries = flat.into_iter().enumerate();
+        let mut records = vec![];
+        while let Some((index, record)) = entries.next() {
+            records.push((index, Self::take_children(index, record, &mut entries)?));
+        }
+        Ok(records)
+    }
+
+    fn take_children(
+        index: usize,
+        mut record: LayerRecord,
+        entries: &mut impl Iterator<Item = (usize, LayerRecord)>,
+    ) -> Result<LayerRecord, (usize, String)> {
+        let Some(position) = record
+            .attributes
+            .iter()
+            .position(|(k, _)| k == CHILDREN_KEY)
+        else {
+            return Ok(record);
+        };
+        let (_, value) = record.attributes.remove(position);
+        let count: usize = value.parse().map_err(|_| {
+            (
+                index,
+                format!("{}: invalid {} '{}'", record.kind, CHILDREN_KEY, value),
+            )
+        })?;
+        for _ in 0..count {
+            let (child_index, child) = entries.next().ok_or_else(|| {
+                (
+                    index,
+                    format!(
+                        "{}: expected {} child layers after it, the file ends first",
+                        record.kind, count
+                    ),
+                )
+            })?;
+            let child = Self::take_children(child_index, child, entries)?;
+            record.children.push(child);
+        }
+        Ok(record)
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: impl ToString) -> Self {
+        self.attributes.push((String::from(key), value.to_string()));
+        self
+    }
+
+    /// Values of any precision are stored in their shortest decimal form,
+    /// see `Float::to_decimal`
+    pub fn with_tensor<T: Float>(mut self, name: &str, values: &[T]) -> Self {
+        let values = values.iter().map(|v| v.to_decimal()).collect();
+        self.tensors.push((String::from(name), values));
+        self
+    }
+
+    pub fn attribute<T: FromStr>(&self, key: &str) -> Result<T, String> {
+        let value = self
+            .attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+            .ok_or_else(|| format!("{}: missing attribute '{}'", self.kind, key))?;
+        value
+            .parse()
+            .map_err(|_| format!("{}: invalid {} '{}'", self.kind, key, value))
+    }
+
+    /// Tensor `name`, which must hold exactly `len` values
+    pub fn tensor<T: Float>(&self, name: &str, len: usize) -> Result<Vec<T>, String> {
+        let values = self
+            .tensors
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+            .ok_or_else(|| format!("{}: missing tensor '{}'", self.kind, name))?;
+        if values.len() != len {
+            return Err(format!(
+                "{}: tensor '{}' has {} values, expected {}",
+                self.kind,
+                name,
+                values.len(),
+                len
+            ));
+        }
+        Ok(float::cast_all(values))
+    }
+
+    /// Rebuild the element this record was saved from, in precision `T`
+    pub fn build<T: Float>(&self) -> Result<Box<dyn NetworkElement<T>>, String> {
+        match self.kind.as_str() {
+            "dense" => Ok(Box::new(Layer::from_record(self)?)),
+            "dropout" => Ok(Box::new(Dropout::from_record(self)?)),
+            "layernorm" => Ok(Box::new(LayerNorm::from_record(self)?)),
+            "batchnorm" => Ok(Box::new(BatchNorm::from_record(self)?)),
+            "conv2d" => Ok(Box::new(Conv2D::from_record(self)?)),
+            "flatten" => Ok(Box::new(Flatten::from_record(self)?)),
+            "residual" => Ok(Box::new(Residual::from_record(self)?)),
+            "embedding" => Ok(Box::new(Embedding::from_record(self)?)),
+            "encoder" => Ok(Box::new(Encoder::from_record(self)?)),
+            "pool" => Ok(Box::new(Pool::from_record(self)?)),
+            "sparse_dense" => Ok(Box::new(SparseDense::from_record(self)?)),
+            _ => Err(format!("unknown layer kind '{}'", self.kind)),
+        }
+    }
+
+    /// Parse a text block starting with an `@kind key=value...` header
+    pub fn parse_text(block: &str) -> Result<Self, ParseError> {
+        let mut lines = block.lines().enumerate();
+        let header = lines.next().map_or("", |(_, line)| line);
+        let tokens = tokens_with_columns(header);
+
+        let (_, kind) = tokens[0];
+        let mut record = LayerRecord::new(&kind[HEADER_PREFIX.len_utf8()..]);
+        if record.kind.is_empty() {
+            return Err(
+                ParseError::new(String::from("missing layer kind after '@'"))
+                    .at_token(0, 1)
+                    .at_line(1),
+            );
+        }
+        for (index, &(column, token)) in tokens.iter().enumerate().skip(1) {
+            let (key, value) = token.split_once('=').ok_or_else(|| {
+                ParseError::new(format!("expected KEY=VALUE, got '{}'", token))
+                    .at_token(index, column)
+                    .at_line(1)
+            })?;
+            record = record.with_attribute(key, value);
+        }
+
+        for (line_idx, line) in lines {
+            let tokens = tokens_with_columns(line);
+            let Some(&(_, name)) = tokens.first() else {
+                continue;
+            };
+            let values = tokens
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(index, &(column, token))| {
+                    token.parse::<f64>().map_err(|_| {
+                        ParseError::new(format!("invalid value '{}' in '{}'", token, name))
+                            .at_token(index, column)
+                            .at_line(line_idx + 1)
+                    })
+                })
+                .collect::<Result<Vec<f64>, _>>()?;
+            record.tensors.push((String::from(name), values));
+        }
+        Ok(record)
+    }
+}
+
+/// Text block of the record: neuron lines for dense layers, a header line
+/// and one line per tensor otherwise. Children follow as their own blocks.
+impl fmt::Display for LayerRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.children.is_empty() {
+            for (index, record) in self.clone().flatten().iter().enumerate() {
+                if index > 0 {
+                    write!(f, "\n---\n")?;
+                }
+                write!(f, "{}", record)?;
+            }
+            return Ok(());
+        }
+        if self.kind == "dense" {
+            return write_dense(self, f);
+        }
+
+        write!(f, "{}{}", HEADER_PREFIX, self.kind)?;
+        for (key, value) in &self.attributes {
+            write!(f, " {}={}", key, value)?;
+        }
+        for (name, values) in &self.tensors {
+            write!(f, "\n{}", name)?;
+            for v in values {
+                write!(f, " {}", v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_dense(record: &LayerRecord, f: &mut fmt::Formatter) -> fmt::Result {
+    let (Ok(activation), Ok(nb_inputs), Ok(nb_outputs)) = (
+        record.attribute::<Activation>("activation"),
+        record.attribute::<usize>("inputs"),
+        record.attribute::<usize>("outputs"),
+    ) else {
+        return Err(fmt::Error);
+    };
+    let (Ok(weights), Ok(biases)) = (
+        record.tensor::<f64>("weights", nb_inputs * nb_outputs),
+        record.tensor::<f64>("biases", nb_outputs),
+    ) else {
+        return Err(fmt::Error);
+    };
+
+    for (index, bias) in biases.iter().enumerate() {
+        if index > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{} {}", activation, bias)?;
+        for w in &weights[index * nb_inputs..(index + 1) * nb_inputs] {
+            write!(f, " {}", w)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::datastruct::activation::Activation;
+    use crate::network::datastruct::layer_norm::LayerNorm;
+    use crate::network::datastruct::network::Network;
+
+    #[test]
+    fn test_text_round_trip_of_every_kind() {
+        let mut network = Network::new_random_he(
+            4,
+            vec![3, 2],
+            vec![0.25],
+            Activation::Tanh,
+            Activation::Linear,
+        );
+        let mut norm = LayerNorm::new(3);
+        norm.gamma = vec![0.5, 1.5, 2.0];
+        network.layers.insert(1, Box::new(norm));
+
+        let text = network.to_string();
+        assert!(text.contains("\n---\n@layernorm size=3 epsilon=0.00001\ngamma 0.5 1.5 2\n"));
+        assert!(text.contains("\n---\n@dropout size=3 rate=0.25\n---\n"));
+
+        let parsed: Network = Network::new(text.clone()).unwrap();
+        let kinds: Vec<_> = parsed.layers.iter().map(|l| l.kind()).collect();
+        assert_eq!(kinds, vec!["dense", "layernorm", "dropout", "dense"]);
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn test_header_errors() {
+        let parse = |text: &str| Network::<f64>::new(String::from(text));
+        let error = parse("relu 0 1\n---\n@layernorm size=1 epsilon\n")
+            .err()
+            .unwrap();
+        assert_eq!(
+            (error.line, error.layer, error.token),
+            (Some(3), Some(1), Some(2))
+        );
+
+        assert!(parse("relu 0 1\n---\n@pool size=1\n").is_err());
+        assert!(parse("relu 0 1\n---\n@dropout size=1 rate=1.5\n").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::network::autodiff::{self, Differentiable, Tape, Var};
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::layer::Layer;
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::r#trait::{Cache, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+
+/// Residual block: `outputs = body(x) + shortcut(x)`.
+///
+/// The shortcut is either the identity, which needs the body to keep the
+/// width, or a learned linear projection (a dense layer with a linear
+/// activation). The gradient reaches `x` through the shortcut unchanged,
+/// which keeps deep stacks trainable.
+pub struct Residual<T: Float = f64> {
+    pub body: Vec<Box<dyn NetworkElement<T>>>,
+    pub projection: Option<Layer<T>>,
+}
+
+impl<T: Float> Residual<T> {
+    pub fn new(body: Vec<Box<dyn NetworkElement<T>>>, projection: bool) -> Result<Self, String> {
+        let nb_inputs = body.first().ok_or("residual: empty block")?.nb_inputs();
+        let nb_outputs = body.last().unwrap().nb_outputs();
+        let projection = projection
+            .then(|| Layer::new_random_he(nb_outputs as u32, nb_inputs as u32, Activation::Linear));
+        let residual = Residual { body, projection };
+        residual.check_widths()?;
+        Ok(residual)
+    }
+
+    /// Children are the body elements, then the projection if any
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let projection: bool = record.attribute("projection")?;
+        let mut children = record
+            .children
+            .iter()
+            .map(LayerRecord::build::<T>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("residual: {}", e))?;
+
+        let projection = if projection {
+            let record = record.children.last().filter(|r| r.kind == "dense");
+            let layer = record.ok_or("residual: missing projection dense layer")?;
+            children.pop();
+            Some(Layer::from_record(layer)?)
+        } else {
+            None
+        };
+        if children.is_empty() {
+            return Err(String::from("residual: empty block"));
+        }
+
+        let residual = Residual {
+            body: children,
+            projection,
+        };
+        residual.check_widths()?;
+        Ok(residual)
+    }
+
+    fn check_widths(&self) -> Result<(), String> {
+        for (index, pair) in self.body.windows(2).enumerate() {
+            if pair[1].nb_inputs() != pair[0].nb_outputs() {
+                return Err(format!(
+                    "residual: {} layer takes {} inputs but body layer {} has {} outputs",
+                    pair[1].kind(),
+                    pair[1].nb_inputs(),
+                    index,
+                    pair[0].nb_outputs()
+                ));
+            }
+        }
+        let (nb_inputs, nb_outputs) = (self.nb_inputs(), self.nb_outputs());
+        match &self.projection {
+            None if nb_inputs != nb_outputs => Err(format!(
+                "residual: block maps {} values to {}, an identity shortcut needs a projection",
+                nb_inputs, nb_outputs
+            )),
+            Some(projection)
+                if projection.nb_inputs() != nb_inputs || projection.nb_outputs() != nb_outputs =>
+            {
+                Err(format!(
+                    "residual: projection maps {} values to {}, the block {} to {}",
+                    projection.nb_inputs(),
+                    projection.nb_outputs(),
+                    nb_inputs,
+                    nb_outputs
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: Float> Differentiable<T> for Residual<T> {
+    /// Every body element after the other, plus the shortcut. The elements
+    /// run their own passes, so the block has no leaf parameters.
+    fn record(&self, tape: &mut Tape<T>, inputs: Var) -> (Var, Vec<Var>) {
+        let mut outputs = inputs;
+        for element in &self.body {
+            outputs = tape.element(outputs, element.as_ref());
+        }
+        let shortcut = match &self.projection {
+            Some(projection) => tape.element(inputs, projection),
+            None => inputs,
+        };
+        (tape.add(outputs, shortcut), vec![])
+    }
+
+    /// The body elements, then the projection if any
+    fn elements(&self) -> Vec<&dyn NetworkElement<T>> {
+        self.body
+            .iter()
+            .map(|element| element.as_ref())
+            .chain(self.projection.iter().map(|p| p as &dyn NetworkElement<T>))
+            .collect()
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Residual<T> {
+    fn kind(&self) -> &'static str {
+        "residual"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.body[0].nb_inputs()
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.body.last().unwrap().nb_outputs()
+    }
+
+    fn set_training_mode(&mut self, training: bool) {
+        for element in &mut self.body {
+            element.set_training_mode(training);
+        }
+    }
+
+    fn couples_batch(&self) -> bool {
+        self.elements()
+            .iter()
+            .any(|element| element.couples_batch())
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        autodiff::forward(self, inputs)
+    }
+
+    /// Parameter gradients follow `parameters()`: body elements in order,
+    /// then the projection
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        autodiff::backward(self, cache, output_grads, need_input_grads)
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        self.elements()
+            .into_iter()
+            .flat_map(|element| element.parameters())
+            .collect()
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        let mut parameters: Vec<_> = self
+            .body
+            .iter_mut()
+            .flat_map(|element| element.parameters_mut())
+            .collect();
+        if let Some(projection) = &mut self.projection {
+            parameters.extend(projection.parameters_mut());
+        }
+        parameters
+    }
+
+    fn clip_weight_norms(&mut self, max_norm: f64) {
+        for element in &mut self.body {
+            element.clip_weight_norms(max_norm);
+        }
+        if let Some(projection) = &mut self.projection {
+            projection.clip_weight_norms(max_norm);
+        }
+    }
+
+    /// Statistics of every body element, each one prefixed by its length
+    /// (0 when the element keeps none)
+    fn batch_statistics(&self, cache: &Cache) -> Option<Vec<T>> {
+        let mut statistics = vec![];
+        let mut any = false;
+        let body_caches = autodiff::element_caches::<T>(cache);
+        for (element, element_cache) in self.body.iter().zip(body_caches) {
+            match element.batch_statistics(element_cache) {
+                Some(element_statistics) => {
+                    any = true;
+                    statistics.push(T::cast(element_statistics.len() as f64));
+                    statistics.extend(element_statistics);
+                }
+                None => statistics.push(T::zero()),
+            }
+        }
+        any.then_some(statistics)
+    }
+
+    fn update_statistics(&mut self, statistics: &[Vec<T>]) {
+        let mut offsets = vec![0; statistics.len()];
+        for element in &mut self.body {
+            let mut element_statistics = vec![];
+            for (chunk, offset) in statistics.iter().zip(offsets.iter_mut()) {
+                let len = chunk[*offset].as_f64() as usize;
+                if len > 0 {
+                    element_statistics.push(chunk[*offset + 1..*offset + 1 + len].to_vec());
+                }
+                *offset += 1 + len;
+            }
+            if !element_statistics.is_empty() {
+                element.update_statistics(&element_statistics);
+            }
+        }
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        self.elements().into_iter().fold(
+            LayerRecord::new("residual").with_attribute("projection", self.projection.is_some()),
+            |record, element| record.with_child(element.serialize()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::batch_norm::BatchNorm;
+    use crate::network::datastruct::binary::{self, Precision};
+    use crate::network::datastruct::metadata::Metadata;
+    use crate::network::datastruct::network::Network;
+    use crate::network::gradient_check::assert_gradients;
+
+    fn dense(nb_outputs: u32, nb_inputs: u32) -> Box<dyn NetworkElement> {
+        Box::new(Layer::new_random_he(
+            nb_outputs,
+            nb_inputs,
+            Activation::Tanh,
+        ))
+    }
+
+    #[test]
+    fn test_backward_matches_finite_differences() {
+        for projection in [false, true] {
+            let block = Residual::new(vec![dense(4, 3), dense(3, 4)], projection).unwrap();
+            let inputs = Matrix::new(2, 3, vec![0.3, -1.2, 0.7, 1.0, 0.5, -0.4]);
+            let weights = Matrix::new(2, 3, vec![1.0, -2.0, 0.5, 0.3, 0.8, -1.5]);
+            let loss = |x: &Matrix| -> f64 {
+                let (out, _) = block.forward(x);
+                out.data.iter().zip(&weights.data).map(|(o, w)| o * w).sum()
+            };
+
+            let (_, cache) = block.forward(&inputs);
+            let (input_grads, param_grads) = block.backward(&inputs, &cache, &weights, true);
+            let input_grads = input_grads.unwrap();
+            assert_eq!(param_grads.len(), block.parameters().len());
+
+            let h = 1e-6;
+            for i in 0..inputs.data.len() {
+                let mut plus = inputs.clone();
+                plus.data[i] += h;
+                let mut minus = inputs.clone();
+                minus.data[i] -= h;
+                let numeric = (loss(&plus) - loss(&minus)) / (2.0 * h);
+                assert!((numeric - input_grads.data[i]).abs() < 1e-5);
+            }
+        }
+        assert!(Residual::new(vec![dense(4, 3)], false).is_err());
+
+        // Parameter gradients, with a batch-coupled element in the body
+        let mut block = Residual::new(
+            vec![dense(4, 3), Box::new(BatchNorm::new(4)), dense(2, 4)],
+            true,
+        )
+        .unwrap();
+        block.set_training_mode(true);
+        let inputs = Matrix::new(3, 3, vec![0.3, -1.2, 0.7, 1.0, 0.5, -0.4, -0.6, 0.2, 0.9]);
+        assert_gradients(&mut block, &inputs);
+    }
+
+    #[test]
+    fn test_nested_blocks_round_trip() {
+        let inner = Residual::new(vec![dense(3, 3), Box::new(BatchNorm::new(3))], false).unwrap();
+        let outer = Residual::new(vec![dense(3, 2), Box::new(inner), dense(4, 3)], true).unwrap();
+        let network = Network {
+            layers: vec![Box::new(outer), dense(2, 4)],
+            metadata: Metadata::default(),
+        };
+
+        let text = network.to_string();
+        assert!(text.starts_with("@residual projection=true children=4\n"));
+        assert!(text.contains("@residual projection=false children=2\n"));
+        let parsed = Network::new(text.clone()).unwrap();
+        assert_eq!(parsed.layers.len(), 2);
+        assert_eq!(parsed.to_string(), text);
+
+        let decoded: Network = binary::decode(&binary::encode(&network, Precision::F64)).unwrap();
+        assert_eq!(decoded.to_string(), text);
+
+        let inputs = vec![0.4, -0.9];
+        assert_eq!(parsed.exec(inputs.clone()), network.exec(inputs));
+
+        let truncated = text.replacen("children=4", "children=5", 1);
+        assert!(Network::<f64>::new(truncated).is_err());
+    }
+}
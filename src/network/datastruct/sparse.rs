@@ -0,0 +1,230 @@
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::layer::Layer;
+use crate::network::datastruct::matrix::{axpy, dot, Matrix};
+use crate::network::datastruct::r#trait::{cache_ref, Cache, LinearInput, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+
+/// Fully connected layer for sparse inputs (NNUE-style first layer).
+///
+/// A board sets about 33 of its 833 one-hot inputs, so instead of a full
+/// product the layer sums the weight rows of the active features. Weights
+/// are stored feature-major (`nb_inputs × nb_outputs`, one row per input)
+/// so each feature's weights are contiguous, and backprop only touches the
+/// rows of the active features. It computes the same function as a dense
+/// layer, see `from_dense`.
+pub struct SparseDense<T = f64> {
+    pub activation: Activation,
+    pub weights: Matrix<T>,
+    pub biases: Vec<T>,
+}
+
+impl<T: Float> SparseDense<T> {
+    pub fn new_random_he(nb_outputs: usize, nb_inputs: usize, activation: Activation) -> Self {
+        Self::from_dense(&Layer::new_random_he(
+            nb_outputs as u32,
+            nb_inputs as u32,
+            activation,
+        ))
+    }
+
+    /// Same layer with transposed weights
+    pub fn from_dense(layer: &Layer<T>) -> Self {
+        let (nb_outputs, nb_inputs) = (layer.weights.rows, layer.weights.cols);
+        let mut weights = Matrix::zeros(nb_inputs, nb_outputs);
+        for o in 0..nb_outputs {
+            for (i, &w) in layer.weights.row(o).iter().enumerate() {
+                weights.row_mut(i)[o] = w;
+            }
+        }
+        SparseDense {
+            activation: layer.activation,
+            weights,
+            biases: layer.biases.clone(),
+        }
+    }
+
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let nb_inputs: usize = record.attribute("inputs")?;
+        let nb_outputs: usize = record.attribute("outputs")?;
+        Ok(SparseDense {
+            activation: record.attribute("activation")?,
+            weights: Matrix::new(
+                nb_inputs,
+                nb_outputs,
+                record.tensor("weights", nb_inputs * nb_outputs)?,
+            ),
+            biases: record.tensor("biases", nb_outputs)?,
+        })
+    }
+}
+
+/// Indices and values of the non-zero inputs of a sample
+fn active<T: Float>(inputs: &[T]) -> impl Iterator<Item = (usize, T)> + '_ {
+    inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, &x)| x != T::zero())
+        .map(|(i, &x)| (i, x))
+}
+
+impl<T: Float> NetworkElement<T> for SparseDense<T> {
+    fn kind(&self) -> &'static str {
+        "sparse_dense"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.weights.rows
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.weights.cols
+    }
+
+    /// Caches dy/dz for every output, like a dense layer
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        let mut outputs = Matrix::zeros(inputs.rows, self.nb_outputs());
+        let mut derivatives = Matrix::zeros(inputs.rows, self.nb_outputs());
+        for b in 0..inputs.rows {
+            let out = outputs.row_mut(b);
+            out.copy_from_slice(&self.biases);
+            for (feature, x) in active(inputs.row(b)) {
+                axpy(out, x, self.weights.row(feature));
+            }
+            for (out, d) in out.iter_mut().zip(derivatives.row_mut(b).iter_mut()) {
+                let z = *out;
+                *out = self.activation.apply(z);
+                *d = self.activation.derivative(z, *out);
+            }
+        }
+        (outputs, Box::new(derivatives))
+    }
+
+    /// Weight gradients are `x_i · δ` for the active features only; input
+    /// gradients (rarely needed for a first layer) are a dense product
+    fn backward(
+        &self,
+        inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let derivatives: &Matrix<T> = cache_ref(cache);
+        let mut deltas = output_grads.clone();
+        for (delta, &d) in deltas.data.iter_mut().zip(derivatives.data.iter()) {
+            *delta *= d;
+        }
+
+        let mut weight_grads = Matrix::zeros(self.weights.rows, self.weights.cols);
+        for b in 0..inputs.rows {
+            for (feature, x) in active(inputs.row(b)) {
+                axpy(weight_grads.row_mut(feature), x, deltas.row(b));
+            }
+        }
+
+        let input_grads = need_input_grads.then(|| {
+            let mut input_grads = Matrix::zeros(inputs.rows, inputs.cols);
+            for b in 0..inputs.rows {
+                for (g, w) in input_grads
+                    .row_mut(b)
+                    .iter_mut()
+                    .zip(self.weights.data.chunks(self.weights.cols))
+                {
+                    *g = dot(w, deltas.row(b));
+                }
+            }
+            input_grads
+        });
+        (input_grads, vec![weight_grads.data, deltas.column_sums()])
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![
+            (ParamKind::Weight, &self.weights.data),
+            (ParamKind::Bias, &self.biases),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![
+            (ParamKind::Weight, &mut self.weights.data),
+            (ParamKind::Bias, &mut self.biases),
+        ]
+    }
+
+    /// A unit's weights are a column of the feature-major matrix
+    fn clip_weight_norms(&mut self, max_norm: f64) {
+        self.weights.clip_column_norms(max_norm);
+    }
+
+    fn as_linear_input(&self) -> Option<&dyn LinearInput<T>> {
+        Some(self)
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("sparse_dense")
+            .with_attribute("activation", self.activation)
+            .with_attribute("inputs", self.nb_inputs())
+            .with_attribute("outputs", self.nb_outputs())
+            .with_tensor("weights", &self.weights.data)
+            .with_tensor("biases", &self.biases)
+    }
+}
+
+impl<T: Float> LinearInput<T> for SparseDense<T> {
+    fn biases(&self) -> &[T] {
+        &self.biases
+    }
+
+    /// One contiguous weight row per input
+    fn add_input(&self, sums: &mut [T], input: usize, scale: T) {
+        axpy(sums, scale, self.weights.row(input));
+    }
+
+    fn activation(&self) -> Activation {
+        self.activation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_dense_layer() {
+        let dense = Layer::<f64>::new_random_he(4, 6, Activation::Tanh);
+        let sparse = SparseDense::from_dense(&dense);
+        let inputs = Matrix::new(
+            2,
+            6,
+            vec![0.0, 1.0, 0.0, 0.0, 0.5, 0.0, 1.0, 0.0, 0.0, -2.0, 0.0, 1.0],
+        );
+        let output_grads = Matrix::new(2, 4, vec![0.3, -1.0, 0.2, 0.8, -0.5, 0.1, 1.2, 0.4]);
+
+        let (dense_out, dense_cache) = dense.forward(&inputs);
+        let (sparse_out, sparse_cache) = sparse.forward(&inputs);
+        for (a, b) in dense_out.data.iter().zip(&sparse_out.data) {
+            assert!((a - b).abs() < 1e-12);
+        }
+
+        let (dense_input, dense_grads) = dense.backward(&inputs, &dense_cache, &output_grads, true);
+        let (sparse_input, sparse_grads) =
+            sparse.backward(&inputs, &sparse_cache, &output_grads, true);
+        for (a, b) in dense_input
+            .unwrap()
+            .data
+            .iter()
+            .zip(&sparse_input.unwrap().data)
+        {
+            assert!((a - b).abs() < 1e-12);
+        }
+        // Same gradients, transposed
+        for o in 0..4 {
+            for i in 0..6 {
+                assert!((dense_grads[0][o * 6 + i] - sparse_grads[0][i * 4 + o]).abs() < 1e-12);
+            }
+        }
+        assert_eq!(dense_grads[1], sparse_grads[1]);
+    }
+}
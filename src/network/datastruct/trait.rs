@@ -1,20 +1,95 @@
-trait NetworkElement {
-    pub fn new(config: String) -> Result<Self, String> {
-        let mut tokens = config.split_whitespace();
-        let f_id = String::from(tokens.next().unwrap());
-        let f = function_getter(f_id.clone())?;
-        let b: f64 = tokens.next().unwrap().parse().unwrap();
-        let w: Vec<f64> = tokens.map(f64::from_str).map(Result::unwrap).collect();
-        Ok(Perceptron {
-            func: f,
-            func_id: f_id,
-            weights: w,
-            biais: b,
-        })
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+use std::any::Any;
+
+/// Whatever an element keeps from its forward pass for the backward pass
+/// (activation derivatives, dropout mask, normalized inputs...).
+pub type Cache = Box<dyn Any + Send>;
+
+/// One element of a network's layer stack.
+///
+/// Every element works on batches, one sample per row, of `T` values. A
+/// network is a `Vec<Box<dyn NetworkElement<T>>>` where each element's
+/// `nb_inputs` equals the previous element's `nb_outputs`.
+pub trait NetworkElement<T: Float = f64>: Send + Sync {
+    /// Name of the element in model files (`dense`, `dropout`, ...)
+    fn kind(&self) -> &'static str;
+
+    fn nb_inputs(&self) -> usize;
+
+    fn nb_outputs(&self) -> usize;
+
+    /// Switch between training and inference behaviour (dropout...)
+    fn set_training_mode(&mut self, _training: bool) {}
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache);
+
+    /// Backpropagate `output_grads` (dL/d outputs). Returns dL/d inputs when
+    /// `need_input_grads` is set, and the gradients of `parameters()`, in
+    /// the same order, summed over the batch.
+    fn backward(
+        &self,
+        inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>);
+
+    /// Trainable parameter groups
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![]
+    }
+
+    /// Whether, in training mode, the outputs of a sample depend on the
+    /// other samples of the batch (batch normalization)
+    fn couples_batch(&self) -> bool {
+        false
     }
 
-    pub fn new_random(nb_weight: u32, w_range: &(f64, f64), b_range: &(f64, f64)) -> Self;
+    /// Statistics of a training batch the element wants to keep (e.g. the
+    /// batch mean and variance of batch normalization)
+    fn batch_statistics(&self, _cache: &Cache) -> Option<Vec<T>> {
+        None
+    }
+
+    /// Fold in the `batch_statistics` of every chunk of a training step,
+    /// in chunk order
+    fn update_statistics(&mut self, _statistics: &[Vec<T>]) {}
+
+    /// Max-norm constraint: scale down the incoming weights of every unit
+    /// whose L2 norm exceeds `max_norm`
+    fn clip_weight_norms(&mut self, _max_norm: f64) {}
+
+    /// The element as a linear input layer, if it is one
+    fn as_linear_input(&self) -> Option<&dyn LinearInput<T>> {
+        None
+    }
+
+    fn serialize(&self) -> LayerRecord;
+}
+
+/// Layer computing `activation(biases + Σ x_i · weights_i)`, whose
+/// pre-activations can be kept up to date input by input (see
+/// `Accumulator`)
+pub trait LinearInput<T: Float = f64> {
+    fn biases(&self) -> &[T];
+
+    /// `sums += scale · (weights of input)`
+    fn add_input(&self, sums: &mut [T], input: usize, scale: T);
+
+    fn activation(&self) -> Activation;
+}
 
-    pub fn exec<T>(&self, inputs: &Vec<T>) -> T;
-    pub fn to_string(&self) -> String;
+/// Downcast a cache created by the same element's `forward`
+pub fn cache_ref<C: 'static>(cache: &Cache) -> &C {
+    cache
+        .downcast_ref::<C>()
+        .expect("cache was not created by this element")
 }
@@ -0,0 +1,636 @@
+//! Transformer encoder over the squares of the board.
+//!
+//! Each sample is a sequence of tokens, stored token after token in its row
+//! (`tokens × dim` values). `Embedding` turns the board planes into one
+//! token per square, `Encoder` is one self-attention + feed-forward layer
+//! and `Pool` averages the tokens into a vector for the dense head.
+//!
+//! Within a batch, the `B × (tokens · dim)` matrix has the same data as the
+//! `(B · tokens) × dim` matrix of all tokens, so token-wise projections and
+//! norms are plain dense and layernorm elements over that second view.
+
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::layer::Layer;
+use crate::network::datastruct::layer_norm::LayerNorm;
+use crate::network::datastruct::matrix::{axpy, dot, Matrix};
+use crate::network::datastruct::r#trait::{cache_ref, Cache, NetworkElement};
+use crate::network::datastruct::record::LayerRecord;
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+use crate::network::random;
+use rand_distr::{Distribution, Normal};
+
+/// Standard deviation of the initial embedding tables
+const EMBEDDING_INIT_STD: f64 = 0.1;
+
+/// Token of every square: the embedding of its one-hot planes plus a
+/// learned positional embedding, plus the embedding of the `extra` values
+/// (the side to move), which is shared by every token.
+pub struct Embedding<T = f64> {
+    pub tokens: usize,
+    pub channels: usize,
+    pub extra: usize,
+    /// `dim × channels`
+    pub pieces: Matrix<T>,
+    /// `tokens × dim`
+    pub positions: Matrix<T>,
+    /// `dim × extra`
+    pub extra_weights: Matrix<T>,
+}
+
+impl<T: Float> Embedding<T> {
+    pub fn new_random(tokens: usize, channels: usize, extra: usize, dim: usize) -> Self {
+        let mut rng = random::rng();
+        let normal = Normal::new(0.0, EMBEDDING_INIT_STD).unwrap();
+        let mut random = |rows: usize, cols: usize| {
+            Matrix::new(
+                rows,
+                cols,
+                (0..rows * cols)
+                    .map(|_| T::cast(normal.sample(&mut rng)))
+                    .collect(),
+            )
+        };
+        Embedding {
+            tokens,
+            channels,
+            extra,
+            pieces: random(dim, channels),
+            positions: random(tokens, dim),
+            extra_weights: random(dim, extra),
+        }
+    }
+
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let tokens: usize = record.attribute("tokens")?;
+        let channels: usize = record.attribute("channels")?;
+        let extra: usize = record.attribute("extra")?;
+        let dim: usize = record.attribute("dim")?;
+        Ok(Embedding {
+            tokens,
+            channels,
+            extra,
+            pieces: Matrix::new(dim, channels, record.tensor("pieces", dim * channels)?),
+            positions: Matrix::new(tokens, dim, record.tensor("positions", tokens * dim)?),
+            extra_weights: Matrix::new(dim, extra, record.tensor("extra", dim * extra)?),
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.pieces.rows
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Embedding<T> {
+    fn kind(&self) -> &'static str {
+        "embedding"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.tokens * self.channels + self.extra
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.tokens * self.dim()
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        let planes_size = self.tokens * self.channels;
+        let mut outputs = Matrix::zeros(inputs.rows, self.nb_outputs());
+        for b in 0..inputs.rows {
+            let sample = inputs.row(b);
+            let shared = self.extra_weights.mul_vec(&sample[planes_size..]);
+            for (t, token) in outputs.row_mut(b).chunks_mut(self.dim()).enumerate() {
+                let square = &sample[t * self.channels..(t + 1) * self.channels];
+                for (d, value) in token.iter_mut().enumerate() {
+                    *value = dot(self.pieces.row(d), square) + self.positions.row(t)[d] + shared[d];
+                }
+            }
+        }
+        (outputs, Box::new(()))
+    }
+
+    fn backward(
+        &self,
+        inputs: &Matrix<T>,
+        _cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let planes_size = self.tokens * self.channels;
+        let dim = self.dim();
+        let mut piece_grads = Matrix::zeros(dim, self.channels);
+        let mut extra_grads = Matrix::zeros(dim, self.extra);
+        let mut input_grads = Matrix::zeros(inputs.rows, inputs.cols);
+
+        for b in 0..inputs.rows {
+            let sample = inputs.row(b);
+            let mut token_sum = vec![T::zero(); dim];
+            for (t, dy) in output_grads.row(b).chunks(dim).enumerate() {
+                let square = &sample[t * self.channels..(t + 1) * self.channels];
+                for (d, &g) in dy.iter().enumerate() {
+                    axpy(piece_grads.row_mut(d), g, square);
+                }
+                axpy(&mut token_sum, T::one(), dy);
+                if need_input_grads {
+                    let dx = &mut input_grads.row_mut(b)[t * self.channels..];
+                    for (d, &g) in dy.iter().enumerate() {
+                        axpy(&mut dx[..self.channels], g, self.pieces.row(d));
+                    }
+                }
+            }
+            for (d, &g) in token_sum.iter().enumerate() {
+                axpy(extra_grads.row_mut(d), g, &sample[planes_size..]);
+                if need_input_grads {
+                    axpy(
+                        &mut input_grads.row_mut(b)[planes_size..],
+                        g,
+                        self.extra_weights.row(d),
+                    );
+                }
+            }
+        }
+
+        let position_grads = output_grads.column_sums();
+        (
+            need_input_grads.then_some(input_grads),
+            vec![piece_grads.data, position_grads, extra_grads.data],
+        )
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        vec![
+            (ParamKind::Weight, &self.pieces.data),
+            (ParamKind::Weight, &self.positions.data),
+            (ParamKind::Weight, &self.extra_weights.data),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        vec![
+            (ParamKind::Weight, &mut self.pieces.data),
+            (ParamKind::Weight, &mut self.positions.data),
+            (ParamKind::Weight, &mut self.extra_weights.data),
+        ]
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("embedding")
+            .with_attribute("tokens", self.tokens)
+            .with_attribute("channels", self.channels)
+            .with_attribute("extra", self.extra)
+            .with_attribute("dim", self.dim())
+            .with_tensor("pieces", &self.pieces.data)
+            .with_tensor("positions", &self.positions.data)
+            .with_tensor("extra", &self.extra_weights.data)
+    }
+}
+
+/// One post-norm encoder layer:
+/// `y = norm1(x + attention(x))`, `outputs = norm2(y + ff(y))`,
+/// with multi-head scaled dot-product attention over the tokens of a
+/// sample and a two-layer feed-forward block applied to every token.
+pub struct Encoder<T = f64> {
+    pub tokens: usize,
+    pub heads: usize,
+    /// Queries, keys and values of a token (`3·dim × dim`, linear)
+    pub qkv: Layer<T>,
+    /// Mix of the heads' outputs (`dim × dim`, linear)
+    pub output: Layer<T>,
+    pub norm1: LayerNorm<T>,
+    pub hidden: Layer<T>,
+    /// Back to `dim` values (linear)
+    pub feed: Layer<T>,
+    pub norm2: LayerNorm<T>,
+}
+
+struct EncoderCache<T> {
+    x: Matrix<T>,
+    qkv: Matrix<T>,
+    qkv_cache: Cache,
+    /// Attention weights, `heads × tokens × tokens` per sample
+    weights: Vec<T>,
+    attended: Matrix<T>,
+    output_cache: Cache,
+    r1: Matrix<T>,
+    norm1_cache: Cache,
+    y1: Matrix<T>,
+    hidden: Matrix<T>,
+    hidden_cache: Cache,
+    feed_cache: Cache,
+    r2: Matrix<T>,
+    norm2_cache: Cache,
+}
+
+impl<T: Float> Encoder<T> {
+    pub fn new_random(
+        tokens: usize,
+        dim: usize,
+        heads: usize,
+        feed_forward: usize,
+        activation: Activation,
+    ) -> Result<Self, String> {
+        let dense = |outputs: usize, inputs: usize, activation| {
+            Layer::new_random_he(outputs as u32, inputs as u32, activation)
+        };
+        let encoder = Encoder {
+            tokens,
+            heads,
+            qkv: dense(3 * dim, dim, Activation::Linear),
+            output: dense(dim, dim, Activation::Linear),
+            norm1: LayerNorm::new(dim),
+            hidden: dense(feed_forward, dim, activation),
+            feed: dense(dim, feed_forward, Activation::Linear),
+            norm2: LayerNorm::new(dim),
+        };
+        encoder.check()?;
+        Ok(encoder)
+    }
+
+    /// Children: qkv, output, norm1, hidden, feed, norm2
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        let [qkv, output, norm1, hidden, feed, norm2] = record.children.as_slice() else {
+            return Err(format!(
+                "encoder: expected 6 child layers, got {}",
+                record.children.len()
+            ));
+        };
+        let encoder = Encoder {
+            tokens: record.attribute("tokens")?,
+            heads: record.attribute("heads")?,
+            qkv: Layer::from_record(qkv)?,
+            output: Layer::from_record(output)?,
+            norm1: LayerNorm::from_record(norm1)?,
+            hidden: Layer::from_record(hidden)?,
+            feed: Layer::from_record(feed)?,
+            norm2: LayerNorm::from_record(norm2)?,
+        };
+        encoder.check()?;
+        Ok(encoder)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let dim = self.dim();
+        if self.heads == 0 || !dim.is_multiple_of(self.heads) {
+            return Err(format!(
+                "encoder: {} heads do not divide the token size {}",
+                self.heads, dim
+            ));
+        }
+        let widths = [
+            (self.qkv.nb_outputs(), 3 * dim),
+            (self.output.nb_inputs(), dim),
+            (self.output.nb_outputs(), dim),
+            (self.norm1.nb_inputs(), dim),
+            (self.hidden.nb_inputs(), dim),
+            (self.feed.nb_inputs(), self.hidden.nb_outputs()),
+            (self.feed.nb_outputs(), dim),
+            (self.norm2.nb_inputs(), dim),
+        ];
+        if widths.iter().any(|(width, expected)| width != expected) {
+            return Err(format!(
+                "encoder: sub-layer widths do not match the token size {}",
+                dim
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn dim(&self) -> usize {
+        self.qkv.nb_inputs()
+    }
+
+    fn elements(&self) -> [&dyn NetworkElement<T>; 6] {
+        [
+            &self.qkv,
+            &self.output,
+            &self.norm1,
+            &self.hidden,
+            &self.feed,
+            &self.norm2,
+        ]
+    }
+
+    /// `softmax(Q Kᵀ / √d_head) V` for every sample and head. Returns the
+    /// heads' outputs side by side and the attention weights.
+    fn attend(&self, qkv: &Matrix<T>) -> (Matrix<T>, Vec<T>) {
+        let (t, dim) = (self.tokens, self.dim());
+        let head_dim = dim / self.heads;
+        let scale = T::cast(1.0 / (head_dim as f64).sqrt());
+        let batch = qkv.rows / t;
+
+        let mut attended = Matrix::zeros(qkv.rows, dim);
+        let mut weights = vec![T::zero(); batch * self.heads * t * t];
+        for b in 0..batch {
+            for h in 0..self.heads {
+                let head = h * head_dim..(h + 1) * head_dim;
+                for i in 0..t {
+                    let q = &qkv.row(b * t + i)[head.clone()];
+                    let start = ((b * self.heads + h) * t + i) * t;
+                    let w = &mut weights[start..start + t];
+                    for (j, w) in w.iter_mut().enumerate() {
+                        let k = &qkv.row(b * t + j)[dim..][head.clone()];
+                        *w = dot(q, k) * scale;
+                    }
+                    let max = w.iter().cloned().fold(T::neg_infinity(), T::max);
+                    let mut sum = T::zero();
+                    for w in w.iter_mut() {
+                        *w = (*w - max).exp();
+                        sum += *w;
+                    }
+                    let out = &mut attended.row_mut(b * t + i)[head.clone()];
+                    for (j, w) in w.iter_mut().enumerate() {
+                        *w /= sum;
+                        axpy(out, *w, &qkv.row(b * t + j)[2 * dim..][head.clone()]);
+                    }
+                }
+            }
+        }
+        (attended, weights)
+    }
+
+    /// Gradients of the queries, keys and values from those of the heads'
+    /// outputs. With `S` the scaled scores and `A = softmax(S)`:
+    /// `dV = Aᵀ dO`, `dA = dO Vᵀ`, `dS = A ⊙ (dA - rowsum(dA ⊙ A))`,
+    /// `dQ = dS K · scale`, `dK = dSᵀ Q · scale`.
+    fn attend_backward(
+        &self,
+        qkv: &Matrix<T>,
+        weights: &[T],
+        attended_grads: &Matrix<T>,
+    ) -> Matrix<T> {
+        let (t, dim) = (self.tokens, self.dim());
+        let head_dim = dim / self.heads;
+        let scale = T::cast(1.0 / (head_dim as f64).sqrt());
+        let batch = qkv.rows / t;
+
+        let mut grads = Matrix::zeros(qkv.rows, 3 * dim);
+        let mut weight_grads = vec![T::zero(); t];
+        for b in 0..batch {
+            for h in 0..self.heads {
+                let head = h * head_dim..(h + 1) * head_dim;
+                for i in 0..t {
+                    let start = ((b * self.heads + h) * t + i) * t;
+                    let w = &weights[start..start + t];
+                    let d_out = &attended_grads.row(b * t + i)[head.clone()];
+
+                    for (j, dw) in weight_grads.iter_mut().enumerate() {
+                        *dw = dot(d_out, &qkv.row(b * t + j)[2 * dim..][head.clone()]);
+                        axpy(
+                            &mut grads.row_mut(b * t + j)[2 * dim..][head.clone()],
+                            w[j],
+                            d_out,
+                        );
+                    }
+                    let weighted = dot(w, &weight_grads);
+                    let q = &qkv.row(b * t + i)[head.clone()];
+                    for j in 0..t {
+                        let ds = w[j] * (weight_grads[j] - weighted) * scale;
+                        let k = &qkv.row(b * t + j)[dim..][head.clone()];
+                        axpy(&mut grads.row_mut(b * t + i)[head.clone()], ds, k);
+                        axpy(&mut grads.row_mut(b * t + j)[dim..][head.clone()], ds, q);
+                    }
+                }
+            }
+        }
+        grads
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Encoder<T> {
+    fn kind(&self) -> &'static str {
+        "encoder"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.tokens * self.dim()
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.tokens * self.dim()
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        let x = Matrix::new(inputs.rows * self.tokens, self.dim(), inputs.data.clone());
+        let (qkv, qkv_cache) = self.qkv.forward(&x);
+        let (attended, weights) = self.attend(&qkv);
+        let (mut r1, output_cache) = self.output.forward(&attended);
+        axpy(&mut r1.data, T::one(), &x.data);
+        let (y1, norm1_cache) = self.norm1.forward(&r1);
+
+        let (hidden, hidden_cache) = self.hidden.forward(&y1);
+        let (mut r2, feed_cache) = self.feed.forward(&hidden);
+        axpy(&mut r2.data, T::one(), &y1.data);
+        let (y2, norm2_cache) = self.norm2.forward(&r2);
+
+        let outputs = Matrix::new(inputs.rows, inputs.cols, y2.data);
+        let cache = EncoderCache {
+            x,
+            qkv,
+            qkv_cache,
+            weights,
+            attended,
+            output_cache,
+            r1,
+            norm1_cache,
+            y1,
+            hidden,
+            hidden_cache,
+            feed_cache,
+            r2,
+            norm2_cache,
+        };
+        (outputs, Box::new(cache))
+    }
+
+    fn backward(
+        &self,
+        inputs: &Matrix<T>,
+        cache: &Cache,
+        output_grads: &Matrix<T>,
+        need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let c: &EncoderCache<T> = cache_ref(cache);
+        let y2_grads = Matrix::new(c.x.rows, c.x.cols, output_grads.data.clone());
+
+        let (r2_grads, norm2_grads) = self.norm2.backward(&c.r2, &c.norm2_cache, &y2_grads, true);
+        let r2_grads = r2_grads.unwrap();
+        let (hidden_grads, feed_grads) =
+            self.feed
+                .backward(&c.hidden, &c.feed_cache, &r2_grads, true);
+        let (y1_ff_grads, hidden_param_grads) =
+            self.hidden
+                .backward(&c.y1, &c.hidden_cache, &hidden_grads.unwrap(), true);
+        let mut y1_grads = r2_grads;
+        axpy(&mut y1_grads.data, T::one(), &y1_ff_grads.unwrap().data);
+
+        let (r1_grads, norm1_grads) = self.norm1.backward(&c.r1, &c.norm1_cache, &y1_grads, true);
+        let r1_grads = r1_grads.unwrap();
+        let (attended_grads, output_grads) =
+            self.output
+                .backward(&c.attended, &c.output_cache, &r1_grads, true);
+        let qkv_grads = self.attend_backward(&c.qkv, &c.weights, &attended_grads.unwrap());
+        let (x_grads, qkv_param_grads) =
+            self.qkv
+                .backward(&c.x, &c.qkv_cache, &qkv_grads, need_input_grads);
+
+        let input_grads = x_grads.map(|mut x_grads| {
+            axpy(&mut x_grads.data, T::one(), &r1_grads.data);
+            Matrix::new(inputs.rows, inputs.cols, x_grads.data)
+        });
+        let param_grads = [
+            qkv_param_grads,
+            output_grads,
+            norm1_grads,
+            hidden_param_grads,
+            feed_grads,
+            norm2_grads,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        (input_grads, param_grads)
+    }
+
+    fn parameters(&self) -> Vec<(ParamKind, &[T])> {
+        self.elements()
+            .into_iter()
+            .flat_map(|element| element.parameters())
+            .collect()
+    }
+
+    fn parameters_mut(&mut self) -> Vec<(ParamKind, &mut [T])> {
+        let mut parameters = self.qkv.parameters_mut();
+        parameters.extend(self.output.parameters_mut());
+        parameters.extend(self.norm1.parameters_mut());
+        parameters.extend(self.hidden.parameters_mut());
+        parameters.extend(self.feed.parameters_mut());
+        parameters.extend(self.norm2.parameters_mut());
+        parameters
+    }
+
+    fn clip_weight_norms(&mut self, max_norm: f64) {
+        for layer in [
+            &mut self.qkv,
+            &mut self.output,
+            &mut self.hidden,
+            &mut self.feed,
+        ] {
+            layer.clip_weight_norms(max_norm);
+        }
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        self.elements().into_iter().fold(
+            LayerRecord::new("encoder")
+                .with_attribute("tokens", self.tokens)
+                .with_attribute("heads", self.heads),
+            |record, element| record.with_child(element.serialize()),
+        )
+    }
+}
+
+/// Mean of the tokens of every sample
+pub struct Pool {
+    pub tokens: usize,
+    pub dim: usize,
+}
+
+impl Pool {
+    pub fn from_record(record: &LayerRecord) -> Result<Self, String> {
+        Ok(Pool {
+            tokens: record.attribute("tokens")?,
+            dim: record.attribute("dim")?,
+        })
+    }
+}
+
+impl<T: Float> NetworkElement<T> for Pool {
+    fn kind(&self) -> &'static str {
+        "pool"
+    }
+
+    fn nb_inputs(&self) -> usize {
+        self.tokens * self.dim
+    }
+
+    fn nb_outputs(&self) -> usize {
+        self.dim
+    }
+
+    fn forward(&self, inputs: &Matrix<T>) -> (Matrix<T>, Cache) {
+        let share = T::cast(1.0 / self.tokens as f64);
+        let mut outputs = Matrix::zeros(inputs.rows, self.dim);
+        for b in 0..inputs.rows {
+            for token in inputs.row(b).chunks(self.dim) {
+                axpy(outputs.row_mut(b), share, token);
+            }
+        }
+        (outputs, Box::new(()))
+    }
+
+    fn backward(
+        &self,
+        _inputs: &Matrix<T>,
+        _cache: &Cache,
+        output_grads: &Matrix<T>,
+        _need_input_grads: bool,
+    ) -> (Option<Matrix<T>>, Vec<Vec<T>>) {
+        let share = T::cast(1.0 / self.tokens as f64);
+        let mut input_grads = Matrix::zeros(output_grads.rows, self.tokens * self.dim);
+        for b in 0..output_grads.rows {
+            for token in input_grads.row_mut(b).chunks_mut(self.dim) {
+                axpy(token, share, output_grads.row(b));
+            }
+        }
+        (Some(input_grads), vec![])
+    }
+
+    fn serialize(&self) -> LayerRecord {
+        LayerRecord::new("pool")
+            .with_attribute("tokens", self.tokens)
+            .with_attribute("dim", self.dim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::gradient_check::assert_gradients;
+
+    fn inputs(rows: usize, cols: usize) -> Matrix {
+        Matrix::new(
+            rows,
+            cols,
+            (0..rows * cols)
+                .map(|i| ((i * 5 % 11) as f64 - 5.0) / 4.0)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_encoder_gradients() {
+        let mut encoder = Encoder::<f64>::new_random(3, 4, 2, 5, Activation::Tanh).unwrap();
+        assert_gradients(&mut encoder, &inputs(2, 12));
+        assert!(Encoder::<f64>::new_random(3, 4, 3, 5, Activation::Relu).is_err());
+    }
+
+    #[test]
+    fn test_embedding_and_pool_gradients() {
+        let mut embedding = Embedding::<f64>::new_random(4, 3, 1, 2);
+        assert_gradients(&mut embedding, &inputs(2, 13));
+        let mut pool = Pool { tokens: 4, dim: 2 };
+        assert_gradients(&mut pool, &inputs(2, 8));
+    }
+
+    #[test]
+    fn test_attention_rows_sum_to_one() {
+        let encoder = Encoder::<f64>::new_random(5, 4, 2, 3, Activation::Relu).unwrap();
+        let x = inputs(10, 4);
+        let (qkv, _) = encoder.qkv.forward(&x);
+        let (_, weights) = encoder.attend(&qkv);
+        for row in weights.chunks(5) {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+    }
+}
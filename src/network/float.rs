@@ -0,0 +1,89 @@
+//! Floating point type of weights, activations and gradients.
+//!
+//! Networks and everything that trains them are generic over `Float`,
+//! `f64` by default. Files, checkpoints and optimizer states store `f64`,
+//! converted with `cast` and `as_f64` on the way in and out.
+
+use crate::network::datastruct::binary::Precision;
+use num_traits::NumAssign;
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+use std::str::FromStr;
+
+pub trait Float:
+    num_traits::Float + NumAssign + Sum + Default + Debug + Display + FromStr + Send + Sync + 'static
+{
+    const PRECISION: Precision;
+
+    /// Nearest value to `x`
+    fn cast(x: f64) -> Self;
+
+    /// Exact conversion to `f64`
+    fn as_f64(self) -> f64;
+
+    /// The `f64` with the same shortest decimal form, so that an `f32` 0.1
+    /// is saved as 0.1 rather than 0.10000000149011612. Converting it back
+    /// with `cast` gives the value again.
+    fn to_decimal(self) -> f64;
+}
+
+impl Float for f32 {
+    const PRECISION: Precision = Precision::F32;
+
+    fn cast(x: f64) -> Self {
+        x as f32
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn to_decimal(self) -> f64 {
+        if self.is_finite() {
+            self.to_string().parse().unwrap()
+        } else {
+            self as f64
+        }
+    }
+}
+
+impl Float for f64 {
+    const PRECISION: Precision = Precision::F64;
+
+    fn cast(x: f64) -> Self {
+        x
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+
+    fn to_decimal(self) -> f64 {
+        self
+    }
+}
+
+/// `values` converted with `Float::cast`
+pub fn cast_all<T: Float>(values: &[f64]) -> Vec<T> {
+    values.iter().map(|&x| T::cast(x)).collect()
+}
+
+/// `values` converted with `Float::as_f64`
+pub fn widen_all<T: Float>(values: &[T]) -> Vec<f64> {
+    values.iter().map(|&x| x.as_f64()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(0.1f32.to_decimal(), 0.1);
+        assert_eq!(f32::cast(0.1f32.to_decimal()), 0.1f32);
+        assert_eq!(0.1f32.as_f64(), 0.10000000149011612);
+        assert_eq!(f32::INFINITY.to_decimal(), f64::INFINITY);
+        assert_eq!(f32::cast(1e40), f32::INFINITY);
+        assert_eq!(cast_all::<f32>(&[0.5, -2.0]), vec![0.5f32, -2.0]);
+    }
+}
@@ -0,0 +1,422 @@
+use std::fmt;
+
+use crate::network::datastruct::matrix::{dot, Matrix};
+use crate::network::datastruct::network::Network;
+use crate::network::datastruct::r#trait::NetworkElement;
+use crate::network::loss::Loss;
+use crate::network::random;
+use rand::seq::index;
+use rand::Rng;
+
+pub const DEFAULT_STEP: f64 = 1e-5;
+pub const DEFAULT_TOLERANCE: f64 = 1e-4;
+
+/// Numerical verification of backprop.
+///
+/// Each checked value `p` is moved to `p ± step` and the central difference
+/// `(L(p + step) - L(p - step)) / 2·step` is compared with the analytic
+/// gradient. The error is `|analytic - numeric| / max(|analytic| +
+/// |numeric|, 1e-3)`, relative for large gradients and absolute for tiny
+/// ones. Kinks (relu at 0, max) can fail on otherwise correct code.
+pub struct GradientCheck {
+    pub step: f64,
+    pub tolerance: f64,
+    /// Values checked per parameter group, picked at random; all of them
+    /// when `None`
+    pub samples: Option<usize>,
+}
+
+/// Where a checked gradient lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checked {
+    /// Parameter group of the layer, in `parameters()` order
+    Parameter(usize),
+    /// dL/d inputs of the element, see `check_element`
+    Input,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub layer: usize,
+    pub checked: Checked,
+    pub index: usize,
+    pub analytic: f64,
+    pub numeric: f64,
+}
+
+/// Number of checked values and largest error of one layer
+#[derive(Debug, Clone)]
+pub struct LayerSummary {
+    pub kind: &'static str,
+    pub checked: usize,
+    pub max_error: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct GradientReport {
+    pub tolerance: f64,
+    pub layers: Vec<LayerSummary>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Default for GradientCheck {
+    fn default() -> Self {
+        GradientCheck {
+            step: DEFAULT_STEP,
+            tolerance: DEFAULT_TOLERANCE,
+            samples: None,
+        }
+    }
+}
+
+pub fn relative_error(analytic: f64, numeric: f64) -> f64 {
+    (analytic - numeric).abs() / (analytic.abs() + numeric.abs()).max(1e-3)
+}
+
+impl GradientReport {
+    fn new(tolerance: f64) -> Self {
+        GradientReport {
+            tolerance,
+            layers: vec![],
+            mismatches: vec![],
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    fn record(
+        &mut self,
+        layer: usize,
+        checked: Checked,
+        index: usize,
+        analytic: f64,
+        numeric: f64,
+    ) {
+        let error = relative_error(analytic, numeric);
+        let summary = &mut self.layers[layer];
+        summary.checked += 1;
+        summary.max_error = summary.max_error.max(error);
+        if error > self.tolerance || error.is_nan() {
+            self.mismatches.push(Mismatch {
+                layer,
+                checked,
+                index,
+                analytic,
+                numeric,
+            });
+        }
+    }
+}
+
+impl fmt::Display for GradientReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, layer) in self.layers.iter().enumerate() {
+            writeln!(
+                f,
+                "layer {} ({}): {} values checked, max error {:.2e}",
+                index, layer.kind, layer.checked, layer.max_error
+            )?;
+        }
+        for mismatch in &self.mismatches {
+            let place = match mismatch.checked {
+                Checked::Parameter(group) => format!("parameter group {}", group),
+                Checked::Input => String::from("input"),
+            };
+            writeln!(
+                f,
+                "MISMATCH layer {} ({}) {} index {}: backprop {:.6e}, numeric {:.6e}",
+                mismatch.layer,
+                self.layers[mismatch.layer].kind,
+                place,
+                mismatch.index,
+                mismatch.analytic,
+                mismatch.numeric
+            )?;
+        }
+        if self.is_ok() {
+            write!(f, "OK: every gradient within {:.0e}", self.tolerance)
+        } else {
+            write!(
+                f,
+                "FAILED: {} gradients off by more than {:.0e}",
+                self.mismatches.len(),
+                self.tolerance
+            )
+        }
+    }
+}
+
+impl GradientCheck {
+    fn indices(&self, len: usize) -> Vec<usize> {
+        match self.samples {
+            Some(samples) if samples < len => {
+                let mut indices = index::sample(&mut random::rng(), len, samples).into_vec();
+                indices.sort_unstable();
+                indices
+            }
+            _ => (0..len).collect(),
+        }
+    }
+
+    /// Central difference of `loss` around value `index` of `params`
+    fn perturb<E: ?Sized>(
+        &self,
+        target: &mut E,
+        params: impl Fn(&mut E) -> &mut [f64],
+        index: usize,
+        loss: impl Fn(&E) -> f64,
+    ) -> f64 {
+        let original = params(target)[index];
+        params(target)[index] = original + self.step;
+        let plus = loss(target);
+        params(target)[index] = original - self.step;
+        let minus = loss(target);
+        params(target)[index] = original;
+        (plus - minus) / (2.0 * self.step)
+    }
+
+    /// Check one element's parameter and input gradients on a fixed
+    /// weighted sum of its outputs, in its current training mode. The
+    /// generator is reseeded before every forward pass, so that in training
+    /// mode they all draw the same dropout masks.
+    #[cfg(test)]
+    pub fn check_element(
+        &self,
+        element: &mut dyn NetworkElement,
+        inputs: &Matrix,
+    ) -> GradientReport {
+        let mut report = GradientReport::new(self.tolerance);
+        self.check_element_into(&mut report, element, inputs);
+        report
+    }
+
+    /// `check_element` on every layer of `network`, fed the activations of
+    /// `batch`, in training or inference mode. Unlike `check_network`, it
+    /// reaches the input gradients, those of layers without parameters
+    /// included.
+    pub fn check_layers(
+        &self,
+        network: &mut Network,
+        batch: &[(Vec<f64>, Vec<f64>)],
+        training: bool,
+    ) -> GradientReport {
+        network.set_training_mode(training);
+        let inputs: Vec<Vec<f64>> = batch.iter().map(|(inputs, _)| inputs.clone()).collect();
+        let (all_outputs, _) = network.forward(&Matrix::from_rows(&inputs));
+
+        let mut report = GradientReport::new(self.tolerance);
+        for (layer, inputs) in network.layers.iter_mut().zip(&all_outputs) {
+            self.check_element_into(&mut report, layer.as_mut(), inputs);
+        }
+        report
+    }
+
+    /// `check_element`, recorded as the next layer of `report`
+    fn check_element_into(
+        &self,
+        report: &mut GradientReport,
+        element: &mut dyn NetworkElement,
+        inputs: &Matrix,
+    ) {
+        let weights: Vec<f64> = (0..inputs.rows * element.nb_outputs())
+            .map(|i| ((i * 7 % 13) as f64 - 6.0) / 6.0)
+            .collect();
+        let output_weights = Matrix::new(inputs.rows, element.nb_outputs(), weights);
+        let seed: u64 = random::rng().gen();
+        let loss = |element: &dyn NetworkElement, x: &Matrix| -> f64 {
+            random::seed(seed);
+            let (outputs, _) = element.forward(x);
+            dot(&outputs.data, &output_weights.data)
+        };
+
+        let layer = report.layers.len();
+        report.layers.push(LayerSummary {
+            kind: element.kind(),
+            checked: 0,
+            max_error: 0.0,
+        });
+
+        random::seed(seed);
+        let (_, cache) = element.forward(inputs);
+        let (input_grads, param_grads) = element.backward(inputs, &cache, &output_weights, true);
+        let input_grads = input_grads.expect("input gradients were requested");
+        for i in self.indices(inputs.data.len()) {
+            let mut x = inputs.clone();
+            let numeric = self.perturb(&mut x, |x| x.data.as_mut_slice(), i, |x| loss(element, x));
+            report.record(layer, Checked::Input, i, input_grads.data[i], numeric);
+        }
+
+        for (group, grads) in param_grads.iter().enumerate() {
+            for i in self.indices(grads.len()) {
+                let numeric = self.perturb(
+                    element,
+                    |e| e.parameters_mut().swap_remove(group).1,
+                    i,
+                    |e| loss(e, inputs),
+                );
+                report.record(layer, Checked::Parameter(group), i, grads[i], numeric);
+            }
+        }
+    }
+
+    /// Check the parameter gradients of every layer of `network` for
+    /// `loss` over `batch`, in training or inference mode. The generator is
+    /// reseeded before every forward pass, so that in training mode they
+    /// all draw the same dropout masks.
+    pub fn check_network(
+        &self,
+        network: &mut Network,
+        batch: &[(Vec<f64>, Vec<f64>)],
+        loss: &dyn Loss,
+        training: bool,
+    ) -> GradientReport {
+        network.set_training_mode(training);
+        let seed: u64 = random::rng().gen();
+        random::seed(seed);
+        let gradients = network.loss_gradients(batch, loss);
+
+        let mut report = GradientReport::new(self.tolerance);
+        for layer in 0..network.layers.len() {
+            report.layers.push(LayerSummary {
+                kind: network.layers[layer].kind(),
+                checked: 0,
+                max_error: 0.0,
+            });
+            for (group, grads) in gradients.layers[layer].iter().enumerate() {
+                for i in self.indices(grads.len()) {
+                    let numeric = self.perturb(
+                        network,
+                        |n| n.layers[layer].parameters_mut().swap_remove(group).1,
+                        i,
+                        |n| {
+                            random::seed(seed);
+                            n.loss(batch, loss)
+                        },
+                    );
+                    report.record(layer, Checked::Parameter(group), i, grads[i], numeric);
+                }
+            }
+        }
+        report
+    }
+}
+
+/// Test helper: panic with the report unless every input and parameter
+/// gradient of `element` matches finite differences
+#[cfg(test)]
+pub fn assert_gradients(element: &mut dyn NetworkElement, inputs: &Matrix) {
+    let report = GradientCheck::default().check_element(element, inputs);
+    assert!(report.is_ok(), "{}", report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::activation::Activation;
+    use crate::network::datastruct::dropout::Dropout;
+    use crate::network::datastruct::layer::Layer;
+    use crate::network::datastruct::layer_norm::LayerNorm;
+    use crate::network::datastruct::metadata::Metadata;
+    use crate::network::datastruct::r#trait::Cache;
+    use crate::network::datastruct::record::LayerRecord;
+    use crate::network::loss::{self, SoftmaxCrossEntropy};
+
+    #[test]
+    fn test_network_gradients() {
+        let mut network = Network {
+            layers: vec![
+                Box::new(Layer::new_random_he(6, 4, Activation::Tanh)),
+                Box::new(Dropout::new(6, 0.5)),
+                Box::new(LayerNorm::new(6)),
+                Box::new(Layer::new_random_he(3, 6, Activation::Linear)),
+            ],
+            metadata: Metadata::default(),
+        };
+        let batch = vec![
+            (vec![0.5, -1.0, 0.3, 0.8], vec![1.0, 0.0, 0.0]),
+            (vec![-0.2, 0.4, 1.1, -0.7], vec![0.0, 0.0, 1.0]),
+        ];
+        for loss in ["cross_entropy(0.1)", "focal", "mse", "binary_cross_entropy"] {
+            let loss = loss::from_name(loss).unwrap();
+            for training in [false, true] {
+                let report = GradientCheck::default().check_network(
+                    &mut network,
+                    &batch,
+                    loss.as_ref(),
+                    training,
+                );
+                assert!(report.is_ok(), "{}", report);
+                assert_eq!(report.layers[0].checked, 6 * 4 + 6);
+            }
+        }
+
+        for training in [false, true] {
+            let report = GradientCheck::default().check_layers(&mut network, &batch, training);
+            assert!(report.is_ok(), "{}", report);
+            // The dropout layer has no parameters, only its 2·6 inputs
+            assert_eq!(report.layers[1].checked, 2 * 6);
+        }
+
+        let sampled = GradientCheck {
+            samples: Some(5),
+            ..GradientCheck::default()
+        };
+        let report =
+            sampled.check_network(&mut network, &batch, &SoftmaxCrossEntropy::default(), false);
+        assert_eq!(report.layers[0].checked, 5 + 5);
+        assert_eq!(report.layers[1].checked, 0);
+    }
+
+    /// Scales its inputs by 2 but forgets the factor in backprop
+    struct Doubler;
+
+    impl NetworkElement for Doubler {
+        fn kind(&self) -> &'static str {
+            "doubler"
+        }
+
+        fn nb_inputs(&self) -> usize {
+            2
+        }
+
+        fn nb_outputs(&self) -> usize {
+            2
+        }
+
+        fn forward(&self, inputs: &Matrix) -> (Matrix, Cache) {
+            let data = inputs.data.iter().map(|x| 2.0 * x).collect();
+            (Matrix::new(inputs.rows, 2, data), Box::new(()))
+        }
+
+        fn backward(
+            &self,
+            _inputs: &Matrix,
+            _cache: &Cache,
+            output_grads: &Matrix,
+            _need_input_grads: bool,
+        ) -> (Option<Matrix>, Vec<Vec<f64>>) {
+            (Some(output_grads.clone()), vec![])
+        }
+
+        fn serialize(&self) -> LayerRecord {
+            LayerRecord::new("doubler")
+        }
+    }
+
+    #[test]
+    fn test_detects_wrong_gradients() {
+        let inputs = Matrix::new(2, 2, vec![0.2, -0.5, 0.9, 0.1]);
+        let report = GradientCheck::default().check_element(&mut Doubler, &inputs);
+        assert!(!report.is_ok());
+        assert_eq!(report.mismatches.len(), 4);
+        assert_eq!(report.mismatches[0].checked, Checked::Input);
+        assert!(report
+            .to_string()
+            .contains("MISMATCH layer 0 (doubler) input index 0"));
+
+        let mut layer = Layer::new_random_he(2, 3, Activation::Tanh);
+        assert_gradients(&mut layer, &Matrix::new(1, 3, vec![0.2, -0.5, 0.9]));
+    }
+}
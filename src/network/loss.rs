@@ -0,0 +1,397 @@
+use crate::network::autodiff::{Tape, Var};
+use crate::network::datastruct::activation::Activation;
+use crate::network::datastruct::matrix::Matrix;
+use crate::network::float::{self, Float};
+
+/// Names accepted by `from_name`, for error and help messages
+pub const NAMES: &str = "cross_entropy[(smoothing)], weighted_cross_entropy(w1, w2, ...), focal[(gamma)], mse, binary_cross_entropy";
+
+pub const DEFAULT_FOCAL_GAMMA: f64 = 2.0;
+
+/// Smallest `1 - p` of the focal loss: a saturated softmax gives 0, and
+/// `0^gamma` has an infinite derivative when gamma < 1
+const FOCAL_MIN_COMPLEMENT: f64 = 1e-12;
+
+/// Training objective comparing the raw network outputs of a batch (one
+/// sample per row) with their targets.
+///
+/// Losses are written with tape ops, so `gradients` needs no hand-written
+/// derivative. `value` and `gradients` are summed over the samples of the
+/// batch; values are returned as `f64` whatever the precision `T`.
+pub trait Loss<T: Float = f64>: Send + Sync {
+    /// Name as written in configuration files, e.g. `focal(2)`
+    fn name(&self) -> String;
+
+    /// Loss of every sample of the batch (R×1)
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var;
+
+    /// Total loss of the batch (1×1)
+    fn record_total(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        let samples = self.record(tape, outputs, targets);
+        tape.sum(samples)
+    }
+
+    /// Per-class weights, for losses that take them
+    fn class_weights(&self) -> Option<&[f64]> {
+        None
+    }
+
+    fn value(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64 {
+        let mut tape = Tape::new();
+        let output_var = tape.leaf(outputs.clone());
+        let target_var = tape.leaf(targets.clone());
+        let loss = self.record_total(&mut tape, output_var, target_var);
+        tape.value(loss).data[0].as_f64()
+    }
+
+    /// dL/d outputs
+    fn gradients(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T> {
+        let mut tape = Tape::new();
+        let output_var = tape.leaf(outputs.clone());
+        let target_var = tape.leaf(targets.clone());
+        let loss = self.record_total(&mut tape, output_var, target_var);
+        tape.backward(loss, Matrix::new(1, 1, vec![T::one()]))
+            .take(output_var)
+            .unwrap_or_else(|| Matrix::zeros(outputs.rows, outputs.cols))
+    }
+
+    /// Loss of a single sample
+    fn sample_value(&self, outputs: &[T], targets: &[T]) -> f64 {
+        self.value(
+            &Matrix::new(1, outputs.len(), outputs.to_vec()),
+            &Matrix::new(1, targets.len(), targets.to_vec()),
+        )
+    }
+}
+
+/// `-Σ t · log softmax(outputs)` with the targets smoothed towards the
+/// uniform distribution: `t' = (1 - smoothing) · t + smoothing / classes`
+#[derive(Debug, Clone, Default)]
+pub struct SoftmaxCrossEntropy {
+    pub smoothing: f64,
+}
+
+/// Cross-entropy where each class's term is multiplied by its weight, to
+/// count rare classes more
+#[derive(Debug, Clone)]
+pub struct WeightedCrossEntropy {
+    pub weights: Vec<f64>,
+}
+
+/// Focal loss `-Σ t · (1 - p)^gamma · log p`: well classified samples
+/// (p close to 1) weigh less, so training focuses on the hard ones
+#[derive(Debug, Clone)]
+pub struct Focal {
+    pub gamma: f64,
+}
+
+/// Mean squared error of the raw outputs, averaged over the outputs
+#[derive(Debug, Clone)]
+pub struct Mse;
+
+/// Independent sigmoid + binary cross-entropy for every output, written as
+/// `softplus(o) - t · o` to stay finite for large outputs
+#[derive(Debug, Clone)]
+pub struct BinaryCrossEntropy;
+
+/// `-Σ weights ⊙ targets ⊙ log softmax(outputs)` of every sample, weights
+/// being 1×classes
+fn cross_entropy<T: Float>(
+    tape: &mut Tape<T>,
+    outputs: Var,
+    targets: Var,
+    weights: Option<Var>,
+) -> Var {
+    let log_probs = tape.log_softmax(outputs);
+    let mut picked = tape.mul(log_probs, targets);
+    if let Some(weights) = weights {
+        picked = tape.mul(picked, weights);
+    }
+    let total = tape.row_sum(picked);
+    tape.scale(total, -1.0)
+}
+
+impl<T: Float> Loss<T> for SoftmaxCrossEntropy {
+    fn name(&self) -> String {
+        if self.smoothing == 0.0 {
+            String::from("cross_entropy")
+        } else {
+            format!("cross_entropy({})", self.smoothing)
+        }
+    }
+
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        if self.smoothing == 0.0 {
+            return cross_entropy(tape, outputs, targets, None);
+        }
+        let classes = tape.value(targets).cols as f64;
+        let scaled = tape.scale(targets, 1.0 - self.smoothing);
+        let smoothed = tape.add_scalar(scaled, self.smoothing / classes);
+        cross_entropy(tape, outputs, smoothed, None)
+    }
+}
+
+impl<T: Float> Loss<T> for WeightedCrossEntropy {
+    fn name(&self) -> String {
+        let weights: Vec<String> = self.weights.iter().map(|w| w.to_string()).collect();
+        format!("weighted_cross_entropy({})", weights.join(", "))
+    }
+
+    fn class_weights(&self) -> Option<&[f64]> {
+        Some(&self.weights)
+    }
+
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        let weights = Matrix::new(1, self.weights.len(), float::cast_all(&self.weights));
+        let weights = tape.leaf(weights);
+        cross_entropy(tape, outputs, targets, Some(weights))
+    }
+}
+
+impl<T: Float> Loss<T> for Focal {
+    fn name(&self) -> String {
+        format!("focal({})", self.gamma)
+    }
+
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        if self.gamma == 0.0 {
+            return cross_entropy(tape, outputs, targets, None);
+        }
+        let log_probs = tape.log_softmax(outputs);
+        let probs = tape.exp(log_probs);
+        let negated = tape.scale(probs, -1.0);
+        let complement = tape.add_scalar(negated, 1.0);
+        let complement = tape.clamp_min(complement, FOCAL_MIN_COMPLEMENT);
+        let modulation = tape.powf(complement, self.gamma);
+        let weighted = tape.mul(log_probs, modulation);
+        let picked = tape.mul(weighted, targets);
+        let total = tape.row_sum(picked);
+        tape.scale(total, -1.0)
+    }
+}
+
+impl<T: Float> Loss<T> for Mse {
+    fn name(&self) -> String {
+        String::from("mse")
+    }
+
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        let errors = tape.sub(outputs, targets);
+        let squares = tape.mul(errors, errors);
+        tape.row_mean(squares)
+    }
+}
+
+impl<T: Float> Loss<T> for BinaryCrossEntropy {
+    fn name(&self) -> String {
+        String::from("binary_cross_entropy")
+    }
+
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        let softplus = tape.activation(outputs, Activation::Softplus);
+        let products = tape.mul(outputs, targets);
+        let terms = tape.sub(softplus, products);
+        tape.row_sum(terms)
+    }
+}
+
+/// Any loss with every sample's term multiplied by the weight of its
+/// target class (targets being one-hot, or probabilities: the weight is then
+/// averaged over them)
+pub struct ClassWeighted<T: Float = f64> {
+    pub loss: Box<dyn Loss<T>>,
+    pub weights: Vec<f64>,
+}
+
+impl<T: Float> Loss<T> for ClassWeighted<T> {
+    fn name(&self) -> String {
+        self.loss.name()
+    }
+
+    fn class_weights(&self) -> Option<&[f64]> {
+        Some(&self.weights)
+    }
+
+    fn record(&self, tape: &mut Tape<T>, outputs: Var, targets: Var) -> Var {
+        let losses = self.loss.record(tape, outputs, targets);
+        let weights = Matrix::new(1, self.weights.len(), float::cast_all(&self.weights));
+        let weights = tape.leaf(weights);
+        let sample_weights = tape.mul_transposed(targets, weights);
+        tape.mul(losses, sample_weights)
+    }
+}
+
+/// Parse a loss written as `name` or `name(arg, ...)`
+pub fn from_name<T: Float>(spec: &str) -> Result<Box<dyn Loss<T>>, String> {
+    let spec = spec.trim().to_lowercase();
+    let (name, args) = match spec.split_once('(') {
+        Some((name, rest)) => {
+            let args = rest
+                .strip_suffix(')')
+                .ok_or_else(|| format!("missing ')' in loss '{}'", spec))?;
+            let args: Result<Vec<f64>, _> = args
+                .split(',')
+                .map(|arg| arg.trim().parse::<f64>())
+                .collect();
+            let args = args.map_err(|_| format!("invalid parameters in loss '{}'", spec))?;
+            (name.trim(), Some(args))
+        }
+        None => (spec.as_str(), None),
+    };
+
+    let single = |default: f64| -> Result<f64, String> {
+        match args.as_deref() {
+            None => Ok(default),
+            Some([value]) => Ok(*value),
+            Some(_) => Err(format!("loss {} takes a single parameter", name)),
+        }
+    };
+
+    let loss: Box<dyn Loss<T>> = match name {
+        "cross_entropy" => {
+            let smoothing = single(0.0)?;
+            if !(0.0..1.0).contains(&smoothing) {
+                return Err(format!(
+                    "invalid label smoothing {} (must be 0 <= smoothing < 1)",
+                    smoothing
+                ));
+            }
+            Box::new(SoftmaxCrossEntropy { smoothing })
+        }
+        "weighted_cross_entropy" => {
+            let weights = args.ok_or("weighted_cross_entropy needs one weight per class")?;
+            if weights.iter().any(|&w| w < 0.0 || !w.is_finite()) {
+                return Err(format!("invalid class weights in loss '{}'", spec));
+            }
+            Box::new(WeightedCrossEntropy { weights })
+        }
+        "focal" => {
+            let gamma = single(DEFAULT_FOCAL_GAMMA)?;
+            if gamma < 0.0 {
+                return Err(format!("invalid focal gamma {} (must be >= 0)", gamma));
+            }
+            Box::new(Focal { gamma })
+        }
+        "mse" if args.is_none() => Box::new(Mse),
+        "binary_cross_entropy" if args.is_none() => Box::new(BinaryCrossEntropy),
+        "mse" | "binary_cross_entropy" => {
+            return Err(format!("loss {} takes no parameters", name));
+        }
+        _ => {
+            return Err(format!("unknown loss '{}' (expected {})", spec, NAMES));
+        }
+    };
+    Ok(loss)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::gradient_check::relative_error;
+
+    #[test]
+    fn test_gradients_match_finite_differences() {
+        let outputs = Matrix::new(2, 3, vec![0.3, -1.2, 2.0, 1.0, 0.5, -0.4]);
+        let targets = Matrix::new(2, 3, vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+        for spec in [
+            "cross_entropy",
+            "cross_entropy(0.1)",
+            "weighted_cross_entropy(1, 2, 0.5)",
+            "focal",
+            "focal(0.5)",
+            "mse",
+            "binary_cross_entropy",
+        ] {
+            let loss = from_name::<f64>(spec).unwrap();
+            assert_eq!(from_name::<f64>(&loss.name()).unwrap().name(), loss.name());
+            let grads = loss.gradients(&outputs, &targets);
+            let h = 1e-6;
+            for i in 0..outputs.data.len() {
+                let mut plus = outputs.clone();
+                plus.data[i] += h;
+                let mut minus = outputs.clone();
+                minus.data[i] -= h;
+                let numeric =
+                    (loss.value(&plus, &targets) - loss.value(&minus, &targets)) / (2.0 * h);
+                assert!(
+                    relative_error(grads.data[i], numeric) < 1e-6,
+                    "{} {}",
+                    spec,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_focal_saturated() {
+        let outputs = Matrix::new(2, 3, vec![100.0, 0.0, 0.0, 0.0, 100.0, 0.0]);
+        let targets = Matrix::new(2, 3, vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        for spec in ["focal(0.5)", "focal", "focal(1)"] {
+            let loss = from_name::<f64>(spec).unwrap();
+            assert!(loss.value(&outputs, &targets).is_finite(), "{}", spec);
+            let grads = loss.gradients(&outputs, &targets);
+            assert!(grads.data.iter().all(|g| g.is_finite()), "{}", spec);
+            // The first sample is already right and contributes nothing
+            assert!(grads.data[..3].iter().all(|g| g.abs() < 1e-12), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_class_weighted() {
+        let outputs = Matrix::new(2, 2, vec![0.5, -0.5, 1.0, 2.0]);
+        let targets = Matrix::new(2, 2, vec![1.0, 0.0, 0.0, 1.0]);
+        let weighted: ClassWeighted = ClassWeighted {
+            loss: Box::new(SoftmaxCrossEntropy::default()),
+            weights: vec![3.0, 0.5],
+        };
+        let explicit = WeightedCrossEntropy {
+            weights: vec![3.0, 0.5],
+        };
+        let explicit: &dyn Loss = &explicit;
+        assert!(
+            (weighted.value(&outputs, &targets) - explicit.value(&outputs, &targets)).abs() < 1e-12
+        );
+        let grads = weighted.gradients(&outputs, &targets);
+        for (a, b) in grads
+            .data
+            .iter()
+            .zip(&explicit.gradients(&outputs, &targets).data)
+        {
+            assert!((a - b).abs() < 1e-12);
+        }
+
+        let mse: ClassWeighted = ClassWeighted {
+            loss: Box::new(Mse),
+            weights: vec![2.0, 1.0],
+        };
+        let first = Loss::<f64>::sample_value(&Mse, &[0.5, -0.5], &[1.0, 0.0]);
+        let second = Loss::<f64>::sample_value(&Mse, &[1.0, 2.0], &[0.0, 1.0]);
+        assert!((mse.value(&outputs, &targets) - (2.0 * first + second)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_values() {
+        let outputs = [0.0, 0.0];
+        let targets = [1.0, 0.0];
+        let ln2 = 2f64.ln();
+        let value = |spec: &str| {
+            from_name::<f64>(spec)
+                .unwrap()
+                .sample_value(&outputs, &targets)
+        };
+
+        assert!((value("cross_entropy") - ln2).abs() < 1e-12);
+        assert!((value("cross_entropy(0.5)") - ln2).abs() < 1e-12);
+        assert!((value("weighted_cross_entropy(3, 1)") - 3.0 * ln2).abs() < 1e-12);
+        // p = 0.5 for the target class
+        assert!((value("focal(2)") - 0.25 * ln2).abs() < 1e-12);
+        assert!((value("mse") - 0.5).abs() < 1e-12);
+        assert!((value("binary_cross_entropy") - 2.0 * ln2).abs() < 1e-12);
+
+        assert!(from_name::<f64>("hinge").is_err());
+        assert!(from_name::<f64>("cross_entropy(1.5)").is_err());
+        assert!(from_name::<f64>("mse(2)").is_err());
+        assert!(from_name::<f64>("weighted_cross_entropy").is_err());
+    }
+}
@@ -1 +1,9 @@
+pub mod autodiff;
 pub mod datastruct;
+pub mod float;
+pub mod gradient_check;
+pub mod loss;
+pub mod optimizer;
+pub mod random;
+pub mod regularization;
+pub mod schedule;
@@ -0,0 +1,332 @@
+use crate::network::float::{self, Float};
+
+/// Which kind of parameter a group holds, so optimizers can treat biases
+/// differently (AdamW never decays them).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    Weight,
+    Bias,
+    /// Learned scale of a normalization (gamma): decaying it towards 0
+    /// would shrink the normalized outputs, so it is handled like a bias
+    Scale,
+}
+
+/// Update rule applied to every parameter group after the gradients of a
+/// step have been computed.
+///
+/// `grads` are true loss gradients (dL/dθ) and the optimizer moves `params`
+/// against them. `slot` identifies the parameter group (a layer's weights or
+/// biases) so optimizers can keep per-parameter state between steps. The
+/// state is kept in the precision `T` of the parameters and handed out as
+/// `f64` for checkpoints.
+pub trait Optimizer<T: Float = f64>: Send {
+    fn name(&self) -> &'static str;
+
+    /// Called once per training step, before any group is updated
+    fn begin_step(&mut self) {}
+
+    fn update(
+        &mut self,
+        slot: usize,
+        kind: ParamKind,
+        params: &mut [T],
+        grads: &[T],
+        learning_rate: f64,
+    );
+
+    /// Buffers and counters kept between steps, for checkpoints
+    fn state(&self) -> Vec<Vec<f64>> {
+        vec![]
+    }
+
+    /// Restore a `state()` of the same optimizer
+    fn load_state(&mut self, _state: Vec<Vec<f64>>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Per-parameter buffer for `slot`, allocated with zeros on first use
+fn state_for<T: Float>(states: &mut Vec<Vec<T>>, slot: usize, len: usize) -> &mut Vec<T> {
+    if states.len() <= slot {
+        states.resize_with(slot + 1, Vec::new);
+    }
+    if states[slot].len() != len {
+        states[slot] = vec![T::zero(); len];
+    }
+    &mut states[slot]
+}
+
+fn widen_states<T: Float>(states: &[Vec<T>]) -> Vec<Vec<f64>> {
+    states.iter().map(|state| float::widen_all(state)).collect()
+}
+
+fn cast_states<T: Float>(states: &[Vec<f64>]) -> Vec<Vec<T>> {
+    states.iter().map(|state| float::cast_all(state)).collect()
+}
+
+/// Plain stochastic gradient descent: `θ -= lr * g`
+pub struct Sgd;
+
+impl<T: Float> Optimizer<T> for Sgd {
+    fn name(&self) -> &'static str {
+        "sgd"
+    }
+
+    fn update(&mut self, _: usize, _: ParamKind, params: &mut [T], grads: &[T], lr: f64) {
+        let lr = T::cast(lr);
+        for (p, &g) in params.iter_mut().zip(grads.iter()) {
+            *p -= lr * g;
+        }
+    }
+}
+
+/// SGD with (optionally Nesterov) momentum
+pub struct Momentum<T = f64> {
+    pub momentum: f64,
+    pub nesterov: bool,
+    velocity: Vec<Vec<T>>,
+}
+
+impl<T> Momentum<T> {
+    pub fn new(momentum: f64, nesterov: bool) -> Self {
+        Momentum {
+            momentum,
+            nesterov,
+            velocity: vec![],
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for Momentum<T> {
+    fn name(&self) -> &'static str {
+        if self.nesterov {
+            "nesterov"
+        } else {
+            "momentum"
+        }
+    }
+
+    fn update(&mut self, slot: usize, _: ParamKind, params: &mut [T], grads: &[T], lr: f64) {
+        let (mu, lr) = (T::cast(self.momentum), T::cast(lr));
+        let velocity = state_for(&mut self.velocity, slot, params.len());
+        for ((p, &g), v) in params.iter_mut().zip(grads.iter()).zip(velocity.iter_mut()) {
+            *v = mu * *v + g;
+            if self.nesterov {
+                *p -= lr * (g + mu * *v);
+            } else {
+                *p -= lr * *v;
+            }
+        }
+    }
+
+    fn state(&self) -> Vec<Vec<f64>> {
+        widen_states(&self.velocity)
+    }
+
+    fn load_state(&mut self, state: Vec<Vec<f64>>) -> Result<(), String> {
+        self.velocity = cast_states(&state);
+        Ok(())
+    }
+}
+
+/// RMSProp: scales each step by a running average of squared gradients
+pub struct RmsProp<T = f64> {
+    pub decay: f64,
+    pub epsilon: f64,
+    mean_square: Vec<Vec<T>>,
+}
+
+impl<T> RmsProp<T> {
+    pub fn new(decay: f64, epsilon: f64) -> Self {
+        RmsProp {
+            decay,
+            epsilon,
+            mean_square: vec![],
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for RmsProp<T> {
+    fn name(&self) -> &'static str {
+        "rmsprop"
+    }
+
+    fn update(&mut self, slot: usize, _: ParamKind, params: &mut [T], grads: &[T], lr: f64) {
+        let (decay, epsilon, lr) = (T::cast(self.decay), T::cast(self.epsilon), T::cast(lr));
+        let mean_square = state_for(&mut self.mean_square, slot, params.len());
+        for ((p, &g), s) in params
+            .iter_mut()
+            .zip(grads.iter())
+            .zip(mean_square.iter_mut())
+        {
+            *s = decay * *s + (T::one() - decay) * g * g;
+            *p -= lr * g / (s.sqrt() + epsilon);
+        }
+    }
+
+    fn state(&self) -> Vec<Vec<f64>> {
+        widen_states(&self.mean_square)
+    }
+
+    fn load_state(&mut self, state: Vec<Vec<f64>>) -> Result<(), String> {
+        self.mean_square = cast_states(&state);
+        Ok(())
+    }
+}
+
+/// Adam, or AdamW when `weight_decay > 0` (decoupled decay, weights only)
+pub struct Adam<T = f64> {
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    pub weight_decay: f64,
+    step: i32,
+    first_moment: Vec<Vec<T>>,
+    second_moment: Vec<Vec<T>>,
+}
+
+impl<T> Adam<T> {
+    pub fn new(beta1: f64, beta2: f64, epsilon: f64) -> Self {
+        Self::adamw(beta1, beta2, epsilon, 0.0)
+    }
+
+    pub fn adamw(beta1: f64, beta2: f64, epsilon: f64, weight_decay: f64) -> Self {
+        Adam {
+            beta1,
+            beta2,
+            epsilon,
+            weight_decay,
+            step: 0,
+            first_moment: vec![],
+            second_moment: vec![],
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for Adam<T> {
+    fn name(&self) -> &'static str {
+        if self.weight_decay > 0.0 {
+            "adamw"
+        } else {
+            "adam"
+        }
+    }
+
+    fn begin_step(&mut self) {
+        self.step += 1;
+    }
+
+    fn update(&mut self, slot: usize, kind: ParamKind, params: &mut [T], grads: &[T], lr: f64) {
+        let step = self.step.max(1);
+        let correction1 = T::cast(1.0 - self.beta1.powi(step));
+        let correction2 = T::cast(1.0 - self.beta2.powi(step));
+        let (beta1, beta2) = (T::cast(self.beta1), T::cast(self.beta2));
+        let (epsilon, lr) = (T::cast(self.epsilon), T::cast(lr));
+        let decay = if kind == ParamKind::Weight {
+            T::cast(self.weight_decay)
+        } else {
+            T::zero()
+        };
+
+        let m = state_for(&mut self.first_moment, slot, params.len());
+        let v = state_for(&mut self.second_moment, slot, params.len());
+        for (i, (p, &g)) in params.iter_mut().zip(grads.iter()).enumerate() {
+            m[i] = beta1 * m[i] + (T::one() - beta1) * g;
+            v[i] = beta2 * v[i] + (T::one() - beta2) * g * g;
+            let m_hat = m[i] / correction1;
+            let v_hat = v[i] / correction2;
+            *p -= lr * (m_hat / (v_hat.sqrt() + epsilon) + decay * *p);
+        }
+    }
+
+    /// The step count, then the first moments of every slot, then the
+    /// second moments
+    fn state(&self) -> Vec<Vec<f64>> {
+        let mut state = vec![vec![self.step as f64]];
+        state.extend(widen_states(&self.first_moment));
+        state.extend(widen_states(&self.second_moment));
+        state
+    }
+
+    fn load_state(&mut self, state: Vec<Vec<f64>>) -> Result<(), String> {
+        if state.is_empty() || state[0].len() != 1 || state.len().is_multiple_of(2) {
+            return Err(String::from("invalid adam state"));
+        }
+        self.step = state[0][0] as i32;
+        let slots = (state.len() - 1) / 2;
+        self.second_moment = cast_states(&state[1 + slots..]);
+        self.first_moment = cast_states(&state[1..1 + slots]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sgd_step() {
+        let mut params = vec![1.0, -1.0];
+        Sgd.update(0, ParamKind::Weight, &mut params, &[0.5, -0.5], 0.1);
+        assert_eq!(params, vec![0.95, -0.95]);
+    }
+
+    #[test]
+    fn test_momentum_accumulates() {
+        let mut opt: Momentum = Momentum::new(0.9, false);
+        let mut params = vec![0.0];
+        opt.update(0, ParamKind::Weight, &mut params, &[1.0], 0.1);
+        opt.update(0, ParamKind::Weight, &mut params, &[1.0], 0.1);
+        // v1 = 1, v2 = 1.9
+        assert!((params[0] + 0.29).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_adam_first_step_is_learning_rate() {
+        let mut opt: Adam = Adam::new(0.9, 0.999, 1e-8);
+        let mut params = vec![0.0, 0.0];
+        opt.begin_step();
+        opt.update(0, ParamKind::Weight, &mut params, &[3.0, -0.01], 0.01);
+        assert!((params[0] + 0.01).abs() < 1e-6);
+        assert!((params[1] - 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_adam_state_round_trip() {
+        let mut opt = Adam::new(0.9, 0.999, 1e-8);
+        let mut params = vec![0.5, -0.5];
+        let mut biases = vec![0.1];
+        for _ in 0..3 {
+            opt.begin_step();
+            opt.update(0, ParamKind::Weight, &mut params, &[0.3, -0.1], 0.01);
+            opt.update(1, ParamKind::Bias, &mut biases, &[0.2], 0.01);
+        }
+
+        let mut restored = Adam::new(0.9, 0.999, 1e-8);
+        restored.load_state(opt.state()).unwrap();
+        assert_eq!(restored.state(), opt.state());
+        let mut copy = params.clone();
+        opt.begin_step();
+        opt.update(0, ParamKind::Weight, &mut params, &[0.3, -0.1], 0.01);
+        restored.begin_step();
+        restored.update(0, ParamKind::Weight, &mut copy, &[0.3, -0.1], 0.01);
+        assert_eq!(params, copy);
+
+        assert!(restored.load_state(vec![vec![1.0], vec![0.0]]).is_err());
+    }
+
+    #[test]
+    fn test_adamw_only_decays_weights() {
+        let mut opt: Adam = Adam::adamw(0.9, 0.999, 1e-8, 0.1);
+        let mut weights = vec![1.0];
+        let mut biases = vec![1.0];
+        let mut scales = vec![1.0];
+        opt.begin_step();
+        opt.update(0, ParamKind::Weight, &mut weights, &[0.0], 0.1);
+        opt.update(1, ParamKind::Bias, &mut biases, &[0.0], 0.1);
+        opt.update(2, ParamKind::Scale, &mut scales, &[0.0], 0.1);
+        assert!((weights[0] - 0.99).abs() < 1e-12);
+        assert_eq!(biases[0], 1.0);
+        assert_eq!(scales[0], 1.0);
+    }
+}
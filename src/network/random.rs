@@ -0,0 +1,62 @@
+//! Seedable replacement for `rand::thread_rng`.
+//!
+//! Every thread owns a generator, seeded from the OS until `seed` is called.
+//! Weight initialization and dropout draw from it through `rng()`, so
+//! seeding the main thread makes network generation reproducible, and
+//! `Network::train_batch` seeds the worker threads chunk by chunk.
+
+use rand::rngs::StdRng;
+use rand::{Error, RngCore, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Restart the generator of the current thread from `seed`
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Handle on the generator of the current thread
+pub fn rng() -> ThreadRng {
+    ThreadRng
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadRng;
+
+impl RngCore for ThreadRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_seed() {
+        seed(7);
+        let first: Vec<u64> = (0..4).map(|_| rng().gen()).collect();
+        seed(7);
+        let second: Vec<u64> = (0..4).map(|_| rng().gen()).collect();
+        assert_eq!(first, second);
+        seed(8);
+        assert_ne!(rng().gen::<u64>(), first[0]);
+    }
+}
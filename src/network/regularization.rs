@@ -0,0 +1,188 @@
+use crate::network::datastruct::network::{Gradients, Network};
+use crate::network::float::Float;
+use crate::network::optimizer::ParamKind;
+
+/// Weight penalties and constraints applied at every training step.
+///
+/// The penalty `l1 · Σ|θ| + l2/2 · Σθ²` is added to the loss: its gradient
+/// `l1 · sign(θ) + l2 · θ` goes through the optimizer with the loss
+/// gradients. The max-norm constraint is applied after the update. Biases
+/// and normalization scales are left alone unless `include_biases` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Regularization {
+    pub l1: f64,
+    pub l2: f64,
+    /// Largest L2 norm of a unit's incoming weights, 0 for no constraint
+    pub max_norm: f64,
+    pub include_biases: bool,
+}
+
+impl Default for Regularization {
+    fn default() -> Self {
+        Regularization {
+            l1: 0.0,
+            l2: 0.0,
+            max_norm: 0.0,
+            include_biases: false,
+        }
+    }
+}
+
+impl Regularization {
+    fn applies_to(&self, kind: ParamKind) -> bool {
+        kind == ParamKind::Weight || self.include_biases
+    }
+
+    /// Penalty of the current parameters of `network`
+    pub fn penalty<T: Float>(&self, network: &Network<T>) -> f64 {
+        if self.l1 == 0.0 && self.l2 == 0.0 {
+            return 0.0;
+        }
+        let mut penalty = 0.0;
+        for layer in &network.layers {
+            for (kind, params) in layer.parameters() {
+                if self.applies_to(kind) {
+                    for &p in params {
+                        let p = p.as_f64();
+                        penalty += self.l1 * p.abs() + 0.5 * self.l2 * p * p;
+                    }
+                }
+            }
+        }
+        penalty
+    }
+
+    /// Add the gradient of the penalty to (batch averaged) `gradients`
+    pub fn add_gradients<T: Float>(&self, network: &Network<T>, gradients: &mut Gradients<T>) {
+        if self.l1 == 0.0 && self.l2 == 0.0 {
+            return;
+        }
+        let (l1, l2) = (T::cast(self.l1), T::cast(self.l2));
+        for (layer, grads) in network.layers.iter().zip(gradients.layers.iter_mut()) {
+            for ((kind, params), g) in layer.parameters().into_iter().zip(grads.iter_mut()) {
+                if self.applies_to(kind) {
+                    for (g, &p) in g.iter_mut().zip(params) {
+                        // Subgradient 0 at 0, so l1 does not push weights off zero
+                        let sign = if p == T::zero() {
+                            T::zero()
+                        } else {
+                            p.signum()
+                        };
+                        *g += l1 * sign + l2 * p;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Enforce `max_norm` on every layer
+    pub fn constrain<T: Float>(&self, network: &mut Network<T>) {
+        if self.max_norm > 0.0 {
+            for layer in &mut network.layers {
+                layer.clip_weight_norms(self.max_norm);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::datastruct::activation::Activation;
+    use crate::network::datastruct::batch_norm::BatchNorm;
+    use crate::network::datastruct::layer::Layer;
+    use crate::network::datastruct::matrix::Matrix;
+    use crate::network::datastruct::metadata::Metadata;
+    use crate::network::datastruct::network::GradientClipping;
+    use crate::network::loss::Mse;
+    use crate::network::optimizer::Sgd;
+
+    fn network() -> Network {
+        Network {
+            layers: vec![Box::new(Layer {
+                activation: Activation::Linear,
+                weights: Matrix::new(2, 2, vec![3.0, -4.0, 0.0, 0.5]),
+                biases: vec![2.0, -1.0],
+            })],
+            metadata: Metadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_penalty_and_gradients() {
+        let network = network();
+        let regularization = Regularization {
+            l1: 0.1,
+            l2: 0.01,
+            ..Regularization::default()
+        };
+        let expected = 0.1 * 7.5 + 0.005 * 25.25;
+        assert!((regularization.penalty(&network) - expected).abs() < 1e-12);
+
+        let mut gradients = Gradients {
+            layers: vec![vec![vec![0.0; 4], vec![0.0; 2]]],
+            statistics: vec![vec![]],
+        };
+        regularization.add_gradients(&network, &mut gradients);
+        let weights = [0.1 + 0.03, -0.1 - 0.04, 0.0, 0.1 + 0.005];
+        for (g, w) in gradients.layers[0][0].iter().zip(&weights) {
+            assert!((g - w).abs() < 1e-12);
+        }
+        assert_eq!(gradients.layers[0][1], vec![0.0, 0.0]);
+
+        let with_biases = Regularization {
+            include_biases: true,
+            ..regularization
+        };
+        let biases = 0.1 * 3.0 + 0.005 * 5.0;
+        assert!((with_biases.penalty(&network) - expected - biases).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_max_norm() {
+        let mut network = network();
+        let batch = vec![(vec![1.0, 1.0], vec![0.0, 0.0])];
+        let regularization = Regularization {
+            max_norm: 2.0,
+            ..Regularization::default()
+        };
+        let clipping = GradientClipping::default();
+        network.train_batch(&batch, &Mse, 0.0, &mut Sgd, &regularization, &clipping);
+
+        let (_, weights) = &network.layers[0].parameters()[0];
+        let norm = (weights[0] * weights[0] + weights[1] * weights[1]).sqrt();
+        assert!((norm - 2.0).abs() < 1e-12);
+        assert_eq!(&weights[2..], &[0.0, 0.5]);
+    }
+
+    #[test]
+    fn test_normalization_scales_left_alone() {
+        let mut network = network();
+        let mut norm = BatchNorm::new(2);
+        norm.gamma = vec![5.0, -5.0];
+        network.layers.push(Box::new(norm));
+        let regularization = Regularization {
+            l1: 0.1,
+            l2: 0.01,
+            max_norm: 2.0,
+            ..Regularization::default()
+        };
+        let expected = 0.1 * 7.5 + 0.005 * 25.25;
+        assert!((regularization.penalty(&network) - expected).abs() < 1e-12);
+
+        let mut gradients = Gradients {
+            layers: vec![
+                vec![vec![0.0; 4], vec![0.0; 2]],
+                vec![vec![0.0; 2], vec![0.0; 2]],
+            ],
+            statistics: vec![vec![], vec![]],
+        };
+        regularization.add_gradients(&network, &mut gradients);
+        assert_eq!(gradients.layers[1], vec![vec![0.0; 2], vec![0.0; 2]]);
+
+        regularization.constrain(&mut network);
+        let (kind, gamma) = &network.layers[1].parameters()[0];
+        assert_eq!(*kind, ParamKind::Scale);
+        assert_eq!(*gamma, &[5.0, -5.0]);
+    }
+}
@@ -0,0 +1,421 @@
+use crate::network::datastruct::architecture::split_top_level;
+use std::f64::consts::PI;
+
+pub const NAMES: &str = "warmup(N), constant, step(RATE, STEP), exponential(GAMMA), \
+cosine[(PERIOD[, MULT])], one_cycle[(PCT)] or plateau[(FACTOR[, PATIENCE])]";
+
+/// Starting factor of one-cycle, as in the original paper's `max / 25`
+const ONE_CYCLE_START: f64 = 1.0 / 25.0;
+/// Final factor of one-cycle
+const ONE_CYCLE_END: f64 = 1e-4;
+
+/// Learning rate schedule, as a factor of the configured learning rate.
+///
+/// The trainer asks for the factor at the start of every epoch and reports
+/// the validation loss at its end, which only adaptive schedules use.
+pub trait LrSchedule: Send {
+    /// Spec as written in configuration files, e.g. `cosine(50, 2)`
+    fn name(&self) -> String;
+
+    fn factor(&self, epoch: usize) -> f64;
+
+    fn observe(&mut self, _epoch: usize, _val_loss: f64) {}
+
+    /// What `observe` has learnt so far, for checkpoints
+    fn state(&self) -> Vec<f64> {
+        vec![]
+    }
+
+    fn load_state(&mut self, _state: &[f64]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct Constant;
+
+/// `rate^(epoch / step)`, the former `lr_decay_*` keys
+pub struct Step {
+    pub rate: f64,
+    pub step: usize,
+}
+
+/// `gamma^epoch`
+pub struct Exponential {
+    pub gamma: f64,
+}
+
+/// Cosine annealing from 1 to 0 over `period` epochs, restarting at 1
+/// after each period (SGDR), every period `mult` times longer than the
+/// previous one
+pub struct Cosine {
+    pub period: usize,
+    pub mult: f64,
+}
+
+/// One-cycle policy over `epochs`: cosine rise from 1/25 to 1 during the
+/// first `pct` of them, then cosine decay to 1e-4
+pub struct OneCycle {
+    pub epochs: usize,
+    pub pct: f64,
+}
+
+/// Multiply the factor by `factor` whenever the validation loss has not
+/// improved for `patience` epochs
+pub struct Plateau {
+    pub factor: f64,
+    pub patience: usize,
+    pub current: f64,
+    pub best: f64,
+    pub bad_epochs: usize,
+}
+
+/// Linear warmup from `1/epochs` to 1 during the first `epochs` epochs,
+/// then `inner`, started at 0
+pub struct Warmup {
+    pub epochs: usize,
+    pub inner: Box<dyn LrSchedule>,
+}
+
+impl LrSchedule for Constant {
+    fn name(&self) -> String {
+        String::from("constant")
+    }
+
+    fn factor(&self, _epoch: usize) -> f64 {
+        1.0
+    }
+}
+
+impl LrSchedule for Step {
+    fn name(&self) -> String {
+        format!("step({}, {})", self.rate, self.step)
+    }
+
+    fn factor(&self, epoch: usize) -> f64 {
+        self.rate.powi((epoch / self.step) as i32)
+    }
+}
+
+impl LrSchedule for Exponential {
+    fn name(&self) -> String {
+        format!("exponential({})", self.gamma)
+    }
+
+    fn factor(&self, epoch: usize) -> f64 {
+        self.gamma.powi(epoch as i32)
+    }
+}
+
+/// Half cosine from `from` (t = 0) to `to` (t = 1)
+fn cosine_between(from: f64, to: f64, t: f64) -> f64 {
+    to + (from - to) * (1.0 + (PI * t).cos()) / 2.0
+}
+
+impl LrSchedule for Cosine {
+    fn name(&self) -> String {
+        format!("cosine({}, {})", self.period, self.mult)
+    }
+
+    fn factor(&self, epoch: usize) -> f64 {
+        let mut start = 0.0;
+        let mut period = self.period as f64;
+        let epoch = epoch as f64;
+        while epoch >= start + period {
+            start += period;
+            period *= self.mult;
+        }
+        cosine_between(1.0, 0.0, (epoch - start) / period)
+    }
+}
+
+impl LrSchedule for OneCycle {
+    fn name(&self) -> String {
+        format!("one_cycle({})", self.pct)
+    }
+
+    fn factor(&self, epoch: usize) -> f64 {
+        let rise = (self.epochs as f64 * self.pct).max(1.0);
+        let epoch = epoch as f64;
+        if epoch < rise {
+            cosine_between(ONE_CYCLE_START, 1.0, epoch / rise)
+        } else {
+            let fall = (self.epochs as f64 - 1.0 - rise).max(1.0);
+            cosine_between(1.0, ONE_CYCLE_END, ((epoch - rise) / fall).min(1.0))
+        }
+    }
+}
+
+impl Plateau {
+    pub fn new(factor: f64, patience: usize) -> Self {
+        Plateau {
+            factor,
+            patience,
+            current: 1.0,
+            best: f64::INFINITY,
+            bad_epochs: 0,
+        }
+    }
+}
+
+impl LrSchedule for Plateau {
+    fn name(&self) -> String {
+        format!("plateau({}, {})", self.factor, self.patience)
+    }
+
+    fn factor(&self, _epoch: usize) -> f64 {
+        self.current
+    }
+
+    fn observe(&mut self, _epoch: usize, val_loss: f64) {
+        if val_loss < self.best {
+            self.best = val_loss;
+            self.bad_epochs = 0;
+        } else {
+            self.bad_epochs += 1;
+            if self.bad_epochs >= self.patience {
+                self.current *= self.factor;
+                self.bad_epochs = 0;
+            }
+        }
+    }
+
+    fn state(&self) -> Vec<f64> {
+        vec![self.current, self.best, self.bad_epochs as f64]
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> Result<(), String> {
+        match *state {
+            [current, best, bad_epochs] => {
+                self.current = current;
+                self.best = best;
+                self.bad_epochs = bad_epochs as usize;
+                Ok(())
+            }
+            _ => Err(String::from("invalid plateau schedule state")),
+        }
+    }
+}
+
+impl LrSchedule for Warmup {
+    fn name(&self) -> String {
+        format!("warmup({}), {}", self.epochs, self.inner.name())
+    }
+
+    fn factor(&self, epoch: usize) -> f64 {
+        if epoch < self.epochs {
+            (epoch + 1) as f64 / self.epochs as f64
+        } else {
+            self.inner.factor(epoch - self.epochs)
+        }
+    }
+
+    fn observe(&mut self, epoch: usize, val_loss: f64) {
+        if epoch >= self.epochs {
+            self.inner.observe(epoch - self.epochs, val_loss);
+        }
+    }
+
+    fn state(&self) -> Vec<f64> {
+        self.inner.state()
+    }
+
+    fn load_state(&mut self, state: &[f64]) -> Result<(), String> {
+        self.inner.load_state(state)
+    }
+}
+
+/// Parse a schedule spec: an optional `warmup(N)` followed by at most one
+/// schedule (`constant` when missing). `epochs` is the length of training,
+/// which `cosine` and `one_cycle` span by default.
+pub fn from_name(spec: &str, epochs: usize) -> Result<Box<dyn LrSchedule>, String> {
+    let spec = spec.trim().to_lowercase();
+    let mut items: Vec<&str> = split_top_level(&spec)?;
+    items.retain(|item| !item.is_empty());
+
+    let mut warmup = None;
+    if let Some(first) = items.first() {
+        if first.starts_with("warmup") {
+            let (_, args) = parse_item(first)?;
+            warmup = match args.as_slice() {
+                [n] if *n >= 1.0 && n.fract() == 0.0 => Some(*n as usize),
+                _ => {
+                    return Err(format!(
+                        "warmup takes a number of epochs >= 1 in '{}'",
+                        first
+                    ))
+                }
+            };
+            items.remove(0);
+        }
+    }
+    let remaining = epochs.saturating_sub(warmup.unwrap_or(0)).max(1);
+
+    let schedule = match items.as_slice() {
+        [] => Box::new(Constant),
+        [item] => parse_schedule(item, remaining)?,
+        _ => {
+            return Err(format!(
+                "'{}' combines several schedules, only warmup(N) can precede one",
+                spec
+            ))
+        }
+    };
+    Ok(match warmup {
+        Some(epochs) => Box::new(Warmup {
+            epochs,
+            inner: schedule,
+        }),
+        None => schedule,
+    })
+}
+
+fn parse_item(item: &str) -> Result<(&str, Vec<f64>), String> {
+    match item.split_once('(') {
+        Some((name, rest)) => {
+            let args = rest
+                .strip_suffix(')')
+                .ok_or_else(|| format!("missing ')' in schedule '{}'", item))?;
+            let args: Result<Vec<f64>, _> = args
+                .split(',')
+                .map(|arg| arg.trim().parse::<f64>())
+                .collect();
+            let args = args.map_err(|_| format!("invalid parameters in schedule '{}'", item))?;
+            Ok((name.trim(), args))
+        }
+        None => Ok((item, vec![])),
+    }
+}
+
+fn parse_schedule(item: &str, epochs: usize) -> Result<Box<dyn LrSchedule>, String> {
+    let (name, args) = parse_item(item)?;
+    let count = |value: f64| value >= 1.0 && value.fract() == 0.0;
+    let invalid = || format!("invalid parameters in schedule '{}'", item);
+
+    let schedule: Box<dyn LrSchedule> = match (name, args.as_slice()) {
+        ("constant", []) => Box::new(Constant),
+        ("step", &[rate, step]) if rate > 0.0 && rate <= 1.0 && count(step) => Box::new(Step {
+            rate,
+            step: step as usize,
+        }),
+        ("exponential", &[gamma]) if gamma > 0.0 && gamma <= 1.0 => Box::new(Exponential { gamma }),
+        ("cosine", []) => Box::new(Cosine {
+            period: epochs,
+            mult: 1.0,
+        }),
+        ("cosine", &[period]) if count(period) => Box::new(Cosine {
+            period: period as usize,
+            mult: 1.0,
+        }),
+        ("cosine", &[period, mult]) if count(period) && mult >= 1.0 => Box::new(Cosine {
+            period: period as usize,
+            mult,
+        }),
+        ("one_cycle", []) => Box::new(OneCycle { epochs, pct: 0.3 }),
+        ("one_cycle", &[pct]) if pct > 0.0 && pct < 1.0 => Box::new(OneCycle { epochs, pct }),
+        ("plateau", []) => Box::new(Plateau::new(0.5, 10)),
+        ("plateau", &[factor]) if factor > 0.0 && factor < 1.0 => {
+            Box::new(Plateau::new(factor, 10))
+        }
+        ("plateau", &[factor, patience]) if factor > 0.0 && factor < 1.0 && count(patience) => {
+            Box::new(Plateau::new(factor, patience as usize))
+        }
+        ("constant" | "step" | "exponential" | "cosine" | "one_cycle" | "plateau", _) => {
+            return Err(invalid())
+        }
+        ("warmup", _) => return Err(String::from("warmup(N) must come first")),
+        _ => return Err(format!("unknown schedule '{}' (expected {})", item, NAMES)),
+    };
+    Ok(schedule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factors(schedule: &dyn LrSchedule, epochs: usize) -> Vec<f64> {
+        (0..epochs).map(|epoch| schedule.factor(epoch)).collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_schedules() {
+        let step = from_name("step(0.5, 2)", 10).unwrap();
+        assert_close(&factors(step.as_ref(), 5), &[1.0, 1.0, 0.5, 0.5, 0.25]);
+
+        let exponential = from_name("exponential(0.9)", 10).unwrap();
+        assert_close(&factors(exponential.as_ref(), 3), &[1.0, 0.9, 0.81]);
+
+        let cosine = from_name("cosine(4)", 10).unwrap();
+        assert_close(
+            &factors(cosine.as_ref(), 6),
+            &[
+                1.0,
+                0.8535533905932737,
+                0.5,
+                0.14644660940672627,
+                1.0,
+                0.8535533905932737,
+            ],
+        );
+        let restarts = from_name("cosine(2, 2)", 10).unwrap();
+        assert_close(
+            &factors(restarts.as_ref(), 7),
+            &[
+                1.0,
+                0.5,
+                1.0,
+                0.8535533905932737,
+                0.5,
+                0.14644660940672627,
+                1.0,
+            ],
+        );
+
+        let one_cycle = from_name("one_cycle(0.5)", 5).unwrap();
+        let cycle = factors(one_cycle.as_ref(), 5);
+        assert!((cycle[0] - ONE_CYCLE_START).abs() < 1e-12);
+        assert!(cycle[2] > cycle[1] && cycle[2] > cycle[3]);
+        assert!((cycle[4] - ONE_CYCLE_END).abs() < 1e-12);
+
+        let warm = from_name("Warmup(2), cosine", 6).unwrap();
+        assert_eq!(warm.name(), "warmup(2), cosine(4, 1)");
+        assert_close(
+            &factors(warm.as_ref(), 4),
+            &[0.5, 1.0, 1.0, 0.8535533905932737],
+        );
+        assert_eq!(from_name("", 10).unwrap().name(), "constant");
+
+        for spec in [
+            "cosine(0)",
+            "step(2, 1)",
+            "cosine(3), step(0.5, 2)",
+            "cosine, warmup(2)",
+            "linear",
+        ] {
+            assert!(from_name(spec, 10).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_plateau() {
+        let mut plateau = from_name("warmup(1), plateau(0.1, 2)", 10).unwrap();
+        let losses = [5.0, 1.0, 0.9, 0.95, 0.92, 0.91, 0.5];
+        let mut seen = vec![];
+        for (epoch, &loss) in losses.iter().enumerate() {
+            seen.push(plateau.factor(epoch));
+            plateau.observe(epoch, loss);
+        }
+        assert_close(&seen, &[1.0, 1.0, 1.0, 1.0, 1.0, 0.1, 0.1]);
+
+        let mut resumed = from_name("warmup(1), plateau(0.1, 2)", 10).unwrap();
+        resumed.load_state(&plateau.state()).unwrap();
+        assert_eq!(resumed.factor(7), 0.1);
+        assert!(resumed.load_state(&[1.0]).is_err());
+    }
+}
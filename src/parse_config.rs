@@ -1,9 +1,15 @@
+use crate::network::datastruct::binary::Precision;
 use std::env;
 
+/// Values checked per parameter group by --check-gradients
+pub const DEFAULT_SAMPLES: usize = 20;
+
 #[derive(Debug, PartialEq)]
 pub enum Mode {
     Predict,
     Train,
+    Convert,
+    CheckGradients,
 }
 
 #[derive(Debug)]
@@ -12,7 +18,20 @@ pub struct Config {
     pub loadfile: String,
     pub chessfile: String,
     pub savefile: Option<String>,
+    /// Where the weights of the last epoch go, when they differ from the
+    /// best ones saved in `savefile`
+    pub final_savefile: Option<String>,
     pub configfile: Option<String>,
+    /// Checkpoint to resume training from
+    pub resume: Option<String>,
+    /// Overrides the `seed` of the training configuration
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub format: Option<String>,
+    /// Values checked per parameter group by --check-gradients
+    pub samples: Option<usize>,
+    /// Float type the network computes in with --predict
+    pub precision: Precision,
 }
 
 impl Config {
@@ -30,7 +49,15 @@ impl Config {
 
         let mut mode: Option<Mode> = None;
         let mut savefile: Option<String> = None;
+        let mut final_savefile: Option<String> = None;
         let mut configfile: Option<String> = None;
+        let mut resume: Option<String> = None;
+        let mut seed: Option<u64> = None;
+        let mut threads: Option<usize> = None;
+        let mut format: Option<String> = None;
+        let mut samples: Option<usize> = Some(DEFAULT_SAMPLES);
+        let mut samples_set = false;
+        let mut precision: Option<Precision> = None;
         let mut loadfile: Option<String> = None;
         let mut chessfile: Option<String> = None;
 
@@ -39,18 +66,76 @@ impl Config {
             match args[i].as_str() {
                 "--predict" => {
                     if mode.is_some() {
-                        return Err(String::from("Cannot specify both --predict and --train"));
+                        return Err(String::from(
+                            "Only one of --predict, --train, --convert and --check-gradients can be used",
+                        ));
                     }
                     mode = Some(Mode::Predict);
                     i += 1;
                 }
                 "--train" => {
                     if mode.is_some() {
-                        return Err(String::from("Cannot specify both --predict and --train"));
+                        return Err(String::from(
+                            "Only one of --predict, --train, --convert and --check-gradients can be used",
+                        ));
                     }
                     mode = Some(Mode::Train);
                     i += 1;
                 }
+                "--convert" => {
+                    if mode.is_some() {
+                        return Err(String::from(
+                            "Only one of --predict, --train, --convert and --check-gradients can be used",
+                        ));
+                    }
+                    mode = Some(Mode::Convert);
+                    i += 1;
+                }
+                "--check-gradients" => {
+                    if mode.is_some() {
+                        return Err(String::from(
+                            "Only one of --predict, --train, --convert and --check-gradients can be used",
+                        ));
+                    }
+                    mode = Some(Mode::CheckGradients);
+                    i += 1;
+                }
+                "--samples" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--samples requires a number or 'all'"));
+                    }
+                    i += 1;
+                    samples = match args[i].as_str() {
+                        "all" => None,
+                        n => match n.parse() {
+                            Ok(0) | Err(_) => {
+                                return Err(format!("Invalid sample count: {}", args[i]));
+                            }
+                            Ok(n) => Some(n),
+                        },
+                    };
+                    samples_set = true;
+                    i += 1;
+                }
+                "--precision" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--precision requires f32 or f64"));
+                    }
+                    i += 1;
+                    precision = Some(
+                        Precision::from_name(&args[i])
+                            .map_err(|e| format!("Invalid precision: {}", e))?,
+                    );
+                    i += 1;
+                }
+                "--format" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--format requires a format name"));
+                    }
+                    i += 1;
+                    format = Some(args[i].clone());
+                    i += 1;
+                }
                 "--save" => {
                     if i + 1 >= args.len() {
                         return Err(String::from("--save requires a filename"));
@@ -59,6 +144,14 @@ impl Config {
                     savefile = Some(args[i].clone());
                     i += 1;
                 }
+                "--save-final" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--save-final requires a filename"));
+                    }
+                    i += 1;
+                    final_savefile = Some(args[i].clone());
+                    i += 1;
+                }
                 "--config" => {
                     if i + 1 >= args.len() {
                         return Err(String::from("--config requires a filename"));
@@ -67,6 +160,39 @@ impl Config {
                     configfile = Some(args[i].clone());
                     i += 1;
                 }
+                "--resume" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--resume requires a filename"));
+                    }
+                    i += 1;
+                    resume = Some(args[i].clone());
+                    i += 1;
+                }
+                "--seed" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--seed requires a number"));
+                    }
+                    i += 1;
+                    seed = Some(
+                        args[i]
+                            .parse()
+                            .map_err(|_| format!("Invalid seed: {}", args[i]))?,
+                    );
+                    i += 1;
+                }
+                "--threads" => {
+                    if i + 1 >= args.len() {
+                        return Err(String::from("--threads requires a number"));
+                    }
+                    i += 1;
+                    threads = match args[i].parse() {
+                        Ok(0) | Err(_) => {
+                            return Err(format!("Invalid thread count: {}", args[i]));
+                        }
+                        Ok(n) => Some(n),
+                    };
+                    i += 1;
+                }
                 _ => {
                     if loadfile.is_none() {
                         loadfile = Some(args[i].clone());
@@ -80,27 +206,92 @@ impl Config {
             }
         }
 
-        let mode = mode.ok_or("Mode not specified (use --predict or --train)")?;
+        let mode = mode
+            .ok_or("Mode not specified (use --predict, --train, --convert or --check-gradients)")?;
         let loadfile = loadfile.ok_or("LOADFILE not specified")?;
-        let chessfile = chessfile.ok_or("CHESSFILE not specified")?;
 
-        if savefile.is_some() && mode == Mode::Predict {
+        if savefile.is_some() && mode != Mode::Train {
             return Err(String::from("--save can only be used with --train"));
         }
 
+        if final_savefile.is_some() && mode != Mode::Train {
+            return Err(String::from("--save-final can only be used with --train"));
+        }
+
+        if resume.is_some() && mode != Mode::Train {
+            return Err(String::from("--resume can only be used with --train"));
+        }
+
+        if resume.is_some() && configfile.is_some() {
+            return Err(String::from(
+                "--config cannot be used with --resume: the checkpoint holds the configuration",
+            ));
+        }
+
+        if seed.is_some() && mode != Mode::Train && mode != Mode::CheckGradients {
+            return Err(String::from(
+                "--seed can only be used with --train or --check-gradients",
+            ));
+        }
+
+        if seed.is_some() && resume.is_some() {
+            return Err(String::from(
+                "--seed cannot be used with --resume: the checkpoint holds the seed",
+            ));
+        }
+
+        if format.is_some() && matches!(mode, Mode::Predict | Mode::CheckGradients) {
+            return Err(String::from(
+                "--format can only be used with --train or --convert",
+            ));
+        }
+
+        if samples_set && mode != Mode::CheckGradients {
+            return Err(String::from(
+                "--samples can only be used with --check-gradients",
+            ));
+        }
+
+        if precision.is_some() && mode != Mode::Predict {
+            return Err(String::from(
+                "--precision can only be used with --predict, use the precision key of the configuration to train",
+            ));
+        }
+
+        // In convert mode the second file is where the converted network goes
+        let (chessfile, savefile) = if mode == Mode::Convert {
+            (
+                String::new(),
+                Some(chessfile.ok_or("SAVEFILE not specified")?),
+            )
+        } else {
+            (chessfile.ok_or("CHESSFILE not specified")?, savefile)
+        };
+
         Ok(Config {
             mode,
             loadfile,
             chessfile,
             savefile,
+            final_savefile,
             configfile,
+            resume,
+            seed,
+            threads,
+            format,
+            samples,
+            precision: precision.unwrap_or(Precision::F64),
         })
     }
 
     pub fn print_help() {
         println!("USAGE");
         println!(
-            "    ./my_torch_analyzer [--predict | --train [--save SAVEFILE] [--config CONFIGFILE]] LOADFILE CHESSFILE"
+            "    ./my_torch_analyzer [--predict [--precision f32 | f64] | --train [--save SAVEFILE] [--save-final FINALFILE] [--config CONFIGFILE | --resume CHECKPOINT] [--seed N]] [--threads N] LOADFILE CHESSFILE"
+        );
+        println!("    ./my_torch_analyzer --convert [--format FORMAT] LOADFILE SAVEFILE");
+        println!(
+            "    ./my_torch_analyzer --check-gradients [--samples N | all] [--seed N] LOADFILE CHESSFILE"
         );
         println!();
         println!("DESCRIPTION");
@@ -115,11 +306,66 @@ impl Config {
         println!("                  contain inputs to send to the neural network in FEN notation, and optionally an expected");
         println!("                  output.");
         println!();
+        println!(
+            "    --precision   Float type the network computes in: f32 or f64 (default). The network"
+        );
+        println!(
+            "                  is converted when its file has the other precision. Only works in"
+        );
+        println!(
+            "                  predict mode; training uses the precision key of the configuration."
+        );
+        println!();
         println!("    --save        Save neural network into SAVEFILE. Only works in train mode.");
         println!();
+        println!("    --save-final  Also save the weights of the last epoch into FINALFILE. SAVEFILE gets the");
+        println!("                  weights of the best validation loss unless restore_best_weights = false.");
+        println!("                  Only works in train mode.");
+        println!();
         println!("    --config      Configuration file for training hyperparameters (.conf file).");
         println!("                  If not specified, uses default configuration.");
-        println!("                  Only works in train mode.");
+        println!("                  Only works in train and check-gradients modes.");
+        println!();
+        println!("    --resume      Resume training from CHECKPOINT, written every checkpoint_every epochs");
+        println!(
+            "                  to SAVEFILE.ckpt. The run goes on with the configuration, weights,"
+        );
+        println!("                  optimizer state and schedule it was stopped with. Only works in train mode.");
+        println!();
+        println!(
+            "    --seed        Seed of the weight initialization, dropout masks and batch order,"
+        );
+        println!("                  instead of the seed key of the configuration. Runs with the same seed");
+        println!("                  and data give the same network. In check-gradients mode, seed of the");
+        println!(
+            "                  checked values and dropout masks. Only works in these two modes."
+        );
+        println!();
+        println!(
+            "    --convert     Convert the network in LOADFILE between the text and binary formats"
+        );
+        println!("                  and write it to SAVEFILE.");
+        println!();
+        println!("    --format      Format used to write the network: text, f32 or f64 (binary).");
+        println!(
+            "                  In convert mode, defaults to f64 for a text LOADFILE and text for a"
+        );
+        println!(
+            "                  binary one. In train mode, defaults to the format of LOADFILE."
+        );
+        println!();
+        println!("    --check-gradients");
+        println!("                  Compare the backprop gradients of the network with finite differences");
+        println!("                  of the loss (from --config, cross-entropy by default) on the first 16");
+        println!("                  chessboards of CHESSFILE, which must be labelled like training data.");
+        println!();
+        println!(
+            "    --samples     Values checked per parameter group, picked at random (default 20),"
+        );
+        println!("                  or 'all'. Only works with --check-gradients.");
+        println!();
+        println!("    --threads     Number of threads used to split each training batch.");
+        println!("                  Defaults to one thread per available core.");
         println!();
         println!("    LOADFILE      File containing an artificial neural network");
         println!();